@@ -1,6 +1,445 @@
-use reqwest::Client;
+use crate::config::{Config, ProviderKind};
+use crate::data_sql;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// A single completion request, independent of the provider dialect
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+  pub prompt: String,
+  pub model: String,
+  pub max_tokens: i32,
+  pub temperature: f32,
+}
+
+/// Tokens billed for one or more requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+  pub prompt_tokens: u64,
+  pub completion_tokens: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+  fn add_assign(&mut self, other: Self) {
+    self.prompt_tokens += other.prompt_tokens;
+    self.completion_tokens += other.completion_tokens;
+  }
+}
+
+/// The model's reply and what it cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+  pub text: String,
+  /// Zero when the endpoint reports no usage or the reply came from the cache
+  pub usage: TokenUsage,
+}
+
+/// A backend able to turn a prompt into a text completion.
+///
+/// `process_query` only talks to this trait, so any endpoint can be plugged in
+/// through `Config` without touching the pipeline.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+  /// Short human-readable name, used in progress messages
+  fn name(&self) -> &'static str;
+
+  /// Endpoint the requests go to; two servers may serve different models under one name
+  fn base_url(&self) -> &str;
+
+  /// Send the request and return the model's reply
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>>;
+}
+
+/// Build the provider selected in `config`.
+///
+/// All providers share one HTTP client with the configured timeouts, and
+/// transient failures are retried according to `RetryPolicy::from_config`.
+///
+/// # Arguments
+///
+/// * `config` - Provider kind, base URL, extra headers, timeouts and retries
+/// * `api_key` - API key, may be empty for providers that do not need one
+pub fn provider_from_config(
+  config: &Config,
+  api_key: &str,
+) -> Result<Box<dyn LlmProvider>, Box<dyn Error>> {
+  let base_url = config
+    .base_url
+    .clone()
+    .unwrap_or_else(|| config.provider.default_base_url().to_string());
+  let headers = build_headers(&config.headers)?;
+  let client = build_client(config)?;
+
+  let provider: Box<dyn LlmProvider> = match config.provider {
+    ProviderKind::OpenAi => Box::new(OpenAiProvider::new(client, &base_url, api_key, headers)),
+    ProviderKind::Ollama => Box::new(OllamaProvider::new(client, &base_url, headers)),
+    ProviderKind::Anthropic => {
+      Box::new(AnthropicProvider::new(client, &base_url, api_key, headers))
+    }
+  };
+  Ok(Box::new(RetryingProvider::new(
+    provider,
+    RetryPolicy::from_config(config),
+  )))
+}
+
+/// HTTP client with the timeouts from `config`
+pub fn build_client(config: &Config) -> Result<Client, Box<dyn Error>> {
+  Ok(
+    Client::builder()
+      .connect_timeout(Duration::from_secs(config.connect_timeout))
+      .timeout(Duration::from_secs(config.request_timeout))
+      .build()?,
+  )
+}
+
+fn build_headers(headers: &HashMap<String, String>) -> Result<HeaderMap, Box<dyn Error>> {
+  let mut map = HeaderMap::new();
+  for (name, value) in headers {
+    map.insert(
+      HeaderName::from_bytes(name.as_bytes())?,
+      HeaderValue::from_str(value)?,
+    );
+  }
+  Ok(map)
+}
+
+/// Why an LLM request failed
+#[derive(Debug)]
+pub enum ApiError {
+  /// The key was rejected (401 or 403); retrying will not help
+  Auth { status: StatusCode, body: String },
+  /// Rate limiting, a server error, a timeout or a dropped connection; worth retrying
+  Transient {
+    status: Option<StatusCode>,
+    message: String,
+    /// How long the server asked us to wait
+    retry_after: Option<Duration>,
+  },
+  /// Any other rejected request, such as an unknown model
+  Request { status: StatusCode, body: String },
+  /// The reply could not be read or parsed
+  Response(String),
+}
+
+impl ApiError {
+  /// Whether the same request may succeed if sent again
+  pub fn is_transient(&self) -> bool {
+    matches!(self, ApiError::Transient { .. })
+  }
+
+  /// Classify a non-success response, reading its body and `Retry-After`
+  async fn from_response(response: reqwest::Response) -> Self {
+    let status = response.status();
+    let retry_after = response
+      .headers()
+      .get(RETRY_AFTER)
+      .and_then(|value| value.to_str().ok())
+      .and_then(parse_retry_after);
+    let body = response.text().await.unwrap_or_default();
+
+    match status {
+      StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ApiError::Auth { status, body },
+      StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => ApiError::Transient {
+        status: Some(status),
+        message: body,
+        retry_after,
+      },
+      _ if status.is_server_error() => ApiError::Transient {
+        status: Some(status),
+        message: body,
+        retry_after,
+      },
+      _ => ApiError::Request { status, body },
+    }
+  }
+}
+
+impl From<reqwest::Error> for ApiError {
+  fn from(e: reqwest::Error) -> Self {
+    if e.is_timeout() || e.is_connect() || e.is_request() {
+      ApiError::Transient {
+        status: None,
+        message: e.to_string(),
+        retry_after: None,
+      }
+    } else {
+      ApiError::Response(e.to_string())
+    }
+  }
+}
+
+impl fmt::Display for ApiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ApiError::Auth { status, body } => {
+        write!(
+          f,
+          "API authentication failed with status code {}: {}",
+          status, body
+        )
+      }
+      ApiError::Transient {
+        status: Some(status),
+        message,
+        ..
+      } => write!(
+        f,
+        "API request failed with status code {}: {}",
+        status, message
+      ),
+      ApiError::Transient {
+        status: None,
+        message,
+        ..
+      } => write!(f, "API request failed: {}", message),
+      ApiError::Request { status, body } => {
+        write!(
+          f,
+          "API request failed with status code {}: {}",
+          status, body
+        )
+      }
+      ApiError::Response(message) => write!(f, "Invalid API response: {}", message),
+    }
+  }
+}
+
+impl Error for ApiError {}
+
+/// Parse `Retry-After`, either delay seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+  let value = value.trim();
+  if let Ok(seconds) = value.parse::<u64>() {
+    return Some(Duration::from_secs(seconds));
+  }
+  let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+  // A date in the past means "now"
+  let wait = date.signed_duration_since(chrono::Utc::now());
+  Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// When and how often failed requests are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+  /// Total tries including the first, at least 1
+  pub max_attempts: u32,
+  /// Delay before the first retry, doubled on every further retry
+  pub base_delay: Duration,
+  /// Upper bound for the computed delay; a longer `Retry-After` is still honored
+  pub max_delay: Duration,
+  /// Longest `Retry-After` honored; a longer one is not retried at all
+  pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+  pub fn from_config(config: &Config) -> Self {
+    Self {
+      max_attempts: config.max_attempts.max(1),
+      base_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(60),
+      max_retry_after: Duration::from_secs(config.max_retry_after),
+    }
+  }
+
+  /// Whether a transient failure on try number `attempt` is worth another try
+  ///
+  /// A server asking to wait longer than `max_retry_after`, e.g. until a
+  /// daily quota resets, is not retried, so the run fails instead of hanging.
+  pub fn should_retry(&self, attempt: u32, retry_after: Option<Duration>) -> bool {
+    attempt < self.max_attempts && retry_after.is_none_or(|wait| wait <= self.max_retry_after)
+  }
+
+  /// Delay before retry number `retry` (starting at 1).
+  ///
+  /// Uses the server's `Retry-After` if given, otherwise exponential backoff
+  /// where the upper half of each step is random, so parallel runs that hit
+  /// the same rate limit do not retry in lockstep.
+  pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+    if let Some(retry_after) = retry_after {
+      return retry_after;
+    }
+    let exponential = self
+      .base_delay
+      .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+      .min(self.max_delay);
+    exponential / 2 + exponential.mul_f64(jitter() / 2.0)
+  }
+}
+
+/// A random number in `[0, 1)`, good enough to spread out retries
+fn jitter() -> f64 {
+  let random = std::collections::hash_map::RandomState::new()
+    .build_hasher()
+    .finish();
+  (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Retries the requests of another provider on transient failures
+pub struct RetryingProvider {
+  inner: Box<dyn LlmProvider>,
+  policy: RetryPolicy,
+}
+
+impl RetryingProvider {
+  pub fn new(inner: Box<dyn LlmProvider>, policy: RetryPolicy) -> Self {
+    Self { inner, policy }
+  }
+}
+
+#[async_trait]
+impl LlmProvider for RetryingProvider {
+  fn name(&self) -> &'static str {
+    self.inner.name()
+  }
+
+  fn base_url(&self) -> &str {
+    self.inner.base_url()
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let mut attempt = 1;
+    loop {
+      // The error is not `Send`, so only the delay may outlive this block
+      let delay = match self.inner.complete(request).await {
+        Ok(reply) => return Ok(reply),
+        Err(e) => {
+          let retry_after = match e.downcast_ref::<ApiError>() {
+            Some(ApiError::Transient { retry_after, .. })
+              if self.policy.should_retry(attempt, *retry_after) =>
+            {
+              *retry_after
+            }
+            _ => return Err(e),
+          };
+          let delay = self.policy.delay(attempt, retry_after);
+          eprintln!(
+            "✗ {} request failed (attempt {}/{}): {}, retrying in {:.1}s",
+            self.name(),
+            attempt,
+            self.policy.max_attempts,
+            e,
+            delay.as_secs_f64()
+          );
+          delay
+        }
+      };
+      tokio::time::sleep(delay).await;
+      attempt += 1;
+    }
+  }
+}
+
+/// Cache key of a request: a hash of everything that shapes the reply
+///
+/// # Arguments
+///
+/// * `provider` - Name of the provider, the same model name may mean different models
+/// * `base_url` - Endpoint of the provider, e.g. a local server and a hosted API
+///   both speaking the OpenAI dialect
+/// * `request` - Model, prompt, temperature and token limit of the request
+pub fn cache_key(provider: &str, base_url: &str, request: &CompletionRequest) -> String {
+  let fields = serde_json::json!([
+    provider,
+    base_url,
+    request.model,
+    request.temperature,
+    request.max_tokens,
+    request.prompt,
+  ]);
+  Sha256::digest(fields.to_string().as_bytes())
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Answers repeated requests from the `llm_cache` table instead of the API.
+///
+/// Only replies accepted by `is_valid` are cached, and cached replies it
+/// rejects count as misses, so a malformed reply is never replayed. Cache
+/// errors are reported and otherwise ignored, a broken cache must not stop a
+/// run.
+pub struct CachedProvider {
+  inner: Box<dyn LlmProvider>,
+  path_to_db: String,
+  is_valid: fn(&str) -> bool,
+}
+
+impl CachedProvider {
+  /// Wrap a provider with the response cache
+  ///
+  /// # Arguments
+  /// * `inner` - The provider asked on a cache miss
+  /// * `path_to_db` - Path to the database holding the `llm_cache` table
+  /// * `is_valid` - Whether a reply is good enough to cache and replay
+  pub fn new(inner: Box<dyn LlmProvider>, path_to_db: &str, is_valid: fn(&str) -> bool) -> Self {
+    Self {
+      inner,
+      path_to_db: path_to_db.to_string(),
+      is_valid,
+    }
+  }
+}
+
+#[async_trait]
+impl LlmProvider for CachedProvider {
+  fn name(&self) -> &'static str {
+    self.inner.name()
+  }
+
+  fn base_url(&self) -> &str {
+    self.inner.base_url()
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let key = cache_key(self.name(), self.base_url(), request);
+    match data_sql::get_cached_response(&key, &self.path_to_db).await {
+      Ok(Some(text)) if (self.is_valid)(&text) => {
+        return Ok(Completion {
+          text,
+          usage: TokenUsage::default(),
+        })
+      }
+      Ok(_) => {}
+      Err(e) => eprintln!("✗ Could not read the response cache: {}", e),
+    }
+
+    let response = self.inner.complete(request).await?;
+    if (self.is_valid)(&response.text) {
+      if let Err(e) =
+        data_sql::save_cached_response(&key, &request.model, &response.text, &self.path_to_db).await
+      {
+        eprintln!("✗ Could not write the response cache: {}", e);
+      }
+    }
+    Ok(response)
+  }
+}
+
+/// Send a request and turn a non-success response into an `ApiError`
+async fn send(builder: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+  let response = builder.send().await?;
+  if response.status().is_success() {
+    Ok(response)
+  } else {
+    Err(ApiError::from_response(response).await)
+  }
+}
+
+/// Read a successful response as JSON; a body cut off by a timeout is transient
+async fn read_json<T: serde::de::DeserializeOwned>(
+  response: reqwest::Response,
+) -> Result<T, ApiError> {
+  Ok(response.json().await?)
+}
 
 #[derive(Serialize)]
 struct Message {
@@ -8,6 +447,15 @@ struct Message {
   content: String,
 }
 
+impl Message {
+  fn user(content: &str) -> Self {
+    Self {
+      role: "user".to_string(),
+      content: content.to_string(),
+    }
+  }
+}
+
 #[derive(Serialize)]
 struct RequestPayload {
   model: String,
@@ -26,57 +474,249 @@ struct ResponseMessage {
   content: String,
 }
 
+#[derive(Deserialize)]
+struct ApiUsage {
+  #[serde(default)]
+  prompt_tokens: u64,
+  #[serde(default)]
+  completion_tokens: u64,
+}
+
 #[derive(Deserialize)]
 struct ApiResponse {
   choices: Vec<ResponseChoice>,
+  #[serde(default)]
+  usage: Option<ApiUsage>,
 }
 
-/// Send a prompt to DeepSeek API and get the response.
-///
-/// # Arguments
-///
-/// * `prompt` - The input prompt to send to the API
-/// * `api_key` - Your DeepSeek API key
-/// * `model` - The model to use (default: deepseek-chat)
-/// * `max_tokens` - Maximum number of tokens to generate
-/// * `temperature` - Controls randomness (0.0-2.0)
-///
-/// # Returns
-///
-/// The text response from the API
-pub async fn query_openai(
-  prompt: &str,
-  api_key: &str,
-  model: &str,
-  max_tokens: i32,
+/// OpenAI-compatible chat completions (`POST {base_url}/chat/completions`)
+pub struct OpenAiProvider {
+  client: Client,
+  base_url: String,
+  api_key: String,
+  headers: HeaderMap,
+}
+
+impl OpenAiProvider {
+  pub fn new(client: Client, base_url: &str, api_key: &str, headers: HeaderMap) -> Self {
+    Self {
+      client,
+      base_url: base_url.trim_end_matches('/').to_string(),
+      api_key: api_key.to_string(),
+      headers,
+    }
+  }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+  fn name(&self) -> &'static str {
+    "OpenAI-compatible"
+  }
+
+  fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let payload = RequestPayload {
+      model: request.model.clone(),
+      messages: vec![Message::user(&request.prompt)],
+      max_tokens: request.max_tokens,
+      temperature: request.temperature,
+    };
+
+    let mut builder = self
+      .client
+      .post(format!("{}/chat/completions", self.base_url))
+      .headers(self.headers.clone())
+      .json(&payload);
+    if !self.api_key.is_empty() {
+      builder = builder.bearer_auth(&self.api_key);
+    }
+    let response = send(builder).await?;
+    let api_response: ApiResponse = read_json(response).await?;
+    let usage = api_response
+      .usage
+      .map_or_else(TokenUsage::default, |usage| TokenUsage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+      });
+    let text = api_response
+      .choices
+      .into_iter()
+      .next()
+      .map(|choice| choice.message.content)
+      .ok_or_else(|| ApiError::Response("no choices in reply".to_string()))?;
+    Ok(Completion { text, usage })
+  }
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
   temperature: f32,
-) -> Result<String, Box<dyn Error>> {
-  let client = Client::new();
+  num_predict: i32,
+}
 
-  let payload = RequestPayload {
-    model: model.to_string(),
-    messages: vec![Message {
-      role: "user".to_string(),
-      content: prompt.to_string(),
-    }],
-    max_tokens,
-    temperature,
-  };
+#[derive(Serialize)]
+struct OllamaPayload {
+  model: String,
+  messages: Vec<Message>,
+  stream: bool,
+  options: OllamaOptions,
+}
 
-  let response = client
-    .post("https://api.deepseek.com/v1/chat/completions")
-    .header("Content-Type", "application/json")
-    .header("Authorization", format!("Bearer {}", api_key))
-    .json(&payload)
-    .send()
+#[derive(Deserialize)]
+struct OllamaResponse {
+  message: ResponseMessage,
+  #[serde(default)]
+  prompt_eval_count: u64,
+  #[serde(default)]
+  eval_count: u64,
+}
+
+/// Ollama native chat API (`POST {base_url}/api/chat`)
+pub struct OllamaProvider {
+  client: Client,
+  base_url: String,
+  headers: HeaderMap,
+}
+
+impl OllamaProvider {
+  pub fn new(client: Client, base_url: &str, headers: HeaderMap) -> Self {
+    Self {
+      client,
+      base_url: base_url.trim_end_matches('/').to_string(),
+      headers,
+    }
+  }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+  fn name(&self) -> &'static str {
+    "Ollama"
+  }
+
+  fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let payload = OllamaPayload {
+      model: request.model.clone(),
+      messages: vec![Message::user(&request.prompt)],
+      stream: false,
+      options: OllamaOptions {
+        temperature: request.temperature,
+        num_predict: request.max_tokens,
+      },
+    };
+
+    let response = send(
+      self
+        .client
+        .post(format!("{}/api/chat", self.base_url))
+        .headers(self.headers.clone())
+        .json(&payload),
+    )
     .await?;
+    let api_response: OllamaResponse = read_json(response).await?;
+    Ok(Completion {
+      text: api_response.message.content,
+      usage: TokenUsage {
+        prompt_tokens: api_response.prompt_eval_count,
+        completion_tokens: api_response.eval_count,
+      },
+    })
+  }
+}
 
-  if response.status().is_success() {
-    let api_response: ApiResponse = response.json().await?;
-    Ok(api_response.choices[0].message.content.clone())
-  } else {
-    let status = response.status();
-    let text = response.text().await?;
-    Err(format!("API request failed with status code {}: {}", status, text).into())
+#[derive(Deserialize)]
+struct AnthropicContent {
+  #[serde(rename = "type")]
+  kind: String,
+  #[serde(default)]
+  text: String,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+  #[serde(default)]
+  input_tokens: u64,
+  #[serde(default)]
+  output_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+  content: Vec<AnthropicContent>,
+  #[serde(default)]
+  usage: Option<AnthropicUsage>,
+}
+
+/// Anthropic messages API (`POST {base_url}/messages`)
+pub struct AnthropicProvider {
+  client: Client,
+  base_url: String,
+  api_key: String,
+  headers: HeaderMap,
+}
+
+impl AnthropicProvider {
+  pub fn new(client: Client, base_url: &str, api_key: &str, headers: HeaderMap) -> Self {
+    Self {
+      client,
+      base_url: base_url.trim_end_matches('/').to_string(),
+      api_key: api_key.to_string(),
+      headers,
+    }
+  }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+  fn name(&self) -> &'static str {
+    "Anthropic"
+  }
+
+  fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let payload = RequestPayload {
+      model: request.model.clone(),
+      messages: vec![Message::user(&request.prompt)],
+      max_tokens: request.max_tokens,
+      temperature: request.temperature,
+    };
+
+    let response = send(
+      self
+        .client
+        .post(format!("{}/messages", self.base_url))
+        .header("x-api-key", &self.api_key)
+        .header("anthropic-version", "2023-06-01")
+        .headers(self.headers.clone())
+        .json(&payload),
+    )
+    .await?;
+    let api_response: AnthropicResponse = read_json(response).await?;
+    let usage = api_response
+      .usage
+      .map_or_else(TokenUsage::default, |usage| TokenUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+      });
+    Ok(Completion {
+      text: api_response
+        .content
+        .into_iter()
+        .filter(|block| block.kind == "text")
+        .map(|block| block.text)
+        .collect(),
+      usage,
+    })
   }
 }
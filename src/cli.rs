@@ -1,6 +1,9 @@
-use clap::{Parser, Subcommand};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::time::Duration;
+use crate::data_sql::SortKey;
+use crate::email::source::SourceKind;
+use crate::export::ExportFormat;
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,54 +16,161 @@ pub struct Cli {
 pub enum Commands {
   /// Query emails, generate summaries, and store results
   Query {
-    /// Number of days of emails to fetch
+    #[command(flatten)]
+    args: QueryArgs,
+
+    /// Only fetch emails that arrived since the last incremental run (IMAP only)
+    #[arg(long)]
+    incremental: bool,
+
+    /// Where to read emails from
+    #[arg(long, value_enum, default_value_t = SourceKind::Imap)]
+    source: SourceKind,
+
+    /// Maildir directory, mbox file or directory of .eml files for local sources
+    #[arg(long, required_if_eq_any = [("source", "maildir"), ("source", "mbox"), ("source", "eml")])]
+    source_path: Option<PathBuf>,
+
+    /// Print the fetched emails and the prompts that would be sent, without calling the model
+    #[arg(long, conflicts_with = "no_store")]
+    dry_run: bool,
+
+    /// Extract events but only print what would be inserted or updated
+    #[arg(long)]
+    no_store: bool,
+  },
+
+  /// Keep running, summarizing new emails as they arrive (IMAP IDLE with polling fallback)
+  Daemon {
+    #[command(flatten)]
+    args: QueryArgs,
+
+    /// Seconds between polls, and the longest a single IDLE wait lasts
+    #[arg(long, default_value_t = 300)]
+    poll_interval: u64,
+
+    /// Upper bound in seconds for the reconnect backoff
+    #[arg(long, default_value_t = 600)]
+    max_backoff: u64,
+  },
+
+  /// Search events by date range, speaker, position, sender or keyword
+  Search {
+    /// Search string for time_begin field (supports Chinese characters)
+    query: Option<String>,
+
+    /// Earliest event date, inclusive (YYYY-MM-DD)
+    #[arg(long)]
+    from: Option<NaiveDate>,
+
+    /// Latest event date, inclusive (YYYY-MM-DD)
+    #[arg(long)]
+    to: Option<NaiveDate>,
+
+    /// Substring of the speaker's name or title
+    #[arg(long)]
+    speaker: Option<String>,
+
+    /// Substring of the event location
+    #[arg(long)]
+    position: Option<String>,
+
+    /// Substring of the sender address
     #[arg(long)]
-    date: Option<u64>,
+    sender: Option<String>,
 
-    /// API key for authentication
+    /// Substring of the event title or abstract
     #[arg(long)]
-    api_key: Option<String>,
+    keyword: Option<String>,
+
+    /// Full-text search over titles, abstracts and speakers, ranked by relevance
+    #[arg(long)]
+    text: Option<String>,
+
+    /// Field to sort results by
+    #[arg(long, value_enum, default_value_t = SortKey::Time)]
+    sort: SortKey,
 
-    /// Email address to use for authentication
+    /// Sort in descending order
     #[arg(long)]
-    mail_address: Option<String>,
+    desc: bool,
 
-    /// Email password for authentication
+    /// Maximum number of results
     #[arg(long)]
-    mail_pwd: Option<String>,
+    limit: Option<usize>,
 
     /// Path to the SQLite database
     #[arg(long)]
     db_path: Option<String>,
+  },
+
+  /// Export stored events to a file or stdout
+  Export {
+    /// Output format (default: from the --output extension, else JSON)
+    #[arg(long, value_enum)]
+    format: Option<ExportFormat>,
 
-    /// Model name to use for API requests
+    /// File to write to (default: stdout)
     #[arg(long)]
-    model: Option<String>,
+    output: Option<PathBuf>,
 
-    /// Maximum tokens for API response
+    /// Earliest event date, inclusive (YYYY-MM-DD)
     #[arg(long)]
-    max_tokens: Option<i32>,
+    from: Option<NaiveDate>,
 
-    /// Temperature setting for response randomness
+    /// Latest event date, inclusive (YYYY-MM-DD)
     #[arg(long)]
-    temperature: Option<f32>,
+    to: Option<NaiveDate>,
 
-    /// Mail server address
-    #[arg(long, default_value = "mails.tsinghua.edu.cn")]
-    mail_server: String,
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
   },
 
-  /// Search events by time_begin field
-  Search {
-    /// Search string for time_begin field (supports Chinese characters)
-    #[arg(required = true)]
-    query: String,
+  /// Import events from a JSON, CSV or JSON Lines export
+  Import {
+    /// File to read
+    file: PathBuf,
+
+    /// Input format (default: from the file extension)
+    #[arg(long, value_enum)]
+    format: Option<ExportFormat>,
+
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
+  },
+
+  /// Serve the events database as a JSON HTTP API
+  Serve {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
 
     /// Path to the SQLite database
     #[arg(long)]
     db_path: Option<String>,
   },
 
+  /// Inspect or upgrade the database schema
+  Db {
+    #[command(subcommand)]
+    action: DbCommand,
+  },
+
+  /// Manage the cache of LLM responses
+  Cache {
+    #[command(subcommand)]
+    action: CacheCommand,
+  },
+
+  /// Summarize LLM token usage and estimated cost by week and model
+  Stats {
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
+  },
+
   /// Generate HTML for events
   Generate {
     /// Search string for time_begin field (date to search)
@@ -81,23 +191,89 @@ pub enum Commands {
   },
 }
 
-/// Create a styled progress bar
-pub fn create_progress_bar(
-  m: &MultiProgress,
-  message: &str,
-  spinner_style: &str,
-  color: &str,
-) -> ProgressBar {
-  let pb = m.add(ProgressBar::new_spinner());
-  pb.set_style(
-    ProgressStyle::default_spinner()
-      .tick_chars(spinner_style)
-      .template(&format!("{{spinner:.{color}}} {{msg}}"))
-      .unwrap(),
-  );
-  pb.set_message(message.to_string());
-  pb.enable_steady_tick(Duration::from_millis(100));
-  pb
+/// Actions of the `db` subcommand
+#[derive(Subcommand)]
+pub enum DbCommand {
+  /// Apply pending schema migrations
+  Migrate {
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
+  },
+
+  /// Show the schema version and pending migrations without changing anything
+  Status {
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
+  },
+}
+
+/// Actions of the `cache` subcommand
+#[derive(Subcommand)]
+pub enum CacheCommand {
+  /// Delete cached responses older than the given number of days
+  Prune {
+    /// Age in days; 0 empties the cache
+    #[arg(long)]
+    older_than: u32,
+
+    /// Path to the SQLite database
+    #[arg(long)]
+    db_path: Option<String>,
+  },
+}
+
+/// Mail, LLM and database options shared by `query` and `daemon`
+#[derive(Args)]
+pub struct QueryArgs {
+  /// Number of days of emails to fetch
+  #[arg(long)]
+  pub date: Option<u64>,
+
+  /// API key for authentication
+  #[arg(long)]
+  pub api_key: Option<String>,
+
+  /// Email address to use for authentication
+  #[arg(long)]
+  pub mail_address: Option<String>,
+
+  /// Email password for authentication
+  #[arg(long)]
+  pub mail_pwd: Option<String>,
+
+  /// Path to the SQLite database
+  #[arg(long)]
+  pub db_path: Option<String>,
+
+  /// Model name to use for API requests
+  #[arg(long)]
+  pub model: Option<String>,
+
+  /// Maximum tokens for API response
+  #[arg(long)]
+  pub max_tokens: Option<i32>,
+
+  /// Temperature setting for response randomness
+  #[arg(long)]
+  pub temperature: Option<f32>,
+
+  /// Mail server address
+  #[arg(long, default_value = "mails.tsinghua.edu.cn")]
+  pub mail_server: String,
+
+  /// Always ask the API, ignoring and not updating the response cache
+  #[arg(long)]
+  pub no_cache: bool,
+
+  /// Extract events from each email with a request of its own
+  #[arg(long)]
+  pub per_email: bool,
+
+  /// Maximum number of LLM requests in flight at once
+  #[arg(long)]
+  pub concurrency: Option<usize>,
 }
 
 /// Load environment variables or use provided CLI values
@@ -109,14 +285,20 @@ pub fn get_config_values(
 ) -> Result<(String, String, String, String), Box<dyn std::error::Error>> {
   dotenv::dotenv().ok();
 
-  let api_key =
-    api_key.unwrap_or_else(|| dotenv::var("DEEPSEEK_API_KEY").expect("API key not found"));
+  // Local providers such as Ollama need no key, so a missing one is not fatal here
+  let api_key = api_key
+    .or_else(|| dotenv::var("LLM_API_KEY").ok())
+    .or_else(|| dotenv::var("DEEPSEEK_API_KEY").ok())
+    .unwrap_or_default();
 
-  let email_address =
-    mail_address.unwrap_or_else(|| dotenv::var("MAIL_ADDRESS").expect("Email address not found"));
+  // Only IMAP needs credentials, local sources run without them
+  let email_address = mail_address
+    .or_else(|| dotenv::var("MAIL_ADDRESS").ok())
+    .unwrap_or_default();
 
-  let email_password =
-    mail_pwd.unwrap_or_else(|| dotenv::var("MAIL_PASSWORD").expect("Email password not found"));
+  let email_password = mail_pwd
+    .or_else(|| dotenv::var("MAIL_PASSWORD").ok())
+    .unwrap_or_default();
 
   let path_to_db =
     db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
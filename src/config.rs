@@ -1,14 +1,176 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
   pub model: String,
   pub prompt: String,
   pub temperature: f32,
+  /// Output tokens allowed per LLM call; the default of 4096 fits the events
+  /// of a full `batch_size` batch, where 1024 cut the JSON array short
   pub max_tokens: i32,
   pub dates: u64,
+  /// Estimated prompt tokens allowed per LLM call; larger inputs are split into batches
+  pub token_budget: usize,
+  /// Maximum number of emails sent in one LLM call
+  pub batch_size: usize,
+  /// How many times to re-prompt the model when its output fails validation
+  pub repair_attempts: u32,
+  /// Send every email in a request of its own instead of batching
+  pub per_email: bool,
+  /// Maximum number of LLM requests in flight at once
+  pub concurrency: usize,
+  /// How many incremental runs may fail on the same message before it is
+  /// skipped, so a message that cannot be fetched or summarized stops being retried
+  pub max_message_attempts: u32,
+  /// How many times an LLM request is tried before a rate limit, server error
+  /// or timeout fails the run
+  pub max_attempts: u32,
+  /// Longest `Retry-After`, in seconds, worth waiting for; a server asking for
+  /// more fails the request instead
+  pub max_retry_after: u64,
+  /// Seconds allowed for connecting to the LLM endpoint
+  pub connect_timeout: u64,
+  /// Seconds allowed for a whole LLM request, including the model's reply
+  pub request_timeout: u64,
+  /// Which API dialect the LLM endpoint speaks
+  pub provider: ProviderKind,
+  /// Base URL of the LLM endpoint, `None` uses the provider's default
+  pub base_url: Option<String>,
+  /// Extra HTTP headers sent with every LLM request
+  pub headers: HashMap<String, String>,
+  /// Which fetched emails are passed on to the model
+  pub filters: FilterConfig,
+  /// Model name to its price, the `[prices]` table; runs of unlisted models have no cost
+  pub prices: HashMap<String, ModelPrice>,
+}
+
+/// Price of a model per million tokens, in whatever currency the API bills
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+  pub prompt: f64,
+  pub completion: f64,
+}
+
+impl ModelPrice {
+  /// Estimated cost of the given token counts
+  pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+    (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion) / 1_000_000.0
+  }
+}
+
+/// Rules deciding which emails are processed, the `[filters]` table.
+///
+/// Sender entries are either a full address (`news@tsinghua.edu.cn`) or a
+/// domain (`tsinghua.edu.cn` or `@tsinghua.edu.cn`), a domain also covering
+/// its subdomains. Subject and header entries are regular expressions.
+///
+/// An email is dropped if any deny rule matches. Otherwise each non-empty
+/// allow list must have at least one match; empty allow lists accept anything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterConfig {
+  pub allow_senders: Vec<String>,
+  pub deny_senders: Vec<String>,
+  pub allow_subjects: Vec<String>,
+  pub deny_subjects: Vec<String>,
+  /// Header name to regex, e.g. `List-Id = "seminar\\.math"`
+  pub allow_headers: HashMap<String, String>,
+  pub deny_headers: HashMap<String, String>,
+}
+
+impl Default for FilterConfig {
+  fn default() -> Self {
+    Self {
+      allow_senders: vec![
+        "mail.tsinghua.edu.cn".to_string(),
+        "mails.tsinghua.edu.cn".to_string(),
+      ],
+      deny_senders: Vec::new(),
+      allow_subjects: Vec::new(),
+      deny_subjects: Vec::new(),
+      allow_headers: HashMap::new(),
+      deny_headers: HashMap::new(),
+    }
+  }
+}
+
+impl FilterConfig {
+  fn apply_toml(&mut self, table: &toml::value::Table) {
+    let strings = |key: &str| {
+      table.get(key).and_then(|v| v.as_array()).map(|items| {
+        items
+          .iter()
+          .filter_map(|item| item.as_str().map(str::to_string))
+          .collect::<Vec<_>>()
+      })
+    };
+    let string_map = |key: &str| {
+      table.get(key).and_then(|v| v.as_table()).map(|items| {
+        items
+          .iter()
+          .filter_map(|(name, value)| value.as_str().map(|v| (name.clone(), v.to_string())))
+          .collect::<HashMap<_, _>>()
+      })
+    };
+
+    if let Some(allow_senders) = strings("allow_senders") {
+      self.allow_senders = allow_senders;
+    }
+    if let Some(deny_senders) = strings("deny_senders") {
+      self.deny_senders = deny_senders;
+    }
+    if let Some(allow_subjects) = strings("allow_subjects") {
+      self.allow_subjects = allow_subjects;
+    }
+    if let Some(deny_subjects) = strings("deny_subjects") {
+      self.deny_subjects = deny_subjects;
+    }
+    if let Some(allow_headers) = string_map("allow_headers") {
+      self.allow_headers = allow_headers;
+    }
+    if let Some(deny_headers) = string_map("deny_headers") {
+      self.deny_headers = deny_headers;
+    }
+  }
+}
+
+/// Supported LLM API dialects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+  /// OpenAI-compatible `/chat/completions` (DeepSeek, OpenAI, vLLM, gateways, ...)
+  OpenAi,
+  /// Ollama native `/api/chat`
+  Ollama,
+  /// Anthropic `/messages`
+  Anthropic,
+}
+
+impl ProviderKind {
+  /// Base URL used when none is configured
+  pub fn default_base_url(&self) -> &'static str {
+    match self {
+      ProviderKind::OpenAi => "https://api.deepseek.com/v1",
+      ProviderKind::Ollama => "http://localhost:11434",
+      ProviderKind::Anthropic => "https://api.anthropic.com/v1",
+    }
+  }
+}
+
+impl FromStr for ProviderKind {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_lowercase().as_str() {
+      "openai" | "openai-compatible" | "deepseek" => Ok(ProviderKind::OpenAi),
+      "ollama" => Ok(ProviderKind::Ollama),
+      "anthropic" | "claude" => Ok(ProviderKind::Anthropic),
+      other => Err(format!("Unknown provider: {}", other)),
+    }
+  }
 }
 
 const DEFAULT_PROMPT: &str = "input = {emails_input}
@@ -25,6 +187,7 @@ const DEFAULT_PROMPT: &str = "input = {emails_input}
 - position: 活动地点
 - speaker_name: 主讲人姓名 (注意！主讲人通常不是邮件的发件人)
 - speaker_title: 主讲人职称或头衔
+- source_id: 该活动所来自邮件的 id 字段，原样输出
 - abstract: 活动内容概要，包括主要议题、参会嘉宾和重要信息。若为学术报告，需概括研究成果。注意，摘要需要尽可能详实丰富，包含所有关键信息。但不能直接照搬邮件内容，而应当利用介绍的表达方式进行概括。
 
 重要提示：
@@ -41,8 +204,23 @@ impl Default for Config {
       model: "deepseek-chat".to_string(),
       prompt: DEFAULT_PROMPT.to_string(),
       temperature: 0.7,
-      max_tokens: 1024,
+      max_tokens: 4096,
       dates: 1,
+      token_budget: 8000,
+      batch_size: 10,
+      repair_attempts: 1,
+      per_email: false,
+      concurrency: 4,
+      max_message_attempts: 3,
+      max_attempts: 4,
+      max_retry_after: 300,
+      connect_timeout: 10,
+      request_timeout: 300,
+      provider: ProviderKind::OpenAi,
+      base_url: None,
+      headers: HashMap::new(),
+      filters: FilterConfig::default(),
+      prices: HashMap::new(),
     }
   }
 }
@@ -71,24 +249,112 @@ impl Config {
     if let Some(dates) = toml_value.get("dates").and_then(|v| v.as_integer()) {
       config.dates = dates as u64;
     }
+    if let Some(token_budget) = toml_value.get("token_budget").and_then(|v| v.as_integer()) {
+      config.token_budget = token_budget as usize;
+    }
+    if let Some(batch_size) = toml_value.get("batch_size").and_then(|v| v.as_integer()) {
+      config.batch_size = batch_size as usize;
+    }
+    if let Some(repair_attempts) = toml_value
+      .get("repair_attempts")
+      .and_then(|v| v.as_integer())
+    {
+      config.repair_attempts = repair_attempts as u32;
+    }
+    if let Some(per_email) = toml_value.get("per_email").and_then(|v| v.as_bool()) {
+      config.per_email = per_email;
+    }
+    if let Some(concurrency) = toml_value.get("concurrency").and_then(|v| v.as_integer()) {
+      config.concurrency = concurrency as usize;
+    }
+    if let Some(max_message_attempts) = toml_value
+      .get("max_message_attempts")
+      .and_then(|v| v.as_integer())
+    {
+      config.max_message_attempts = max_message_attempts as u32;
+    }
+    if let Some(max_attempts) = toml_value.get("max_attempts").and_then(|v| v.as_integer()) {
+      config.max_attempts = max_attempts as u32;
+    }
+    if let Some(max_retry_after) = toml_value
+      .get("max_retry_after")
+      .and_then(|v| v.as_integer())
+    {
+      config.max_retry_after = max_retry_after as u64;
+    }
+    if let Some(connect_timeout) = toml_value
+      .get("connect_timeout")
+      .and_then(|v| v.as_integer())
+    {
+      config.connect_timeout = connect_timeout as u64;
+    }
+    if let Some(request_timeout) = toml_value
+      .get("request_timeout")
+      .and_then(|v| v.as_integer())
+    {
+      config.request_timeout = request_timeout as u64;
+    }
+    if let Some(provider) = toml_value.get("provider").and_then(|v| v.as_str()) {
+      config.provider = provider.parse()?;
+    }
+    if let Some(base_url) = toml_value.get("base_url").and_then(|v| v.as_str()) {
+      config.base_url = Some(base_url.to_string());
+    }
+    if let Some(headers) = toml_value.get("headers").and_then(|v| v.as_table()) {
+      for (name, value) in headers {
+        if let Some(value) = value.as_str() {
+          config.headers.insert(name.clone(), value.to_string());
+        }
+      }
+    }
+    if let Some(filters) = toml_value.get("filters").and_then(|v| v.as_table()) {
+      config.filters.apply_toml(filters);
+    }
+    if let Some(prices) = toml_value.get("prices").and_then(|v| v.as_table()) {
+      // Prices may be written as integers, e.g. `completion = 2`
+      let number = |price: &toml::Value, key: &str| {
+        price
+          .get(key)
+          .and_then(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)))
+          .unwrap_or(0.0)
+      };
+      for (model, price) in prices {
+        config.prices.insert(
+          model.clone(),
+          ModelPrice {
+            prompt: number(price, "prompt"),
+            completion: number(price, "completion"),
+          },
+        );
+      }
+    }
 
     Ok(config)
   }
 
-  /// Gets config from standard locations or creates default if not found
-  pub fn get() -> Self {
+  /// Gets config from the first standard location that has a file, or the
+  /// default if there is none
+  ///
+  /// # Returns
+  ///
+  /// An error naming the file when it exists but cannot be read or parsed,
+  /// e.g. because of an unknown `provider`, rather than quietly running with
+  /// the defaults
+  pub fn get() -> Result<Self, Box<dyn std::error::Error>> {
     let possible_paths = vec![
       Some(PathBuf::from("./email_abstract.toml")),
       dirs::config_dir().map(|p| p.join("email_abstract/config.toml")),
       dirs::home_dir().map(|p| p.join(".config/email_abstract/config.toml")),
     ];
 
-    for path in possible_paths.into_iter().flatten() {
-      if let Ok(config) = Self::load_from_file(&path) {
-        return config;
-      }
+    match possible_paths
+      .into_iter()
+      .flatten()
+      .find(|path| path.exists())
+    {
+      Some(path) => Self::load_from_file(&path)
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into()),
+      None => Ok(Self::default()),
     }
-
-    return Self::default();
   }
 }
@@ -0,0 +1,158 @@
+use crate::email::source::ImapSource;
+use crate::email::{Backoff, IdleSession};
+use crate::{api_req, process_query, QueryOptions};
+use std::time::Duration;
+
+/// First delay before reconnecting; doubles on every failure up to `max_backoff`
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Timing of the daemon loop
+pub(crate) struct DaemonSettings {
+  /// Longest a single IDLE wait lasts; the inbox is polled at least this often
+  pub poll_interval: Duration,
+  /// Upper bound for the reconnect backoff
+  pub max_backoff: Duration,
+}
+
+/// Resolves once SIGTERM or Ctrl-C is received.
+///
+/// The handlers are installed on creation, so a signal arriving while a run is
+/// in progress is remembered instead of killing the process mid-store.
+struct Shutdown {
+  #[cfg(unix)]
+  terminate: tokio::signal::unix::Signal,
+  #[cfg(unix)]
+  interrupt: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+  fn install() -> std::io::Result<Self> {
+    #[cfg(unix)]
+    {
+      use tokio::signal::unix::{signal, SignalKind};
+      Ok(Self {
+        terminate: signal(SignalKind::terminate())?,
+        interrupt: signal(SignalKind::interrupt())?,
+      })
+    }
+    #[cfg(not(unix))]
+    Ok(Self {})
+  }
+
+  async fn wait(&mut self) {
+    #[cfg(unix)]
+    tokio::select! {
+      _ = self.terminate.recv() => {}
+      _ = self.interrupt.recv() => {}
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+  }
+}
+
+/// Wait for new mail on `session`, connecting first if there is none
+///
+/// # Returns
+///
+/// The session to reuse and whether the server reported a change
+async fn wait_for_mail(
+  session: Option<IdleSession>,
+  opts: &QueryOptions,
+  timeout: Duration,
+) -> Result<(IdleSession, bool), String> {
+  let (address, password, server) = (
+    opts.email_address.clone(),
+    opts.email_password.clone(),
+    opts.mail_server.clone(),
+  );
+  // The imap crate blocks, so keep it off the async workers
+  tokio::task::spawn_blocking(move || {
+    let mut session = match session {
+      Some(session) => session,
+      None => {
+        let session =
+          IdleSession::connect(&address, &password, &server).map_err(|e| e.to_string())?;
+        if session.supports_idle() {
+          println!("Connected to {server}, waiting for new mail (IDLE)");
+        } else {
+          println!("Connected to {server}, IDLE not supported, polling");
+        }
+        session
+      }
+    };
+    let changed = session.wait(timeout).map_err(|e| e.to_string())?;
+    Ok((session, changed))
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Summarize whatever arrived since the last run; errors are reported, not fatal
+async fn process_new_mail(provider: &dyn api_req::LlmProvider, opts: &QueryOptions) {
+  let source = ImapSource {
+    email_address: opts.email_address.clone(),
+    password: opts.email_password.clone(),
+    imap_server: opts.mail_server.clone(),
+    days_ago: opts.days,
+  };
+  if let Err(e) = process_query(provider, &source, opts).await {
+    eprintln!("✗ Run failed: {}", e);
+  }
+}
+
+/// Run the incremental pipeline whenever new mail arrives, until SIGTERM.
+///
+/// Catches up once on start, then waits in IMAP IDLE for at most
+/// `poll_interval` at a time and runs again on every change or timeout, so
+/// servers that drop IDLE notifications are still polled. Lost connections are
+/// retried with exponential backoff. A run in progress is always finished
+/// before shutting down.
+pub(crate) async fn run(
+  provider: &dyn api_req::LlmProvider,
+  opts: &QueryOptions,
+  settings: &DaemonSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+  if opts.email_address.is_empty() || opts.email_password.is_empty() {
+    return Err("Email address or password not found, set MAIL_ADDRESS and MAIL_PASSWORD".into());
+  }
+  let mut shutdown = Shutdown::install()?;
+  let mut session = None;
+  let mut backoff = Backoff::new(INITIAL_BACKOFF, settings.max_backoff);
+
+  process_new_mail(provider, opts).await;
+
+  loop {
+    let waited = tokio::select! {
+      _ = shutdown.wait() => break,
+      waited = wait_for_mail(session.take(), opts, settings.poll_interval) => waited,
+    };
+
+    match waited {
+      Ok((idle, changed)) => {
+        session = Some(idle);
+        backoff.reset();
+        if changed {
+          println!("Mailbox changed, fetching new mail");
+        }
+        process_new_mail(provider, opts).await;
+      }
+      Err(e) => {
+        let delay = backoff.next_delay();
+        eprintln!(
+          "✗ IMAP connection failed: {}, reconnecting in {}s",
+          e,
+          delay.as_secs()
+        );
+        tokio::select! {
+          _ = shutdown.wait() => break,
+          _ = tokio::time::sleep(delay) => {}
+        }
+      }
+    }
+  }
+
+  // Shutdown only happens while waiting, when the IDLE session belongs to the
+  // blocking wait; it is dropped with the process, no run is ever cut short
+  println!("Shutting down");
+  Ok(())
+}
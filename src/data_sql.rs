@@ -1,104 +1,424 @@
+pub mod migrations;
+pub mod time;
+
+use crate::event::{Event, SourceEmail};
 use rusqlite;
+use rusqlite::OptionalExtension;
 
-pub async fn store_json_to_db(
-  events: Vec<serde_json::Value>,
-  path_to_db: &str,
-) -> Result<(usize, usize), Box<dyn std::error::Error>> {
-  let conn = rusqlite::Connection::open(path_to_db)?;
+/// Open the database and bring its schema up to date, see `migrations`
+pub fn open_db(path_to_db: &str) -> Result<rusqlite::Connection, Box<dyn std::error::Error>> {
+  let mut conn = rusqlite::Connection::open(path_to_db)?;
+  migrations::migrate(&mut conn)?;
+  Ok(conn)
+}
+
+/// Canonical identity of an event, see `event_key`
+fn canonical_key(
+  sender: &str,
+  event: &str,
+  time_begin: &str,
+  time_begin_iso: Option<&str>,
+  position: &str,
+) -> String {
+  let normalize = |s: &str| {
+    s.split_whitespace()
+      .collect::<Vec<_>>()
+      .join(" ")
+      .to_lowercase()
+  };
+  let mut parts = vec![normalize(sender)];
+  match time_begin_iso {
+    Some(begin) => {
+      parts.push(begin.to_string());
+      // A bare date or an unknown place is shared by every talk a sender
+      // announces for that day, so the title has to tell them apart too
+      if is_date_only(begin) || position.trim().is_empty() {
+        parts.push(normalize(event));
+      }
+    }
+    // A begin time like "待定" says little, so the title has to tell events apart
+    None => parts.extend([normalize(time_begin), normalize(event)]),
+  }
+  parts.push(normalize(position));
+  parts.join("\u{1f}")
+}
+
+/// Whether a normalized begin time came from a date without a time of day
+fn is_date_only(time_begin_iso: &str) -> bool {
+  use chrono::Timelike;
+  chrono::DateTime::parse_from_rfc3339(time_begin_iso)
+    .map(|dt| dt.hour() == 0 && dt.minute() == 0)
+    .unwrap_or(false)
+}
+
+/// Identity of an event in the `events` table.
+///
+/// Two events are the same when they come from the same sender and start at
+/// the same time in the same place, compared case- and whitespace-insensitively
+/// on the normalized begin time. The title is left out so a corrected title
+/// updates the stored event; it only counts when the begin time cannot be
+/// parsed, has no time of day, or the place is unknown.
+pub fn event_key(event: &Event) -> String {
+  let begin_iso = event
+    .time_begin_iso
+    .clone()
+    .or_else(|| normalize_times(&event.time_begin, &event.time_end).0);
+  canonical_key(
+    &event.sender,
+    &event.event,
+    &event.time_begin,
+    begin_iso.as_deref(),
+    &event.position,
+  )
+}
+
+/// Normalized `time_begin`/`time_end` of an event and whether parsing failed.
+///
+/// An empty `time_end` is not a failure, the event simply has no known end.
+pub fn normalize_times(time_begin: &str, time_end: &str) -> (Option<String>, Option<String>, bool) {
+  let begin = time::parse_event_time(time_begin, None);
+  let end = time::parse_event_time(time_end, begin.map(|dt| dt.date_naive()));
+  let failed = begin.is_none() || (end.is_none() && !time_end.trim().is_empty());
+  (
+    begin.map(|dt| dt.to_rfc3339()),
+    end.map(|dt| dt.to_rfc3339()),
+    failed,
+  )
+}
+
+/// Insert the source email of an event if it is not stored yet and return its row id
+fn upsert_source_email(conn: &rusqlite::Connection, source: &SourceEmail) -> rusqlite::Result<i64> {
   conn.execute(
-    "CREATE TABLE IF NOT EXISTS events (
-          id INTEGER PRIMARY KEY,
-          sender TEXT NOT NULL,
-          event TEXT NOT NULL,
-          time_begin TEXT NOT NULL,
-          time_end TEXT NOT NULL,
-          position TEXT NOT NULL,
-          \"abstract\" TEXT NOT NULL,
-          speaker_name TEXT NOT NULL,
-          speaker_title TEXT NOT NULL
-      )",
-    [],
+    "INSERT INTO emails (message_id, subject, date, mailbox, uid) VALUES (?1, ?2, ?3, ?4, ?5)
+     ON CONFLICT (message_id) DO UPDATE SET
+       subject = excluded.subject,
+       date = excluded.date,
+       mailbox = excluded.mailbox,
+       uid = COALESCE(excluded.uid, uid)",
+    rusqlite::params![
+      source.message_id,
+      source.subject,
+      source.date,
+      source.mailbox,
+      source.uid
+    ],
   )?;
+  conn.query_row(
+    "SELECT id FROM emails WHERE message_id = ?1",
+    [&source.message_id],
+    |row| row.get(0),
+  )
+}
 
-  let mut updated = 0;
-  let mut inserted = 0;
+/// Outcome of `store_json_to_db`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+  /// Events that were not stored before
+  pub inserted: usize,
+  /// Stored events whose fields changed
+  pub updated: usize,
+  /// Stored events that were already identical
+  pub unchanged: usize,
+}
 
-  for event in events.clone() {
-    let event_name = event["event"].as_str().unwrap_or_default();
-    let sender = event["sender"].as_str().unwrap_or_default();
-    let position = event["position"].as_str().unwrap_or_default();
-    let time_begin = event["time_begin"].as_str().unwrap_or_default();
-    let time_end = event["time_end"].as_str().unwrap_or_default();
+impl std::ops::AddAssign for StoreStats {
+  fn add_assign(&mut self, other: Self) {
+    self.inserted += other.inserted;
+    self.updated += other.updated;
+    self.unchanged += other.unchanged;
+  }
+}
 
-    let mut stmt = conn.prepare(
-      "SELECT id FROM events WHERE sender = ?1 AND position = ?2 AND time_begin = ?3 AND time_end = ?4",
-    )?;
-    let exists = stmt.exists(rusqlite::params![sender, position, time_begin, time_end])?;
-
-    if exists {
-      // Update existing record
-      conn.execute(
-      "UPDATE events SET time_begin = ?1, time_end = ?2, position = ?3, \"abstract\" = ?4, speaker_name = ?5, speaker_title = ?6 
-       WHERE sender = ?7 AND event = ?8",
-      rusqlite::params![
-        event["time_begin"].as_str().unwrap_or_default(),
-        event["time_end"].as_str().unwrap_or_default(),
-        event["position"].as_str().unwrap_or_default(),
-        event["abstract"].as_str().unwrap_or_default(),
-        event["speaker_name"].as_str().unwrap_or_default(),
-        event["speaker_title"].as_str().unwrap_or_default(),
-        sender,
-        event_name,
-      ],
-    )?;
-      updated += 1;
-    } else {
-      conn.execute(
-        "INSERT INTO events (sender, event, time_begin, time_end, position, \"abstract\", speaker_name, speaker_title) 
-       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        rusqlite::params![
-          sender,
-          event_name,
-          event["time_begin"].as_str().unwrap_or_default(),
-          event["time_end"].as_str().unwrap_or_default(),
-          event["position"].as_str().unwrap_or_default(),
-          event["abstract"].as_str().unwrap_or_default(),
-          event["speaker_name"].as_str().unwrap_or_default(),
-          event["speaker_title"].as_str().unwrap_or_default(),
-        ],
-      )?;
-      inserted += 1;
+/// Insert events, or update the stored ones with the same `event_key`.
+///
+/// The whole batch is written in one transaction, so a failure leaves the
+/// database as it was.
+pub async fn store_json_to_db(
+  events: Vec<Event>,
+  path_to_db: &str,
+) -> Result<StoreStats, Box<dyn std::error::Error>> {
+  let mut conn = open_db(path_to_db)?;
+  let tx = conn.transaction()?;
+  let mut stats = StoreStats::default();
+
+  for event in &events {
+    match upsert_event(&tx, event)? {
+      (false, _) => stats.inserted += 1,
+      (true, true) => stats.updated += 1,
+      (true, false) => stats.unchanged += 1,
     }
   }
 
-  Ok((inserted, updated))
+  tx.commit()?;
+  Ok(stats)
+}
+
+/// Delete the stored events with the same `event_key` as `events`, such as
+/// talks that a calendar invite cancels
+///
+/// # Returns
+///
+/// How many rows were deleted
+pub async fn delete_events(
+  events: &[Event],
+  path_to_db: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+  let mut conn = open_db(path_to_db)?;
+  let tx = conn.transaction()?;
+  let mut deleted = 0;
+  for event in events {
+    deleted += tx.execute(
+      "DELETE FROM events WHERE event_key = ?1",
+      [event_key(event)],
+    )?;
+  }
+  tx.commit()?;
+  Ok(deleted)
+}
+
+/// Insert or update one event by its `event_key`
+///
+/// # Returns
+///
+/// Whether the event was stored before, and whether any column changed
+fn upsert_event(
+  tx: &rusqlite::Transaction,
+  event: &Event,
+) -> Result<(bool, bool), Box<dyn std::error::Error>> {
+  let email_id = match &event.source {
+    Some(source) => Some(upsert_source_email(tx, source)?),
+    None => None,
+  };
+  let (begin_iso, end_iso, time_parse_failed) = match &event.time_begin_iso {
+    // Structured sources such as calendar invites already know the exact time
+    Some(begin_iso) => (Some(begin_iso.clone()), event.time_end_iso.clone(), false),
+    None => normalize_times(&event.time_begin, &event.time_end),
+  };
+  let key = canonical_key(
+    &event.sender,
+    &event.event,
+    &event.time_begin,
+    begin_iso.as_deref(),
+    &event.position,
+  );
+
+  let exists: bool = tx.query_row(
+    "SELECT EXISTS (SELECT 1 FROM events WHERE event_key = ?1)",
+    [&key],
+    |row| row.get(0),
+  )?;
+  // The WHERE clause skips the update when nothing differs, so the change
+  // count tells updated rows from unchanged ones
+  let changed = tx.execute(
+    "INSERT INTO events (event_key, sender, event, time_begin, time_end, position, \"abstract\",
+       speaker_name, speaker_title, email_id, time_begin_iso, time_end_iso, time_parse_failed,
+       updated_at)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+     ON CONFLICT (event_key) DO UPDATE SET
+       sender = excluded.sender,
+       event = excluded.event,
+       time_begin = excluded.time_begin,
+       time_end = excluded.time_end,
+       position = excluded.position,
+       \"abstract\" = excluded.\"abstract\",
+       speaker_name = excluded.speaker_name,
+       speaker_title = excluded.speaker_title,
+       email_id = COALESCE(excluded.email_id, email_id),
+       time_begin_iso = excluded.time_begin_iso,
+       time_end_iso = excluded.time_end_iso,
+       time_parse_failed = excluded.time_parse_failed,
+       updated_at = excluded.updated_at
+     WHERE (sender, event, time_begin, time_end, position, \"abstract\", speaker_name,
+            speaker_title, email_id, time_begin_iso, time_end_iso, time_parse_failed)
+       IS NOT (excluded.sender, excluded.event, excluded.time_begin, excluded.time_end,
+            excluded.position, excluded.\"abstract\", excluded.speaker_name,
+            excluded.speaker_title, COALESCE(excluded.email_id, email_id),
+            excluded.time_begin_iso, excluded.time_end_iso, excluded.time_parse_failed)",
+    rusqlite::params![
+      key,
+      event.sender,
+      event.event,
+      event.time_begin,
+      event.time_end,
+      event.position,
+      event.r#abstract,
+      event.speaker_name,
+      event.speaker_title,
+      email_id,
+      begin_iso,
+      end_iso,
+      time_parse_failed,
+      chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    ],
+  )?;
+  Ok((exists, changed > 0))
+}
+
+/// One column an update would change, see `StoreChange::Update`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+  pub field: &'static str,
+  pub before: String,
+  pub after: String,
+}
+
+/// What `store_json_to_db` would do with one event, see `plan_store`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreChange {
+  /// A new row, as it would be stored
+  Insert(Event),
+  /// A stored row whose columns would change
+  Update {
+    stored: Event,
+    changes: Vec<FieldChange>,
+  },
+  /// A stored row that would stay as it is
+  Unchanged(Event),
+}
+
+/// Work out what `store_json_to_db` would insert and update without writing.
+///
+/// The events are stored in a transaction that is rolled back, so the plan
+/// matches a real store exactly, including time normalization and events that
+/// appear twice in `events`.
+pub async fn plan_store(
+  events: &[Event],
+  path_to_db: &str,
+) -> Result<Vec<StoreChange>, Box<dyn std::error::Error>> {
+  let mut conn = open_db(path_to_db)?;
+  let tx = conn.transaction()?;
+  let stored = |key: &str| -> rusqlite::Result<Option<Event>> {
+    let sql = format!(
+      "SELECT {} {} WHERE events.event_key = ?1",
+      EVENT_COLUMNS, EVENT_FROM
+    );
+    tx.query_row(&sql, [key], event_from_row).optional()
+  };
+
+  let mut plan = Vec::new();
+  for event in events {
+    let key = event_key(event);
+    let before = stored(&key)?;
+    upsert_event(&tx, event)?;
+    let after = stored(&key)?.ok_or("Planned event was not stored")?;
+
+    plan.push(match before {
+      // The row id is rolled back with the transaction
+      None => StoreChange::Insert(Event { id: None, ..after }),
+      Some(before) => {
+        let changes = changed_fields(&before, &after);
+        if changes.is_empty() {
+          StoreChange::Unchanged(after)
+        } else {
+          StoreChange::Update {
+            stored: before,
+            changes,
+          }
+        }
+      }
+    });
+  }
+
+  tx.rollback()?;
+  Ok(plan)
+}
+
+/// Stored columns that differ between two versions of an event
+fn changed_fields(before: &Event, after: &Event) -> Vec<FieldChange> {
+  type Column = (&'static str, fn(&Event) -> String);
+  let fields: [Column; 11] = [
+    ("sender", |e| e.sender.clone()),
+    ("event", |e| e.event.clone()),
+    ("time_begin", |e| e.time_begin.clone()),
+    ("time_end", |e| e.time_end.clone()),
+    ("position", |e| e.position.clone()),
+    ("abstract", |e| e.r#abstract.clone()),
+    ("speaker_name", |e| e.speaker_name.clone()),
+    ("speaker_title", |e| e.speaker_title.clone()),
+    ("time_begin_iso", |e| {
+      e.time_begin_iso.clone().unwrap_or_default()
+    }),
+    ("time_end_iso", |e| {
+      e.time_end_iso.clone().unwrap_or_default()
+    }),
+    ("source", |e| {
+      e.source
+        .as_ref()
+        .map(|source| source.message_id.clone())
+        .unwrap_or_default()
+    }),
+  ];
+  fields
+    .iter()
+    .filter_map(|(field, value)| {
+      let (before, after) = (value(before), value(after));
+      (before != after).then_some(FieldChange {
+        field,
+        before,
+        after,
+      })
+    })
+    .collect()
+}
+
+/// Columns of `events` joined with the email each event came from, see `EVENT_FROM`
+const EVENT_COLUMNS: &str = "events.*,
+  emails.message_id AS source_message_id,
+  emails.subject AS source_subject,
+  emails.date AS source_date,
+  emails.mailbox AS source_mailbox,
+  emails.uid AS source_uid";
+
+const EVENT_FROM: &str = "FROM events LEFT JOIN emails ON emails.id = events.email_id";
+
+/// Map a row selected with `EVENT_COLUMNS` to an `Event`
+fn event_from_row(row: &rusqlite::Row) -> rusqlite::Result<Event> {
+  let source = match row.get::<_, Option<String>>("source_message_id")? {
+    Some(message_id) => Some(SourceEmail {
+      message_id,
+      subject: row.get("source_subject")?,
+      date: row.get("source_date")?,
+      mailbox: row.get("source_mailbox")?,
+      uid: row.get("source_uid")?,
+    }),
+    None => None,
+  };
+
+  Ok(Event {
+    id: Some(row.get("id")?),
+    sender: row.get("sender")?,
+    event: row.get("event")?,
+    time_begin: row.get("time_begin")?,
+    time_end: row.get("time_end")?,
+    position: row.get("position")?,
+    r#abstract: row.get("abstract")?,
+    speaker_name: row.get("speaker_name")?,
+    speaker_title: row.get("speaker_title")?,
+    time_begin_iso: row.get("time_begin_iso")?,
+    time_end_iso: row.get("time_end_iso")?,
+    source_id: None,
+    source,
+    updated_at: row.get("updated_at")?,
+    event_key: row.get("event_key")?,
+  })
 }
 
 pub async fn search_events_by_time_begin(
   search_string: &str,
   path_to_db: &str,
-) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
-  let conn = rusqlite::Connection::open(path_to_db)?;
+) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
 
   // Prepare the query with LIKE operator to search for substring
-  let query = "SELECT * FROM events WHERE time_begin LIKE ?";
-  let mut stmt = conn.prepare(query)?;
+  let query = format!(
+    "SELECT {} {} WHERE time_begin LIKE ?",
+    EVENT_COLUMNS, EVENT_FROM
+  );
+  let mut stmt = conn.prepare(&query)?;
 
   // Execute the query with search pattern including wildcards
   let search_pattern = format!("%{}%", search_string);
-  let rows = stmt.query_map([search_pattern], |row| {
-    Ok(serde_json::json!({
-        "id": row.get::<_, i64>("id")?,
-        "sender": row.get::<_, String>("sender")?,
-        "event": row.get::<_, String>("event")?,
-        "time_begin": row.get::<_, String>("time_begin")?,
-        "time_end": row.get::<_, String>("time_end")?,
-        "position": row.get::<_, String>("position")?,
-        "abstract": row.get::<_, String>("abstract")?,
-        "speaker_name": row.get::<_, String>("speaker_name")?,
-        "speaker_title": row.get::<_, String>("speaker_title")?
-    }))
-  })?;
+  let rows = stmt.query_map([search_pattern], event_from_row)?;
 
   let mut events = Vec::new();
   for row in rows {
@@ -107,3 +427,619 @@ pub async fn search_events_by_time_begin(
 
   Ok(events)
 }
+
+/// How far a mailbox has been synced, see `email::fetch_emails_incremental`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncState {
+  /// Login and server the mailbox belongs to, e.g. `me@example.com@imap.example.com`
+  pub account: String,
+  pub mailbox: String,
+  /// UIDVALIDITY of the mailbox when it was last synced; UIDs are meaningless once it changes
+  pub uid_validity: u32,
+  /// Highest UID already processed
+  pub last_uid: u32,
+}
+
+pub async fn get_sync_state(
+  account: &str,
+  mailbox: &str,
+  path_to_db: &str,
+) -> Result<Option<SyncState>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let mut stmt = conn
+    .prepare("SELECT uid_validity, last_uid FROM sync_state WHERE account = ?1 AND mailbox = ?2")?;
+  let mut rows = stmt.query(rusqlite::params![account, mailbox])?;
+
+  match rows.next()? {
+    Some(row) => Ok(Some(SyncState {
+      account: account.to_string(),
+      mailbox: mailbox.to_string(),
+      uid_validity: row.get(0)?,
+      last_uid: row.get(1)?,
+    })),
+    None => Ok(None),
+  }
+}
+
+pub async fn save_sync_state(
+  state: &SyncState,
+  path_to_db: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  conn.execute(
+    "INSERT INTO sync_state (account, mailbox, uid_validity, last_uid, updated_at)
+     VALUES (?1, ?2, ?3, ?4, ?5)
+     ON CONFLICT (account, mailbox) DO UPDATE SET
+       uid_validity = excluded.uid_validity,
+       last_uid = excluded.last_uid,
+       updated_at = excluded.updated_at",
+    rusqlite::params![
+      state.account,
+      state.mailbox,
+      state.uid_validity,
+      state.last_uid,
+      chrono::Local::now().to_rfc3339(),
+    ],
+  )?;
+  Ok(())
+}
+
+/// Count one more failed attempt at message `uid` of the mailbox synced by `state`
+///
+/// # Arguments
+///
+/// * `state` - Account, mailbox and UIDVALIDITY the UID belongs to
+/// * `uid` - The message that could not be fetched or processed
+/// * `error` - Why, kept to see what happened to a skipped message
+///
+/// # Returns
+///
+/// How many attempts at the message have failed so far, this one included
+pub async fn record_uid_failure(
+  state: &SyncState,
+  uid: u32,
+  error: &str,
+  path_to_db: &str,
+) -> Result<u32, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let attempts = conn.query_row(
+    "INSERT INTO uid_failures (account, mailbox, uid_validity, uid, attempts, last_error, updated_at)
+     VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6)
+     ON CONFLICT (account, mailbox, uid_validity, uid) DO UPDATE SET
+       attempts = attempts + 1,
+       last_error = excluded.last_error,
+       updated_at = excluded.updated_at
+     RETURNING attempts",
+    rusqlite::params![
+      state.account,
+      state.mailbox,
+      state.uid_validity,
+      uid,
+      error,
+      chrono::Local::now().to_rfc3339(),
+    ],
+    |row| row.get(0),
+  )?;
+  Ok(attempts)
+}
+
+/// Time stamp of cache entries, UTC so that they sort as text
+fn cache_timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+  time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// Cached reply to an LLM request
+///
+/// # Arguments
+///
+/// * `key` - Hash of the request, see `api_req::cache_key`
+pub async fn get_cached_response(
+  key: &str,
+  path_to_db: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let response = conn
+    .query_row(
+      "SELECT response FROM llm_cache WHERE key = ?1",
+      [key],
+      |row| row.get(0),
+    )
+    .optional()?;
+  Ok(response)
+}
+
+/// Remember the reply to an LLM request, replacing an older one
+pub async fn save_cached_response(
+  key: &str,
+  model: &str,
+  response: &str,
+  path_to_db: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  conn.execute(
+    "INSERT OR REPLACE INTO llm_cache (key, model, response, created_at) VALUES (?1, ?2, ?3, ?4)",
+    rusqlite::params![key, model, response, cache_timestamp(chrono::Utc::now())],
+  )?;
+  Ok(())
+}
+
+/// Delete cached LLM replies stored more than `older_than` ago
+///
+/// # Returns
+///
+/// The number of deleted entries
+pub async fn prune_cache(
+  older_than: chrono::Duration,
+  path_to_db: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let cutoff = cache_timestamp(chrono::Utc::now() - older_than);
+  Ok(conn.execute("DELETE FROM llm_cache WHERE created_at < ?1", [cutoff])?)
+}
+
+/// Token usage and outcome of one `query` run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+  /// RFC 3339 local time the run started
+  pub started_at: String,
+  pub provider: String,
+  pub model: String,
+  pub prompt_tokens: u64,
+  pub completion_tokens: u64,
+  /// Estimated from `Config::prices`, `None` if the model has no price
+  pub cost: Option<f64>,
+  pub email_count: usize,
+  pub event_count: usize,
+}
+
+/// Record a run, see `spend_by_week`
+pub async fn save_run(run: &Run, path_to_db: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  conn.execute(
+    "INSERT INTO runs (started_at, provider, model, prompt_tokens, completion_tokens, cost,
+                       email_count, event_count)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    rusqlite::params![
+      run.started_at,
+      run.provider,
+      run.model,
+      run.prompt_tokens as i64,
+      run.completion_tokens as i64,
+      run.cost,
+      run.email_count as i64,
+      run.event_count as i64,
+    ],
+  )?;
+  Ok(())
+}
+
+/// Runs of one model in one week, see `spend_by_week`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeeklySpend {
+  /// Monday the week starts on, `YYYY-MM-DD`
+  pub week: String,
+  pub model: String,
+  pub runs: usize,
+  pub prompt_tokens: u64,
+  pub completion_tokens: u64,
+  /// Sum over the priced runs, `None` if none of them had a price
+  pub cost: Option<f64>,
+  pub email_count: usize,
+  pub event_count: usize,
+}
+
+/// Token usage and cost of all recorded runs by week and model, newest week first
+pub async fn spend_by_week(
+  path_to_db: &str,
+) -> Result<Vec<WeeklySpend>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  // Weeks start on the Monday on or before the run's local date
+  let mut stmt = conn.prepare(
+    "SELECT date(substr(started_at, 1, 10), '-6 days', 'weekday 1') AS week, model,
+            COUNT(*), SUM(prompt_tokens), SUM(completion_tokens), SUM(cost),
+            SUM(email_count), SUM(event_count)
+     FROM runs
+     GROUP BY week, model
+     ORDER BY week DESC, model",
+  )?;
+  let rows = stmt.query_map([], |row| {
+    Ok(WeeklySpend {
+      week: row.get(0)?,
+      model: row.get(1)?,
+      runs: row.get::<_, i64>(2)? as usize,
+      prompt_tokens: row.get::<_, i64>(3)? as u64,
+      completion_tokens: row.get::<_, i64>(4)? as u64,
+      cost: row.get(5)?,
+      email_count: row.get::<_, i64>(6)? as usize,
+      event_count: row.get::<_, i64>(7)? as usize,
+    })
+  })?;
+  Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// What `search_events` orders results by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+  /// Normalized begin time, events with unparsed times last
+  #[default]
+  Time,
+  /// Insertion order
+  Id,
+  Speaker,
+}
+
+/// Filters for `search_events`; `None` fields do not restrict the result
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+  /// Earliest begin date, inclusive
+  pub from: Option<chrono::NaiveDate>,
+  /// Latest begin date, inclusive
+  pub to: Option<chrono::NaiveDate>,
+  /// Substring of the free-text `time_begin`
+  pub time_begin: Option<String>,
+  /// Substring of the speaker's name or title
+  pub speaker: Option<String>,
+  pub position: Option<String>,
+  pub sender: Option<String>,
+  /// Substring of the event title or abstract
+  pub keyword: Option<String>,
+  /// Earliest begin time, inclusive; finer-grained than `from`
+  pub since: Option<chrono::DateTime<chrono::FixedOffset>>,
+  pub sort: SortKey,
+  pub descending: bool,
+  pub limit: Option<usize>,
+  /// Number of results to skip, for pagination
+  pub offset: Option<usize>,
+}
+
+/// Escape `%`, `_` and `\` so user input matches literally inside `LIKE ... ESCAPE '\'`
+fn like_pattern(s: &str) -> String {
+  let escaped = s
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_");
+  format!("%{}%", escaped)
+}
+
+/// Append a condition that binds one parameter; every `?` in it refers to that parameter
+fn push_filter(
+  conditions: &mut Vec<String>,
+  params: &mut Vec<String>,
+  condition: &str,
+  param: String,
+) {
+  params.push(param);
+  conditions.push(condition.replace('?', &format!("?{}", params.len())));
+}
+
+/// SQL conditions and their parameters for the filters in `query`
+fn build_filters(query: &EventQuery) -> (Vec<String>, Vec<String>) {
+  let mut conditions: Vec<String> = Vec::new();
+  let mut params: Vec<String> = Vec::new();
+  let mut add =
+    |condition: &str, param: String| push_filter(&mut conditions, &mut params, condition, param);
+
+  // Normalized times all carry the +08:00 offset, so they compare correctly as text
+  if let Some(from) = query.from {
+    add(
+      "events.time_begin_iso >= ?",
+      from.format("%Y-%m-%d").to_string(),
+    );
+  }
+  if let Some(to) = query.to.and_then(|to| to.succ_opt()) {
+    add(
+      "events.time_begin_iso < ?",
+      to.format("%Y-%m-%d").to_string(),
+    );
+  }
+  if let Some(since) = query.since {
+    add(
+      "events.time_begin_iso >= ?",
+      since.with_timezone(&time::shanghai()).to_rfc3339(),
+    );
+  }
+  if let Some(time_begin) = &query.time_begin {
+    add(
+      "events.time_begin LIKE ? ESCAPE '\\'",
+      like_pattern(time_begin),
+    );
+  }
+  if let Some(speaker) = &query.speaker {
+    add(
+      "(events.speaker_name LIKE ? ESCAPE '\\' OR events.speaker_title LIKE ? ESCAPE '\\')",
+      like_pattern(speaker),
+    );
+  }
+  if let Some(position) = &query.position {
+    add("events.position LIKE ? ESCAPE '\\'", like_pattern(position));
+  }
+  if let Some(sender) = &query.sender {
+    add("events.sender LIKE ? ESCAPE '\\'", like_pattern(sender));
+  }
+  if let Some(keyword) = &query.keyword {
+    add(
+      "(events.event LIKE ? ESCAPE '\\' OR events.\"abstract\" LIKE ? ESCAPE '\\')",
+      like_pattern(keyword),
+    );
+  }
+
+  (conditions, params)
+}
+
+/// `ORDER BY` and `LIMIT` clauses for `query`
+fn order_and_limit(query: &EventQuery) -> String {
+  let direction = if query.descending { "DESC" } else { "ASC" };
+  let order = match query.sort {
+    SortKey::Time => format!(
+      "events.time_begin_iso IS NULL, events.time_begin_iso {direction}, events.id {direction}"
+    ),
+    SortKey::Id => format!("events.id {direction}"),
+    SortKey::Speaker => format!("events.speaker_name {direction}, events.id {direction}"),
+  };
+  format!(" ORDER BY {}{}", order, limit_clause(query))
+}
+
+/// `LIMIT` and `OFFSET` clauses for `query`, empty when neither is set
+fn limit_clause(query: &EventQuery) -> String {
+  match (query.limit, query.offset) {
+    (None, None) => String::new(),
+    (limit, offset) => format!(
+      " LIMIT {} OFFSET {}",
+      // SQLite only accepts OFFSET after a LIMIT, -1 meaning none
+      limit.map_or(-1, |limit| limit as i64),
+      offset.unwrap_or(0)
+    ),
+  }
+}
+
+/// Search events with the filters in `query`, combined with AND
+pub async fn search_events(
+  query: &EventQuery,
+  path_to_db: &str,
+) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let (conditions, params) = build_filters(query);
+
+  let mut sql = format!("SELECT {} {}", EVENT_COLUMNS, EVENT_FROM);
+  if !conditions.is_empty() {
+    sql.push_str(" WHERE ");
+    sql.push_str(&conditions.join(" AND "));
+  }
+  sql.push_str(&order_and_limit(query));
+
+  let mut stmt = conn.prepare(&sql)?;
+  let rows = stmt.query_map(rusqlite::params_from_iter(params), event_from_row)?;
+
+  let mut events = Vec::new();
+  for row in rows {
+    events.push(row?);
+  }
+  Ok(events)
+}
+
+/// Count the events matching the filters in `query`, ignoring its limit and offset
+pub async fn count_events(
+  query: &EventQuery,
+  path_to_db: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let (conditions, params) = build_filters(query);
+
+  let mut sql = "SELECT COUNT(*) FROM events".to_string();
+  if !conditions.is_empty() {
+    sql.push_str(" WHERE ");
+    sql.push_str(&conditions.join(" AND "));
+  }
+  let count: i64 = conn.query_row(&sql, rusqlite::params_from_iter(params), |row| row.get(0))?;
+  Ok(count as usize)
+}
+
+/// Get one event by its row id
+pub async fn get_event(
+  id: i64,
+  path_to_db: &str,
+) -> Result<Option<Event>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let sql = format!(
+    "SELECT {} {} WHERE events.id = ?1",
+    EVENT_COLUMNS, EVENT_FROM
+  );
+  let mut stmt = conn.prepare(&sql)?;
+  let mut rows = stmt.query_map([id], event_from_row)?;
+  Ok(rows.next().transpose()?)
+}
+
+/// Markers placed around matched text in `TextMatch::snippet`
+pub const HIGHLIGHT: (&str, &str) = ("【", "】");
+
+/// A full-text search hit
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TextMatch {
+  pub event: Event,
+  /// Excerpt around the match with the matched text wrapped in `HIGHLIGHT`
+  pub snippet: String,
+  /// BM25 score, lower is better; `None` when only short terms were searched
+  pub rank: Option<f64>,
+}
+
+/// Full-text search over event titles, abstracts and speakers.
+///
+/// The index uses the trigram tokenizer so Chinese text needs no word
+/// segmentation, but trigrams cannot match terms shorter than three
+/// characters such as `拓扑`; those fall back to a substring match.
+///
+/// # Arguments
+///
+/// * `text` - Whitespace-separated terms, all of which must match
+/// * `query` - Further filters; `sort` only applies when there is no ranked term
+/// * `path_to_db`
+///
+/// # Returns
+///
+/// The matches, best first
+pub async fn search_events_fulltext(
+  text: &str,
+  query: &EventQuery,
+  path_to_db: &str,
+) -> Result<Vec<TextMatch>, Box<dyn std::error::Error>> {
+  let conn = open_db(path_to_db)?;
+  let terms: Vec<&str> = text.split_whitespace().collect();
+  if terms.is_empty() {
+    return Ok(Vec::new());
+  }
+  let (long_terms, short_terms): (Vec<&str>, Vec<&str>) =
+    terms.iter().partition(|term| term.chars().count() >= 3);
+
+  let (mut conditions, mut params) = build_filters(query);
+  for term in &short_terms {
+    push_filter(
+      &mut conditions,
+      &mut params,
+      "(events.event LIKE ? ESCAPE '\\' OR events.\"abstract\" LIKE ? ESCAPE '\\' OR events.speaker_name LIKE ? ESCAPE '\\')",
+      like_pattern(term),
+    );
+  }
+
+  let mut sql = if long_terms.is_empty() {
+    format!(
+      "SELECT {}, NULL AS snippet, NULL AS score {}",
+      EVENT_COLUMNS, EVENT_FROM
+    )
+  } else {
+    // Quote each term so FTS5 query syntax in user input is taken literally
+    let fts_query = long_terms
+      .iter()
+      .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+      .collect::<Vec<_>>()
+      .join(" ");
+    push_filter(
+      &mut conditions,
+      &mut params,
+      "events_fts MATCH ?",
+      fts_query,
+    );
+    format!(
+      "SELECT {}, snippet(events_fts, -1, '{}', '{}', '…', 24) AS snippet,
+         bm25(events_fts, 10.0, 1.0, 5.0) AS score
+       {} JOIN events_fts ON events_fts.rowid = events.id",
+      EVENT_COLUMNS, HIGHLIGHT.0, HIGHLIGHT.1, EVENT_FROM
+    )
+  };
+  sql.push_str(" WHERE ");
+  sql.push_str(&conditions.join(" AND "));
+  if long_terms.is_empty() {
+    sql.push_str(&order_and_limit(query));
+  } else {
+    sql.push_str(" ORDER BY score");
+    sql.push_str(&limit_clause(query));
+  }
+
+  let mut stmt = conn.prepare(&sql)?;
+  let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+    Ok((
+      event_from_row(row)?,
+      row.get::<_, Option<String>>("snippet")?,
+      row.get::<_, Option<f64>>("score")?,
+    ))
+  })?;
+
+  let mut matches = Vec::new();
+  for row in rows {
+    let (event, snippet, rank) = row?;
+    let snippet = snippet.unwrap_or_else(|| highlight_snippet(&event, &short_terms));
+    matches.push(TextMatch {
+      event,
+      snippet,
+      rank,
+    });
+  }
+  Ok(matches)
+}
+
+/// Build a snippet by hand for matches that did not go through FTS5.
+///
+/// Terms match regardless of case, like the search itself, and the snippet
+/// keeps the case of the event's text.
+fn highlight_snippet(event: &Event, terms: &[&str]) -> String {
+  const CONTEXT: usize = 24;
+
+  let terms: Vec<Vec<char>> = terms.iter().map(|term| fold_case(term)).collect();
+  // Prefer the field matching the most terms, and the earlier field on a tie
+  let matched = |field: &str| {
+    let folded = fold_case(field);
+    terms
+      .iter()
+      .filter(|term| !find_all(&folded, term).is_empty())
+      .count()
+  };
+  let text = [&event.event, &event.r#abstract, &event.speaker_name]
+    .into_iter()
+    .rev()
+    .max_by_key(|field| matched(field))
+    .unwrap_or(&event.event);
+  let chars: Vec<char> = text.chars().collect();
+  let folded = fold_case(text);
+  let occurrences: Vec<(usize, usize)> = terms
+    .iter()
+    .flat_map(|term| {
+      find_all(&folded, term)
+        .into_iter()
+        .map(|start| (start, start + term.len()))
+    })
+    .collect();
+  let first = occurrences
+    .iter()
+    .map(|(start, _)| *start)
+    .min()
+    .unwrap_or(0);
+  let start = first.saturating_sub(CONTEXT / 2);
+  let end = (start + CONTEXT).min(chars.len());
+
+  // Highlight the terms that fit in the snippet, merging overlapping ones
+  let mut highlighted = vec![false; end - start];
+  for (from, to) in occurrences {
+    if from >= start && to <= end {
+      highlighted[from - start..to - start].fill(true);
+    }
+  }
+  let mut snippet = String::new();
+  if start > 0 {
+    snippet.push('…');
+  }
+  for (i, c) in chars[start..end].iter().enumerate() {
+    let inside = highlighted[i];
+    if inside && (i == 0 || !highlighted[i - 1]) {
+      snippet.push_str(HIGHLIGHT.0);
+    }
+    snippet.push(*c);
+    if inside && highlighted.get(i + 1) != Some(&true) {
+      snippet.push_str(HIGHLIGHT.1);
+    }
+  }
+  if end < chars.len() {
+    snippet.push('…');
+  }
+  snippet
+}
+
+/// Lowercase `text` one character at a time, so indices still line up with
+/// the characters of the original
+fn fold_case(text: &str) -> Vec<char> {
+  text
+    .chars()
+    .map(|c| c.to_lowercase().next().unwrap_or(c))
+    .collect()
+}
+
+/// Character offsets of every occurrence of `term` in `text`
+fn find_all(text: &[char], term: &[char]) -> Vec<usize> {
+  if term.is_empty() {
+    return Vec::new();
+  }
+  text
+    .windows(term.len())
+    .enumerate()
+    .filter(|(_, window)| *window == term)
+    .map(|(i, _)| i)
+    .collect()
+}
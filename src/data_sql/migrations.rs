@@ -0,0 +1,423 @@
+mod frozen;
+
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::error::Error;
+
+/// One forward step of the schema
+pub struct Migration {
+  pub version: u32,
+  pub description: &'static str,
+  apply: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// Every schema change, in the order `migrate` applies them.
+///
+/// Append new entries and never edit old ones. Databases created before
+/// versioning have no `schema_version` table but may already contain some of
+/// these changes, so every migration must be idempotent. Logic a migration
+/// shares with the rest of the crate is copied into `frozen`, so later changes
+/// there cannot alter what an old migration does.
+pub const MIGRATIONS: &[Migration] = &[
+  Migration {
+    version: 1,
+    description: "events, emails and sync_state tables",
+    apply: baseline,
+  },
+  Migration {
+    version: 2,
+    description: "link events to their source email",
+    apply: add_email_id,
+  },
+  Migration {
+    version: 3,
+    description: "normalized begin and end times",
+    apply: add_normalized_times,
+  },
+  Migration {
+    version: 4,
+    description: "full-text index over events",
+    apply: add_fulltext_index,
+  },
+  Migration {
+    version: 5,
+    description: "unique event key",
+    apply: add_event_key,
+  },
+  Migration {
+    version: 6,
+    description: "cache of LLM responses",
+    apply: add_llm_cache,
+  },
+  Migration {
+    version: 7,
+    description: "token usage and cost of query runs",
+    apply: add_runs,
+  },
+  Migration {
+    version: 8,
+    description: "last change time of events",
+    apply: add_event_updated_at,
+  },
+  Migration {
+    version: 9,
+    description: "event keys that tell apart talks without a time or place",
+    apply: rekey_date_only_and_placeless_events,
+  },
+  Migration {
+    version: 10,
+    description: "failed attempts per message",
+    apply: add_uid_failures,
+  },
+];
+
+/// A row of the `schema_version` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+  pub version: u32,
+  pub description: String,
+  pub applied_at: String,
+}
+
+/// Version the schema reaches after all migrations
+pub fn latest_version() -> u32 {
+  MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+fn has_version_table(conn: &Connection) -> rusqlite::Result<bool> {
+  conn.query_row(
+    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+    [],
+    |row| row.get(0),
+  )
+}
+
+/// Schema version of a database, 0 if it was never migrated
+pub fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+  if !has_version_table(conn)? {
+    return Ok(0);
+  }
+  let version: Option<u32> = conn
+    .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+      row.get(0)
+    })
+    .optional()?
+    .flatten();
+  Ok(version.unwrap_or(0))
+}
+
+/// Migrations recorded in the database, oldest first
+pub fn applied(conn: &Connection) -> rusqlite::Result<Vec<AppliedMigration>> {
+  if !has_version_table(conn)? {
+    return Ok(Vec::new());
+  }
+  let mut stmt =
+    conn.prepare("SELECT version, description, applied_at FROM schema_version ORDER BY version")?;
+  let rows = stmt.query_map([], |row| {
+    Ok(AppliedMigration {
+      version: row.get(0)?,
+      description: row.get(1)?,
+      applied_at: row.get(2)?,
+    })
+  })?;
+  rows.collect()
+}
+
+/// Migrations not applied to the database yet
+pub fn pending(conn: &Connection) -> rusqlite::Result<Vec<&'static Migration>> {
+  let current = current_version(conn)?;
+  Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Apply all pending migrations.
+///
+/// # Returns
+///
+/// The migrations that were applied, or an error if the database was written
+/// by a newer version of the tool
+pub fn migrate(conn: &mut Connection) -> Result<Vec<&'static Migration>, Box<dyn Error>> {
+  let current = current_version(conn)?;
+  if current > latest_version() {
+    return Err(
+      format!(
+        "Database schema version {} is newer than this build supports ({}), please upgrade",
+        current,
+        latest_version()
+      )
+      .into(),
+    );
+  }
+
+  let pending = pending(conn)?;
+  for migration in &pending {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+      "CREATE TABLE IF NOT EXISTS schema_version (
+          version INTEGER PRIMARY KEY,
+          description TEXT NOT NULL,
+          applied_at TEXT NOT NULL
+      );",
+    )?;
+    (migration.apply)(&tx).map_err(|e| format!("Migration {} failed: {}", migration.version, e))?;
+    tx.execute(
+      "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+      rusqlite::params![
+        migration.version,
+        migration.description,
+        chrono::Utc::now().to_rfc3339()
+      ],
+    )?;
+    tx.commit()?;
+  }
+  Ok(pending)
+}
+
+fn baseline(conn: &Connection) -> rusqlite::Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS events (
+          id INTEGER PRIMARY KEY,
+          sender TEXT NOT NULL,
+          event TEXT NOT NULL,
+          time_begin TEXT NOT NULL,
+          time_end TEXT NOT NULL,
+          position TEXT NOT NULL,
+          \"abstract\" TEXT NOT NULL,
+          speaker_name TEXT NOT NULL,
+          speaker_title TEXT NOT NULL
+      );
+      CREATE TABLE IF NOT EXISTS emails (
+          id INTEGER PRIMARY KEY,
+          message_id TEXT NOT NULL UNIQUE,
+          subject TEXT NOT NULL,
+          date TEXT NOT NULL,
+          mailbox TEXT NOT NULL,
+          uid INTEGER
+      );
+      CREATE TABLE IF NOT EXISTS sync_state (
+          account TEXT NOT NULL,
+          mailbox TEXT NOT NULL,
+          uid_validity INTEGER NOT NULL,
+          last_uid INTEGER NOT NULL,
+          updated_at TEXT NOT NULL,
+          PRIMARY KEY (account, mailbox)
+      );",
+  )
+}
+
+fn add_email_id(conn: &Connection) -> rusqlite::Result<()> {
+  add_column_if_missing(conn, "events", "email_id", "INTEGER REFERENCES emails(id)")
+}
+
+/// Add the normalized time columns and fill them in for existing rows
+fn add_normalized_times(conn: &Connection) -> rusqlite::Result<()> {
+  add_column_if_missing(conn, "events", "time_begin_iso", "TEXT")?;
+  add_column_if_missing(conn, "events", "time_end_iso", "TEXT")?;
+  add_column_if_missing(
+    conn,
+    "events",
+    "time_parse_failed",
+    "INTEGER NOT NULL DEFAULT 0",
+  )?;
+
+  let mut stmt = conn.prepare(
+    "SELECT id, time_begin, time_end FROM events
+     WHERE time_begin_iso IS NULL AND time_parse_failed = 0",
+  )?;
+  let rows = stmt
+    .query_map([], |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, String>(1)?,
+        row.get::<_, String>(2)?,
+      ))
+    })?
+    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+  for (id, time_begin, time_end) in rows {
+    let (begin_iso, end_iso, failed) = frozen::normalize_times_v1(&time_begin, &time_end);
+    conn.execute(
+      "UPDATE events SET time_begin_iso = ?1, time_end_iso = ?2, time_parse_failed = ?3 WHERE id = ?4",
+      rusqlite::params![begin_iso, end_iso, failed, id],
+    )?;
+  }
+  Ok(())
+}
+
+/// Create the FTS5 index over events and the triggers that keep it in sync.
+///
+/// The index is rebuilt from `events` when it is first created, so databases
+/// from older versions become searchable too.
+fn add_fulltext_index(conn: &Connection) -> rusqlite::Result<()> {
+  let exists: bool = conn.query_row(
+    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'events_fts')",
+    [],
+    |row| row.get(0),
+  )?;
+
+  conn.execute_batch(
+    "CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5(
+          event, \"abstract\", speaker_name,
+          content = 'events', content_rowid = 'id', tokenize = 'trigram'
+      );
+      CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+          INSERT INTO events_fts (rowid, event, \"abstract\", speaker_name)
+          VALUES (new.id, new.event, new.\"abstract\", new.speaker_name);
+      END;
+      CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+          INSERT INTO events_fts (events_fts, rowid, event, \"abstract\", speaker_name)
+          VALUES ('delete', old.id, old.event, old.\"abstract\", old.speaker_name);
+      END;
+      CREATE TRIGGER IF NOT EXISTS events_fts_update AFTER UPDATE ON events BEGIN
+          INSERT INTO events_fts (events_fts, rowid, event, \"abstract\", speaker_name)
+          VALUES ('delete', old.id, old.event, old.\"abstract\", old.speaker_name);
+          INSERT INTO events_fts (rowid, event, \"abstract\", speaker_name)
+          VALUES (new.id, new.event, new.\"abstract\", new.speaker_name);
+      END;",
+  )?;
+
+  if !exists {
+    conn.execute("INSERT INTO events_fts (events_fts) VALUES ('rebuild')", [])?;
+  }
+  Ok(())
+}
+
+/// Add `event_key`, compute it for every row and create its unique index.
+///
+/// Keys written by earlier builds are recomputed too. Older versions could
+/// store the same event twice, but no row is deleted here, see `rekey_events`.
+fn add_event_key(conn: &Connection) -> rusqlite::Result<()> {
+  add_column_if_missing(conn, "events", "event_key", "TEXT")?;
+  rekey_events(conn, frozen::event_key_v1)
+}
+
+/// Recompute `event_key` now that the title also counts for date-only begin
+/// times and events without a place, which the first keys let collide
+fn rekey_date_only_and_placeless_events(conn: &Connection) -> rusqlite::Result<()> {
+  rekey_events(conn, frozen::event_key_v2)
+}
+
+/// Recompute `event_key` for every row with `key` and rebuild its unique index.
+///
+/// Rows whose keys collide are all kept: the most recently stored copy gets
+/// the key, and the others keep it with their row id appended, so they stay
+/// visible and can be cleaned up by hand.
+fn rekey_events(
+  conn: &Connection,
+  key: fn(&str, &str, &str, Option<&str>, &str) -> String,
+) -> rusqlite::Result<()> {
+  conn.execute_batch("DROP INDEX IF EXISTS events_event_key;")?;
+
+  let mut stmt = conn.prepare(
+    "SELECT id, sender, event, time_begin, time_begin_iso, position, event_key FROM events
+     ORDER BY id DESC",
+  )?;
+  let rows = stmt
+    .query_map([], |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        key(
+          &row.get::<_, String>(1)?,
+          &row.get::<_, String>(2)?,
+          &row.get::<_, String>(3)?,
+          row.get::<_, Option<String>>(4)?.as_deref(),
+          &row.get::<_, String>(5)?,
+        ),
+        row.get::<_, Option<String>>(6)?,
+      ))
+    })?
+    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+  let mut seen = HashSet::new();
+  let mut collisions = 0;
+  for (id, key, stored) in rows {
+    let key = if seen.insert(key.clone()) {
+      key
+    } else {
+      collisions += 1;
+      format!("{}\u{1f}#{}", key, id)
+    };
+    if stored.as_deref() != Some(key.as_str()) {
+      conn.execute(
+        "UPDATE events SET event_key = ?1 WHERE id = ?2",
+        rusqlite::params![key, id],
+      )?;
+    }
+  }
+  if collisions > 0 {
+    eprintln!(
+      "Kept {} events that look like duplicates of newer ones; their keys end in the row id",
+      collisions
+    );
+  }
+
+  conn.execute_batch("CREATE UNIQUE INDEX IF NOT EXISTS events_event_key ON events (event_key);")
+}
+
+fn add_llm_cache(conn: &Connection) -> rusqlite::Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS llm_cache (
+          key TEXT PRIMARY KEY,
+          model TEXT NOT NULL,
+          response TEXT NOT NULL,
+          created_at TEXT NOT NULL
+      );",
+  )
+}
+
+fn add_runs(conn: &Connection) -> rusqlite::Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS runs (
+          id INTEGER PRIMARY KEY,
+          started_at TEXT NOT NULL,
+          provider TEXT NOT NULL,
+          model TEXT NOT NULL,
+          prompt_tokens INTEGER NOT NULL,
+          completion_tokens INTEGER NOT NULL,
+          cost REAL,
+          email_count INTEGER NOT NULL,
+          event_count INTEGER NOT NULL
+      );",
+  )
+}
+
+fn add_event_updated_at(conn: &Connection) -> rusqlite::Result<()> {
+  // Existing rows keep NULL, their last change is unknown
+  add_column_if_missing(conn, "events", "updated_at", "TEXT")
+}
+
+fn add_uid_failures(conn: &Connection) -> rusqlite::Result<()> {
+  conn.execute_batch(
+    "CREATE TABLE IF NOT EXISTS uid_failures (
+          account TEXT NOT NULL,
+          mailbox TEXT NOT NULL,
+          uid_validity INTEGER NOT NULL,
+          uid INTEGER NOT NULL,
+          attempts INTEGER NOT NULL,
+          last_error TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          PRIMARY KEY (account, mailbox, uid_validity, uid)
+      );",
+  )
+}
+
+/// Add a column to a table created by an older version of the tool
+fn add_column_if_missing(
+  conn: &Connection,
+  table: &str,
+  column: &str,
+  definition: &str,
+) -> rusqlite::Result<()> {
+  let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+  let exists = stmt
+    .query_map([], |row| row.get::<_, String>("name"))?
+    .collect::<rusqlite::Result<Vec<_>>>()?
+    .iter()
+    .any(|name| name == column);
+  if !exists {
+    conn.execute(
+      &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+      [],
+    )?;
+  }
+  Ok(())
+}
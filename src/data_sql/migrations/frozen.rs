@@ -0,0 +1,142 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Timelike};
+
+/// Normalized times as computed by migration 3, a copy of the time parsing
+/// of that release
+///
+/// # Returns
+///
+/// The begin and end as RFC 3339, and whether parsing failed
+pub fn normalize_times_v1(
+  time_begin: &str,
+  time_end: &str,
+) -> (Option<String>, Option<String>, bool) {
+  let begin = parse_event_time_v1(time_begin, None);
+  let end = parse_event_time_v1(time_end, begin.map(|dt| dt.date_naive()));
+  let failed = begin.is_none() || (end.is_none() && !time_end.trim().is_empty());
+  (
+    begin.map(|dt| dt.to_rfc3339()),
+    end.map(|dt| dt.to_rfc3339()),
+    failed,
+  )
+}
+
+fn parse_event_time_v1(
+  text: &str,
+  default_date: Option<NaiveDate>,
+) -> Option<DateTime<FixedOffset>> {
+  let shanghai = FixedOffset::east_opt(8 * 3600)?;
+  let text = text.trim();
+  if text.is_empty() {
+    return None;
+  }
+  if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+    return Some(dt.with_timezone(&shanghai));
+  }
+
+  let numbers = digit_runs_v1(text);
+  let (date, time_numbers) = match numbers.first() {
+    Some((year, len)) if *len == 4 => {
+      let month = numbers.get(1)?.0;
+      let day = numbers.get(2)?.0;
+      (
+        NaiveDate::from_ymd_opt(*year as i32, month, day)?,
+        &numbers[3..],
+      )
+    }
+    Some(_) if numbers.len() <= 2 => (default_date?, &numbers[..]),
+    _ => return None,
+  };
+
+  let mut hour = time_numbers.first().map(|n| n.0);
+  let mut minute = time_numbers.get(1).map(|n| n.0).unwrap_or(0);
+  if text.contains('半') && time_numbers.len() == 1 {
+    minute = 30;
+  }
+
+  let lower = text.to_lowercase();
+  let is_pm = ["下午", "晚上", "傍晚", "pm", "p.m."]
+    .iter()
+    .any(|marker| lower.contains(marker));
+  let is_noon = lower.contains("中午");
+  if let Some(h) = hour {
+    if (is_pm && h < 12) || (is_noon && h < 11) {
+      hour = Some(h + 12);
+    }
+  }
+
+  let time = NaiveTime::from_hms_opt(hour.unwrap_or(0), minute, 0)?;
+  shanghai.from_local_datetime(&date.and_time(time)).single()
+}
+
+fn digit_runs_v1(text: &str) -> Vec<(u32, usize)> {
+  let mut runs = Vec::new();
+  let mut current: Option<(u32, usize)> = None;
+
+  for c in text.chars() {
+    let digit = c.to_digit(10).or_else(|| match c {
+      '０'..='９' => Some(c as u32 - '０' as u32),
+      _ => None,
+    });
+    match (digit, current.as_mut()) {
+      (Some(d), Some((value, len))) => {
+        *value = value.saturating_mul(10).saturating_add(d);
+        *len += 1;
+      }
+      (Some(d), None) => current = Some((d, 1)),
+      (None, _) => runs.extend(current.take()),
+    }
+  }
+  runs.extend(current);
+  runs
+}
+
+fn normalize_v1(s: &str) -> String {
+  s.split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+}
+
+/// `event_key` as computed by migration 5: the title only counts when the
+/// begin time cannot be parsed
+pub fn event_key_v1(
+  sender: &str,
+  event: &str,
+  time_begin: &str,
+  time_begin_iso: Option<&str>,
+  position: &str,
+) -> String {
+  let mut parts = vec![normalize_v1(sender)];
+  match time_begin_iso {
+    Some(begin) => parts.push(begin.to_string()),
+    None => parts.extend([normalize_v1(time_begin), normalize_v1(event)]),
+  }
+  parts.push(normalize_v1(position));
+  parts.join("\u{1f}")
+}
+
+/// `event_key` as computed by migration 9: the title also counts for
+/// date-only begin times and events without a place
+pub fn event_key_v2(
+  sender: &str,
+  event: &str,
+  time_begin: &str,
+  time_begin_iso: Option<&str>,
+  position: &str,
+) -> String {
+  let mut parts = vec![normalize_v1(sender)];
+  match time_begin_iso {
+    Some(begin) => {
+      parts.push(begin.to_string());
+      let date_only = DateTime::parse_from_rfc3339(begin)
+        .map(|dt| dt.hour() == 0 && dt.minute() == 0)
+        .unwrap_or(false);
+      if date_only || position.trim().is_empty() {
+        parts.push(normalize_v1(event));
+      }
+    }
+    None => parts.extend([normalize_v1(time_begin), normalize_v1(event)]),
+  }
+  parts.push(normalize_v1(position));
+  parts.join("\u{1f}")
+}
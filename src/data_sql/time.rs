@@ -0,0 +1,95 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone};
+
+/// Offset of Asia/Shanghai, which has not observed DST since 1991
+pub fn shanghai() -> FixedOffset {
+  FixedOffset::east_opt(8 * 3600).unwrap()
+}
+
+/// Parse an event time as written by the model into a Shanghai-local timestamp.
+///
+/// Besides the requested `2025年03月14日 14时00分`, this accepts the variants
+/// the model tends to produce instead: `2025-03-14 14:00`, `2025/3/14 下午2点半`,
+/// RFC 3339 timestamps, and dates without a time (taken as midnight).
+///
+/// # Arguments
+///
+/// * `text` - The free-text time
+/// * `default_date` - Date to use when `text` only holds a time of day, e.g.
+///   the date of `time_begin` for a `time_end` of `16时00分`
+///
+/// # Returns
+///
+/// `None` when no date can be recognized
+pub fn parse_event_time(
+  text: &str,
+  default_date: Option<NaiveDate>,
+) -> Option<DateTime<FixedOffset>> {
+  let text = text.trim();
+  if text.is_empty() {
+    return None;
+  }
+  if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+    return Some(dt.with_timezone(&shanghai()));
+  }
+
+  let numbers = digit_runs(text);
+  let (date, time_numbers) = match numbers.first() {
+    Some((year, len)) if *len == 4 => {
+      let month = numbers.get(1)?.0;
+      let day = numbers.get(2)?.0;
+      (
+        NaiveDate::from_ymd_opt(*year as i32, month, day)?,
+        &numbers[3..],
+      )
+    }
+    // Without a year the text must be a bare time of day
+    Some(_) if numbers.len() <= 2 => (default_date?, &numbers[..]),
+    _ => return None,
+  };
+
+  let mut hour = time_numbers.first().map(|n| n.0);
+  let mut minute = time_numbers.get(1).map(|n| n.0).unwrap_or(0);
+  if text.contains('半') && time_numbers.len() == 1 {
+    minute = 30;
+  }
+
+  let lower = text.to_lowercase();
+  let is_pm = ["下午", "晚上", "傍晚", "pm", "p.m."]
+    .iter()
+    .any(|marker| lower.contains(marker));
+  let is_noon = lower.contains("中午");
+  if let Some(h) = hour {
+    if (is_pm && h < 12) || (is_noon && h < 11) {
+      hour = Some(h + 12);
+    }
+  }
+
+  let time = NaiveTime::from_hms_opt(hour.unwrap_or(0), minute, 0)?;
+  shanghai()
+    .from_local_datetime(&date.and_time(time))
+    .single()
+}
+
+/// Numbers in `text` with the count of digits each was written with
+fn digit_runs(text: &str) -> Vec<(u32, usize)> {
+  let mut runs = Vec::new();
+  let mut current: Option<(u32, usize)> = None;
+
+  for c in text.chars() {
+    // Full-width digits show up in Chinese text
+    let digit = c.to_digit(10).or_else(|| match c {
+      '０'..='９' => Some(c as u32 - '０' as u32),
+      _ => None,
+    });
+    match (digit, current.as_mut()) {
+      (Some(d), Some((value, len))) => {
+        *value = value.saturating_mul(10).saturating_add(d);
+        *len += 1;
+      }
+      (Some(d), None) => current = Some((d, 1)),
+      (None, _) => runs.extend(current.take()),
+    }
+  }
+  runs.extend(current);
+  runs
+}
@@ -1,72 +1,149 @@
+pub mod attachment;
+pub mod body;
+pub mod source;
+
+use crate::config::FilterConfig;
+use crate::data_sql::SyncState;
+use crate::event::{Event, SourceEmail};
 use chrono::{Duration, Local};
 use imap;
 use mailparse::parse_mail;
 use mailparse::MailHeaderMap;
 use native_tls::TlsConnector;
+use regex::Regex;
+use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct EmailTable {
   pub sender: String,
   pub subject: String,
   pub body: String,
+  /// Message-ID header, or a stand-in derived from sender, date and subject when missing
+  pub message_id: String,
+  /// Date header as RFC 3339 (UTC), or the raw header when it cannot be parsed
+  pub date: String,
+  /// Mailbox the email was read from, or the file or directory for local sources
+  pub mailbox: String,
+  /// IMAP UID within `mailbox`, if known
+  pub uid: Option<u32>,
+  /// Text of PDF and DOCX attachments, appended to the prompt input
+  pub attachment_text: String,
+  /// Events read from calendar invites, which need no model call
+  pub calendar_events: Vec<Event>,
+  /// Events cancelled by calendar invites, to remove from the database
+  pub cancelled_events: Vec<Event>,
 }
 
-/// Get emails
-///
-/// # Arguments
-///
-/// * `email_address`
-/// * `password`
-/// * `days_ago` - The history should be checked
-/// * `imap_server`
-///
-/// # Returns
-///
-/// The text response from the email
-pub async fn fetch_emails(
-  email_address: &str,
-  password: &str,
-  days_ago: u64,
-  imap_server: &str,
-) -> Vec<EmailTable> {
-  match inner_fetch_emails(email_address, password, days_ago, imap_server) {
-    Ok(emails) => emails,
-    Err(e) => {
-      eprintln!("Error: {}", e);
-      Vec::new()
+impl From<&EmailTable> for SourceEmail {
+  fn from(email: &EmailTable) -> Self {
+    SourceEmail {
+      message_id: email.message_id.clone(),
+      subject: email.subject.clone(),
+      date: email.date.clone(),
+      mailbox: email.mailbox.clone(),
+      uid: email.uid,
     }
   }
 }
 
-fn inner_fetch_emails(
+type ImapSession = imap::Session<native_tls::TlsStream<std::net::TcpStream>>;
+
+/// The only mailbox the tool reads
+pub const MAILBOX: &str = "INBOX";
+
+/// Key identifying an account in the `sync_state` table
+pub fn account_key(email_address: &str, imap_server: &str) -> String {
+  format!("{}@{}", email_address, imap_server)
+}
+
+fn connect(
   email_address: &str,
   password: &str,
-  days_ago: u64,
   imap_server: &str,
-) -> Result<Vec<EmailTable>, Box<dyn std::error::Error>> {
-  let mut email_tables = Vec::new();
-
+) -> Result<ImapSession, Box<dyn std::error::Error>> {
   let tls = TlsConnector::builder().build()?;
-  let client = imap::connect((imap_server, 993), imap_server, &tls).unwrap();
+  let client = imap::connect((imap_server, 993), imap_server, &tls)?;
 
-  let mut imap_session = client
+  let imap_session = client
     .login(email_address, password)
     .map_err(|(err, _client)| err)?;
+  Ok(imap_session)
+}
+
+/// An IMAP session kept open on `MAILBOX` to learn about new mail as it arrives
+pub struct IdleSession {
+  session: ImapSession,
+  supports_idle: bool,
+}
+
+impl IdleSession {
+  /// Log in and select `MAILBOX`
+  pub fn connect(
+    email_address: &str,
+    password: &str,
+    imap_server: &str,
+  ) -> Result<Self, Box<dyn std::error::Error>> {
+    let mut session = connect(email_address, password, imap_server)?;
+    let supports_idle = session.capabilities()?.has_str("IDLE");
+    session.select(MAILBOX)?;
+    Ok(Self {
+      session,
+      supports_idle,
+    })
+  }
+
+  /// Whether the server pushes changes, otherwise `wait` just sleeps
+  pub fn supports_idle(&self) -> bool {
+    self.supports_idle
+  }
 
-  imap_session.select("INBOX")?;
+  /// Block until the mailbox changes or `timeout` passes
+  ///
+  /// # Returns
+  ///
+  /// Whether the server reported a change; `false` means the wait timed out
+  /// and the caller should poll
+  pub fn wait(&mut self, timeout: std::time::Duration) -> Result<bool, Box<dyn std::error::Error>> {
+    if !self.supports_idle {
+      std::thread::sleep(timeout);
+      // Notice a dead connection now rather than on the next fetch
+      self.session.noop()?;
+      return Ok(false);
+    }
+    let outcome = self.session.idle()?.wait_with_timeout(timeout)?;
+    Ok(matches!(
+      outcome,
+      imap::extensions::idle::WaitOutcome::MailboxChanged
+    ))
+  }
+}
 
+fn since_criteria(days_ago: u64) -> String {
   let since_date = (Local::now() - Duration::days(days_ago as i64))
     .format("%d-%b-%Y")
     .to_string();
-  let search_criteria = format!("SINCE \"{}\"", since_date);
+  format!("SINCE \"{}\"", since_date)
+}
 
-  let messages = imap_session.search(search_criteria)?;
+fn inner_fetch_emails(
+  email_address: &str,
+  password: &str,
+  days_ago: u64,
+  imap_server: &str,
+  filter: &EmailFilter,
+) -> Result<Vec<EmailTable>, Box<dyn std::error::Error>> {
+  let mut email_tables = Vec::new();
+
+  let mut imap_session = connect(email_address, password, imap_server)?;
+  imap_session.select(MAILBOX)?;
+
+  let messages = imap_session.search(since_criteria(days_ago))?;
 
   for num in messages.iter() {
-    if let Ok(msg) = imap_session.fetch(num.to_string(), "RFC822") {
-      if let Some(msg_body) = msg.iter().next().and_then(|m| m.body()) {
-        if let Ok(parsed) = parse_mail(msg_body) {
-          process_email(&parsed, &mut email_tables);
+    if let Ok(msg) = imap_session.fetch(num.to_string(), "(UID RFC822)") {
+      if let Some(m) = msg.iter().next() {
+        if let Some(parsed) = m.body().and_then(|body| parse_mail(body).ok()) {
+          process_email(&parsed, MAILBOX, m.uid, filter, &mut email_tables);
         }
       }
     }
@@ -75,59 +152,392 @@ fn inner_fetch_emails(
   Ok(email_tables)
 }
 
-fn process_email(parsed: &mailparse::ParsedMail, results: &mut Vec<EmailTable>) {
-  let sender = parsed.headers.get_first_value("From").unwrap_or_default();
-  let sender = extract_email(&sender);
-  if !is_tsinghua_sender(&sender) {
+/// Get only the emails that arrived since the last sync
+///
+/// # Arguments
+///
+/// * `email_address`
+/// * `password`
+/// * `imap_server`
+/// * `previous` - State saved by the last run, `None` on the first run
+/// * `days_ago` - History to fetch when there is no usable previous state
+/// * `filter` - Emails it rejects are skipped, but still count as synced
+///
+/// # Returns
+///
+/// The new emails, the messages that could not be fetched, and the state past
+/// all of them. When the mailbox's UIDVALIDITY no longer matches `previous`,
+/// the saved UIDs are meaningless, so the sync starts over from `days_ago`.
+pub async fn fetch_emails_incremental(
+  email_address: &str,
+  password: &str,
+  imap_server: &str,
+  previous: Option<&SyncState>,
+  days_ago: u64,
+  filter: &EmailFilter,
+) -> Result<IncrementalFetch, Box<dyn std::error::Error>> {
+  let mut imap_session = connect(email_address, password, imap_server)?;
+  let mailbox = imap_session.select(MAILBOX)?;
+  let uid_validity = mailbox.uid_validity.unwrap_or(0);
+
+  let resume_from = match previous {
+    Some(state) if state.uid_validity == uid_validity => Some(state.last_uid),
+    Some(state) => {
+      eprintln!(
+        "UIDVALIDITY of {} changed ({} -> {}), resyncing the last {} days",
+        MAILBOX, state.uid_validity, uid_validity, days_ago
+      );
+      None
+    }
+    None => None,
+  };
+
+  let mut uids: Vec<u32> = match resume_from {
+    // `n:*` always matches the highest UID, even when it is below n, so filter again
+    Some(last_uid) => imap_session
+      .uid_search(format!("UID {}:*", last_uid + 1))?
+      .into_iter()
+      .filter(|uid| *uid > last_uid)
+      .collect(),
+    None => imap_session
+      .uid_search(since_criteria(days_ago))?
+      .into_iter()
+      .collect(),
+  };
+  uids.sort_unstable();
+
+  let mut email_tables = Vec::new();
+  let mut failed = Vec::new();
+  for uid in &uids {
+    let msg = match imap_session.uid_fetch(uid.to_string(), "RFC822") {
+      Ok(msg) => msg,
+      Err(e) => {
+        eprintln!("✗ Could not fetch message {}: {}", uid, e);
+        failed.push((*uid, e.to_string()));
+        continue;
+      }
+    };
+    if let Some(msg_body) = msg.iter().next().and_then(|m| m.body()) {
+      if let Ok(parsed) = parse_mail(msg_body) {
+        process_email(&parsed, MAILBOX, Some(*uid), filter, &mut email_tables);
+      }
+    }
+  }
+  imap_session.logout().ok();
+
+  let last_uid = uids
+    .last()
+    .copied()
+    .or(resume_from)
+    .unwrap_or_else(|| mailbox.uid_next.unwrap_or(1).saturating_sub(1));
+
+  let state = SyncState {
+    account: account_key(email_address, imap_server),
+    mailbox: MAILBOX.to_string(),
+    uid_validity,
+    last_uid,
+  };
+  Ok(IncrementalFetch {
+    emails: email_tables,
+    failed,
+    state,
+  })
+}
+
+/// What `fetch_emails_incremental` got from the mailbox
+pub struct IncrementalFetch {
+  pub emails: Vec<EmailTable>,
+  /// UID and error of every message that could not be fetched
+  pub failed: Vec<(u32, String)>,
+  /// Sync position past every message seen, failed ones included; pass it
+  /// through `resume_uid` before saving it when anything failed
+  pub state: SyncState,
+}
+
+/// Where the next incremental run starts when some messages failed.
+///
+/// The position stays just below the lowest message that failed fewer than
+/// `max_attempts` times, so it is fetched and summarized again. A message that
+/// has failed `max_attempts` times is skipped for good, so that one broken
+/// message neither blocks later mail nor is paid for on every run.
+///
+/// # Arguments
+///
+/// * `last_uid` - Highest UID the run got through
+/// * `failures` - Every failed UID with how often it has failed, this run included
+/// * `max_attempts` - Failures after which a message is skipped
+///
+/// # Returns
+///
+/// The `last_uid` to save
+pub fn resume_uid(last_uid: u32, failures: &[(u32, u32)], max_attempts: u32) -> u32 {
+  failures
+    .iter()
+    .filter(|(_, attempts)| *attempts < max_attempts)
+    .map(|(uid, _)| uid.saturating_sub(1))
+    .fold(last_uid, u32::min)
+}
+
+/// Delay before reconnecting to the mail server, doubling on every failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+  initial: std::time::Duration,
+  max: std::time::Duration,
+  current: std::time::Duration,
+}
+
+impl Backoff {
+  /// Start at `initial`, never waiting longer than `max`
+  pub fn new(initial: std::time::Duration, max: std::time::Duration) -> Self {
+    Self {
+      initial,
+      max,
+      current: initial.min(max),
+    }
+  }
+
+  /// The delay to wait now; the next one is twice as long, up to `max`
+  pub fn next_delay(&mut self) -> std::time::Duration {
+    let delay = self.current;
+    self.current = self
+      .current
+      .checked_mul(2)
+      .unwrap_or(self.max)
+      .min(self.max);
+    delay
+  }
+
+  /// Start over from `initial` after a successful connection
+  pub fn reset(&mut self) {
+    self.current = self.initial.min(self.max);
+  }
+}
+
+fn process_email(
+  parsed: &mailparse::ParsedMail,
+  mailbox: &str,
+  uid: Option<u32>,
+  filter: &EmailFilter,
+  results: &mut Vec<EmailTable>,
+) {
+  if !filter.accepts(parsed) {
     return;
   }
 
+  let sender = parsed.headers.get_first_value("From").unwrap_or_default();
+  let sender = extract_email(&sender);
   let subject = parsed
     .headers
     .get_first_value("Subject")
     .unwrap_or_default();
 
-  let body = extract_body(parsed);
+  let date = parsed.headers.get_first_value("Date").unwrap_or_default();
+  let date = mailparse::dateparse(&date)
+    .ok()
+    .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+    .map(|dt| dt.to_rfc3339())
+    .unwrap_or(date);
+
+  let message_id = parsed
+    .headers
+    .get_first_value("Message-ID")
+    .map(|id| id.trim().to_string())
+    .filter(|id| !id.is_empty())
+    .unwrap_or_else(|| format!("<{}/{}/{}>", sender, date, subject));
 
-  results.push(EmailTable {
+  let body = extract_body(parsed, body::is_forward(&subject));
+  let attachments = attachment::extract_attachments(parsed);
+
+  let mut email = EmailTable {
     sender,
     subject,
     body,
-  });
+    message_id,
+    date,
+    mailbox: mailbox.to_string(),
+    uid,
+    attachment_text: attachments.text,
+    calendar_events: Vec::new(),
+    cancelled_events: Vec::new(),
+  };
+  let source = SourceEmail::from(&email);
+  let from_invite = |event| Event {
+    sender: email.sender.clone(),
+    source: Some(source.clone()),
+    ..event
+  };
+  email.calendar_events = attachments
+    .calendar_events
+    .into_iter()
+    .map(from_invite)
+    .collect();
+  email.cancelled_events = attachments
+    .cancelled_events
+    .into_iter()
+    .map(from_invite)
+    .collect();
+  results.push(email);
 }
 
 pub fn extract_email(s: &str) -> String {
   s.split('<')
-    .last()
+    .next_back()
     .and_then(|s| s.split('>').next())
     .unwrap_or(s)
     .trim()
     .to_lowercase()
 }
 
-pub fn is_tsinghua_sender(sender: &str) -> bool {
-  sender.contains("mail.tsinghua") || sender.contains("mails.tsinghua")
+/// Compiled form of `FilterConfig`, see there for the matching rules
+#[derive(Debug, Clone)]
+pub struct EmailFilter {
+  allow_senders: Vec<String>,
+  deny_senders: Vec<String>,
+  allow_subjects: Vec<Regex>,
+  deny_subjects: Vec<Regex>,
+  allow_headers: Vec<(String, Regex)>,
+  deny_headers: Vec<(String, Regex)>,
 }
 
-fn extract_body(parsed: &mailparse::ParsedMail) -> String {
-  let mut body = String::new();
+impl EmailFilter {
+  /// Compile the rules of the `[filters]` table
+  ///
+  /// # Returns
+  ///
+  /// An error naming the offending pattern if a regex does not compile
+  pub fn from_config(config: &FilterConfig) -> Result<Self, Box<dyn std::error::Error>> {
+    fn senders(entries: &[String]) -> Vec<String> {
+      entries
+        .iter()
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+    }
+    fn compile(pattern: &str) -> Result<Regex, Box<dyn std::error::Error>> {
+      Regex::new(pattern).map_err(|e| format!("Invalid filter pattern '{}': {}", pattern, e).into())
+    }
+    fn regexes(patterns: &[String]) -> Result<Vec<Regex>, Box<dyn std::error::Error>> {
+      patterns.iter().map(|p| compile(p)).collect()
+    }
+    fn header_regexes(
+      rules: &HashMap<String, String>,
+    ) -> Result<Vec<(String, Regex)>, Box<dyn std::error::Error>> {
+      rules
+        .iter()
+        .map(|(name, pattern)| Ok((name.clone(), compile(pattern)?)))
+        .collect()
+    }
+
+    Ok(Self {
+      allow_senders: senders(&config.allow_senders),
+      deny_senders: senders(&config.deny_senders),
+      allow_subjects: regexes(&config.allow_subjects)?,
+      deny_subjects: regexes(&config.deny_subjects)?,
+      allow_headers: header_regexes(&config.allow_headers)?,
+      deny_headers: header_regexes(&config.deny_headers)?,
+    })
+  }
+
+  /// Whether an email passes the filter
+  pub fn accepts(&self, parsed: &mailparse::ParsedMail) -> bool {
+    let sender = extract_email(&parsed.headers.get_first_value("From").unwrap_or_default());
+    let subject = parsed
+      .headers
+      .get_first_value("Subject")
+      .unwrap_or_default();
+    let header_matches = |rules: &[(String, Regex)]| {
+      rules.iter().any(|(name, regex)| {
+        parsed
+          .headers
+          .get_all_values(name)
+          .iter()
+          .any(|value| regex.is_match(value))
+      })
+    };
 
-  fn walk_part(part: &mailparse::ParsedMail) -> Option<String> {
-    if part.ctype.mimetype.starts_with("text/") {
-      return part.get_body().ok();
+    let denied = self
+      .deny_senders
+      .iter()
+      .any(|rule| sender_matches(rule, &sender))
+      || self
+        .deny_subjects
+        .iter()
+        .any(|regex| regex.is_match(&subject))
+      || header_matches(&self.deny_headers);
+    if denied {
+      return false;
     }
 
-    for subpart in &part.subparts {
-      if let Some(body) = walk_part(subpart) {
-        return Some(body);
+    (self.allow_senders.is_empty()
+      || self
+        .allow_senders
+        .iter()
+        .any(|rule| sender_matches(rule, &sender)))
+      && (self.allow_subjects.is_empty()
+        || self
+          .allow_subjects
+          .iter()
+          .any(|regex| regex.is_match(&subject)))
+      && (self.allow_headers.is_empty() || header_matches(&self.allow_headers))
+  }
+}
+
+/// Match a lowercased sender address against an address or domain rule
+fn sender_matches(rule: &str, sender: &str) -> bool {
+  if rule.contains('@') && !rule.starts_with('@') {
+    return sender == rule;
+  }
+  let domain = rule.trim_start_matches('@');
+  let Some((_, sender_domain)) = sender.rsplit_once('@') else {
+    return false;
+  };
+  sender_domain == domain || sender_domain.ends_with(&format!(".{}", domain))
+}
+
+/// Readable text of an email.
+///
+/// `text/plain` is preferred over `text/html`, which is only converted to text
+/// when there is no plain alternative. Quoted replies, signatures and legal
+/// footers are stripped either way, while forwarded content is kept.
+fn extract_body(parsed: &mailparse::ParsedMail, forwarded: bool) -> String {
+  #[derive(Default)]
+  struct Bodies {
+    plain: Option<String>,
+    html: Option<String>,
+    other: Option<String>,
+  }
+
+  fn walk_part(part: &mailparse::ParsedMail, bodies: &mut Bodies) {
+    if attachment::is_attachment(part) {
+      return;
+    }
+    let slot = match part.ctype.mimetype.as_str() {
+      "text/plain" => &mut bodies.plain,
+      "text/html" => &mut bodies.html,
+      mimetype if mimetype.starts_with("text/") => &mut bodies.other,
+      _ => {
+        for subpart in &part.subparts {
+          walk_part(subpart, bodies);
+        }
+        return;
       }
+    };
+    if slot.is_none() {
+      *slot = part.get_body().ok();
     }
-    None
   }
 
-  if let Some(content) = walk_part(parsed) {
-    body = content;
-  }
+  let mut bodies = Bodies::default();
+  walk_part(parsed, &mut bodies);
 
-  body
+  let text = match bodies {
+    Bodies {
+      plain: Some(plain), ..
+    } if !plain.trim().is_empty() => plain,
+    Bodies {
+      html: Some(html), ..
+    } => body::html_to_text(&html),
+    Bodies { other, plain, .. } => other.or(plain).unwrap_or_default(),
+  };
+  body::clean_body(&text, forwarded)
 }
@@ -0,0 +1,423 @@
+use crate::data_sql::time::shanghai;
+use crate::event::Event;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use mailparse::ParsedMail;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+/// Longest text kept from one document attachment, in characters
+pub const MAX_ATTACHMENT_CHARS: usize = 8000;
+
+/// Time zone names meaning UTC+8, including the Windows name Outlook uses
+const UTC8_ZONES: &[&str] = &[
+  "Asia/Shanghai",
+  "Asia/Chongqing",
+  "Asia/Harbin",
+  "Asia/Hong_Kong",
+  "Asia/Macau",
+  "Asia/Taipei",
+  "Asia/Singapore",
+  "PRC",
+  "China Standard Time",
+];
+
+/// What could be read from the attachments of one email
+#[derive(Debug, Clone, Default)]
+pub struct Attachments {
+  /// Events from `text/calendar` invites, ready to store without the model
+  pub calendar_events: Vec<Event>,
+  /// Events that `text/calendar` invites cancel
+  pub cancelled_events: Vec<Event>,
+  /// Text of PDF and DOCX attachments, and of invites that yielded no event,
+  /// each headed by its file name
+  pub text: String,
+}
+
+/// Collect calendar invites and document text from every part of an email
+pub fn extract_attachments(parsed: &ParsedMail) -> Attachments {
+  let mut attachments = Attachments::default();
+  walk_attachments(parsed, &mut attachments);
+  attachments
+}
+
+fn walk_attachments(part: &ParsedMail, attachments: &mut Attachments) {
+  if !part.subparts.is_empty() {
+    for subpart in &part.subparts {
+      walk_attachments(subpart, attachments);
+    }
+    return;
+  }
+
+  let name = file_name(part).unwrap_or_default();
+  let extracted = match AttachmentKind::of(&part.ctype.mimetype, &name) {
+    Some(AttachmentKind::Calendar) => {
+      let Ok(text) = part.get_body() else {
+        return;
+      };
+      let invite = parse_ics(&text);
+      if invite.events.is_empty() && invite.cancelled.is_empty() {
+        Ok(text)
+      } else {
+        attachments.calendar_events.extend(invite.events);
+        attachments.cancelled_events.extend(invite.cancelled);
+        return;
+      }
+    }
+    Some(AttachmentKind::Pdf) => part
+      .get_body_raw()
+      .map_err(Into::into)
+      .and_then(|bytes| pdf_text(&bytes)),
+    Some(AttachmentKind::Docx) => part
+      .get_body_raw()
+      .map_err(Into::into)
+      .and_then(|bytes| docx_text(&bytes)),
+    None => return,
+  };
+
+  // Inline invites often have no file name
+  let name = if name.is_empty() {
+    part.ctype.mimetype.clone()
+  } else {
+    name
+  };
+  match extracted {
+    Ok(text) if !text.trim().is_empty() => {
+      let text: String = text.trim().chars().take(MAX_ATTACHMENT_CHARS).collect();
+      if !attachments.text.is_empty() {
+        attachments.text.push_str("\n\n");
+      }
+      attachments.text.push_str(&format!("[{}]\n{}", name, text));
+    }
+    Ok(_) => {}
+    Err(e) => eprintln!("Skipping attachment '{}': {}", name, e),
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttachmentKind {
+  Calendar,
+  Pdf,
+  Docx,
+}
+
+impl AttachmentKind {
+  /// Recognize an attachment by MIME type, or by extension for `application/octet-stream`
+  fn of(mimetype: &str, name: &str) -> Option<Self> {
+    let name = name.to_lowercase();
+    match mimetype {
+      "text/calendar" | "application/ics" => Some(Self::Calendar),
+      "application/pdf" => Some(Self::Pdf),
+      "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => Some(Self::Docx),
+      _ if name.ends_with(".ics") => Some(Self::Calendar),
+      _ if name.ends_with(".pdf") => Some(Self::Pdf),
+      _ if name.ends_with(".docx") => Some(Self::Docx),
+      _ => None,
+    }
+  }
+}
+
+/// File name from Content-Disposition, falling back to the Content-Type `name`
+fn file_name(part: &ParsedMail) -> Option<String> {
+  let disposition = part.get_content_disposition();
+  disposition
+    .params
+    .get("filename")
+    .or_else(|| part.ctype.params.get("name"))
+    .cloned()
+}
+
+/// Whether a part is an attachment rather than a body alternative
+pub fn is_attachment(part: &ParsedMail) -> bool {
+  part.get_content_disposition().disposition == mailparse::DispositionType::Attachment
+    || AttachmentKind::of(&part.ctype.mimetype, "") == Some(AttachmentKind::Calendar)
+}
+
+/// Extract the text layer of a PDF
+pub fn pdf_text(bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+  // pdf-extract panics on some malformed files instead of returning an error
+  std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(bytes))
+    .map_err(|_| "PDF parser panicked")?
+    .map_err(|e| e.to_string().into())
+}
+
+/// Extract the paragraphs of a Word document
+pub fn docx_text(bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+  let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+  let mut xml = String::new();
+  archive
+    .by_name("word/document.xml")?
+    .read_to_string(&mut xml)?;
+  Ok(document_xml_text(&xml))
+}
+
+/// Text content of WordprocessingML, one line per paragraph
+fn document_xml_text(xml: &str) -> String {
+  let mut text = String::new();
+  let mut rest = xml;
+  while let Some(start) = rest.find('<') {
+    text.push_str(&unescape_xml(&rest[..start]));
+    let Some(end) = rest[start..].find('>') else {
+      break;
+    };
+    let tag = &rest[start + 1..start + end];
+    let tag_name = tag.split([' ', '/']).find(|s| !s.is_empty()).unwrap_or("");
+    match tag_name {
+      "w:p" if tag.starts_with('/') => text.push('\n'),
+      "w:tab" => text.push('\t'),
+      "w:br" | "w:cr" => text.push('\n'),
+      _ => {}
+    }
+    rest = &rest[start + end + 1..];
+  }
+  text
+}
+
+fn unescape_xml(s: &str) -> String {
+  s.replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&amp;", "&")
+}
+
+/// One `NAME;PARAM=VALUE:value` content line of an iCalendar file
+struct ContentLine {
+  name: String,
+  params: HashMap<String, String>,
+  value: String,
+}
+
+/// Undo line folding and split an iCalendar file into content lines
+fn content_lines(ics: &str) -> Vec<ContentLine> {
+  let mut unfolded: Vec<String> = Vec::new();
+  for line in ics.lines() {
+    match (line.strip_prefix([' ', '\t']), unfolded.last_mut()) {
+      (Some(continuation), Some(previous)) => previous.push_str(continuation),
+      _ => unfolded.push(line.to_string()),
+    }
+  }
+
+  unfolded
+    .iter()
+    .filter_map(|line| {
+      // The value starts at the first colon outside a quoted parameter value
+      let mut in_quotes = false;
+      let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+          in_quotes = !in_quotes;
+          None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+      })?;
+      let mut head = line[..colon].split(';');
+      let name = head.next()?.trim().to_uppercase();
+      let params = head
+        .filter_map(|param| param.split_once('='))
+        .map(|(k, v)| (k.trim().to_uppercase(), v.trim_matches('"').to_string()))
+        .collect();
+      Some(ContentLine {
+        name,
+        params,
+        value: line[colon + 1..].to_string(),
+      })
+    })
+    .collect()
+}
+
+/// Undo iCalendar TEXT escaping (`\n`, `\,`, `\;`, `\\`)
+fn unescape_ics(value: &str) -> String {
+  let mut out = String::new();
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      out.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') | Some('N') => out.push('\n'),
+      Some(other) => out.push(other),
+      None => {}
+    }
+  }
+  out
+}
+
+/// Events of an iCalendar invite, see `parse_ics`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Invite {
+  /// Events to store
+  pub events: Vec<Event>,
+  /// Events called off by a `METHOD:CANCEL` invite or `STATUS:CANCELLED`
+  pub cancelled: Vec<Event>,
+}
+
+/// Parse the VEVENTs of an iCalendar invite into events.
+///
+/// Cancelled events are returned apart, so the caller can drop what was
+/// stored for them instead of storing them as live. Times are converted to Asia/Shanghai and stored
+/// in `time_begin_iso` when their zone is UTC, an IANA name, a known UTC+8
+/// name or a VTIMEZONE without DST. Events in any other zone are left out
+/// rather than guessed, so an invite with only such events goes to the model.
+pub fn parse_ics(ics: &str) -> Invite {
+  let lines = content_lines(ics);
+  let method_cancel = lines
+    .iter()
+    .any(|line| line.name == "METHOD" && line.value.trim().eq_ignore_ascii_case("CANCEL"));
+
+  let zones = fixed_zones(&lines);
+  let mut invite = Invite::default();
+  let mut current: Option<Vec<&ContentLine>> = None;
+  for line in &lines {
+    match (line.name.as_str(), line.value.trim()) {
+      ("BEGIN", "VEVENT") => current = Some(Vec::new()),
+      ("END", "VEVENT") => {
+        let Some(props) = current.take() else {
+          continue;
+        };
+        let cancelled = method_cancel
+          || props.iter().any(|line| {
+            line.name == "STATUS" && line.value.trim().eq_ignore_ascii_case("CANCELLED")
+          });
+        if let Some(event) = vevent_to_event(&props, &zones) {
+          if cancelled {
+            invite.cancelled.push(event);
+          } else {
+            invite.events.push(event);
+          }
+        }
+      }
+      _ => {
+        if let Some(props) = current.as_mut() {
+          props.push(line);
+        }
+      }
+    }
+  }
+  invite
+}
+
+/// Offsets of the VTIMEZONEs whose observances all share one UTC offset
+fn fixed_zones(lines: &[ContentLine]) -> HashMap<String, FixedOffset> {
+  let mut zones = HashMap::new();
+  let mut current: Option<(Option<String>, Vec<Option<FixedOffset>>)> = None;
+  for line in lines {
+    match (line.name.as_str(), line.value.trim(), current.as_mut()) {
+      ("BEGIN", "VTIMEZONE", _) => current = Some((None, Vec::new())),
+      ("END", "VTIMEZONE", _) => {
+        if let Some((Some(id), offsets)) = current.take() {
+          if let Some(Some(first)) = offsets.first() {
+            if offsets.iter().all(|offset| *offset == Some(*first)) {
+              zones.insert(id, *first);
+            }
+          }
+        }
+      }
+      ("TZID", id, Some((zone_id, _))) => *zone_id = Some(id.to_string()),
+      ("TZOFFSETTO", offset, Some((_, offsets))) => offsets.push(utc_offset(offset)),
+      _ => {}
+    }
+  }
+  zones
+}
+
+/// Parse a UTC offset such as `+0800`, `-0500` or `+053000`
+fn utc_offset(value: &str) -> Option<FixedOffset> {
+  let (sign, digits) = match value.split_at_checked(1)? {
+    ("+", digits) => (1, digits),
+    ("-", digits) => (-1, digits),
+    _ => return None,
+  };
+  if !matches!(digits.len(), 4 | 6) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  let field = |range: std::ops::Range<usize>| digits.get(range).unwrap_or("0").parse::<i32>();
+  let seconds = field(0..2).ok()? * 3600 + field(2..4).ok()? * 60 + field(4..6).ok()?;
+  FixedOffset::east_opt(sign * seconds)
+}
+
+fn vevent_to_event(props: &[&ContentLine], zones: &HashMap<String, FixedOffset>) -> Option<Event> {
+  let get = |name: &str| props.iter().find(|line| line.name == name);
+  let text = |name: &str| get(name).map(|line| unescape_ics(&line.value).trim().to_string());
+
+  let summary = text("SUMMARY").filter(|s| !s.is_empty())?;
+  let begin = get("DTSTART").and_then(|line| ics_time(line, zones))?;
+  let end = get("DTEND").and_then(|line| ics_time(line, zones));
+
+  Some(Event {
+    event: summary,
+    time_begin: begin.text,
+    time_end: end.as_ref().map(|t| t.text.clone()).unwrap_or_default(),
+    position: text("LOCATION").unwrap_or_default(),
+    r#abstract: text("DESCRIPTION").unwrap_or_default(),
+    time_begin_iso: Some(begin.iso),
+    time_end_iso: end.map(|t| t.iso),
+    ..Default::default()
+  })
+}
+
+/// A DTSTART/DTEND value as Shanghai event text and RFC 3339
+struct IcsTime {
+  text: String,
+  iso: String,
+}
+
+fn ics_time(line: &ContentLine, zones: &HashMap<String, FixedOffset>) -> Option<IcsTime> {
+  let value = line.value.trim();
+
+  if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+    let midnight = shanghai().from_local_datetime(&date.and_hms_opt(0, 0, 0)?);
+    return Some(IcsTime {
+      text: date.format("%Y年%m月%d日").to_string(),
+      iso: midnight.single()?.to_rfc3339(),
+    });
+  }
+
+  let local = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()?;
+  let utc = if value.ends_with('Z') {
+    Utc.from_utc_datetime(&local)
+  } else {
+    match line.params.get("TZID") {
+      Some(zone) => zone_to_utc(zone, &local, zones)?,
+      // Floating times in an invite sent to this tool are local times
+      None => shanghai().from_local_datetime(&local).single()?.to_utc(),
+    }
+  };
+
+  let time = utc.with_timezone(&shanghai());
+  Some(IcsTime {
+    text: time.format("%Y年%m月%d日 %H时%M分").to_string(),
+    iso: time.to_rfc3339(),
+  })
+}
+
+/// Convert a wall-clock time in the zone named by a TZID parameter to UTC
+///
+/// # Arguments
+/// * `zone` - The TZID: a UTC+8 name, an IANA name or the id of a VTIMEZONE
+/// * `local` - The wall-clock time in that zone
+/// * `zones` - Offsets of the invite's VTIMEZONEs without DST
+///
+/// # Returns
+/// `None` when the zone's rules are unknown or the time does not exist there
+fn zone_to_utc(
+  zone: &str,
+  local: &NaiveDateTime,
+  zones: &HashMap<String, FixedOffset>,
+) -> Option<DateTime<Utc>> {
+  if UTC8_ZONES.contains(&zone) {
+    return Some(shanghai().from_local_datetime(local).single()?.to_utc());
+  }
+  if let Ok(tz) = zone.parse::<Tz>() {
+    return Some(tz.from_local_datetime(local).earliest()?.to_utc());
+  }
+  Some(
+    zones
+      .get(zone)?
+      .from_local_datetime(local)
+      .single()?
+      .to_utc(),
+  )
+}
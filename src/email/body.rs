@@ -0,0 +1,257 @@
+/// Elements whose content is never readable text
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template", "title"];
+
+/// Elements that start a new line
+const BLOCK_ELEMENTS: &[&str] = &[
+  "address",
+  "article",
+  "blockquote",
+  "br",
+  "dd",
+  "div",
+  "dl",
+  "dt",
+  "footer",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "header",
+  "hr",
+  "li",
+  "ol",
+  "p",
+  "pre",
+  "section",
+  "table",
+  "tr",
+  "ul",
+];
+
+/// Lines that introduce the quoted original in a reply, or in a forward from
+/// clients that use the same separator for both
+const REPLY_SEPARATORS: &[&str] = &[
+  "-----original message-----",
+  "-----原始邮件-----",
+  "------------------ 原始邮件 ------------------",
+];
+
+/// Subject prefixes of forwarded emails
+const FORWARD_PREFIXES: &[&str] = &["fw:", "fwd:", "fw：", "fwd：", "转发:", "转发："];
+
+/// Beginnings of legal and mailing-list footers
+const FOOTER_MARKERS: &[&str] = &[
+  "this email and any",
+  "this e-mail and any",
+  "this message and any",
+  "confidentiality notice",
+  "disclaimer:",
+  "免责声明",
+  "本邮件及其附件",
+  "此邮件及其附件",
+  "to unsubscribe",
+];
+
+/// Convert an HTML body to plain text.
+///
+/// Scripts, styles and the document head are dropped, block elements become
+/// line breaks, list items get a `- ` bullet and entities are decoded. Tags
+/// such as tracking images simply disappear.
+pub fn html_to_text(html: &str) -> String {
+  let mut text = String::new();
+  let mut rest = html;
+
+  while let Some(start) = rest.find('<') {
+    push_collapsed(&mut text, &decode_entities(&rest[..start]));
+    rest = &rest[start..];
+
+    if let Some(comment) = rest.strip_prefix("<!--") {
+      rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+      continue;
+    }
+    let Some(end) = rest.find('>') else {
+      rest = "";
+      break;
+    };
+    let tag = &rest[1..end];
+    rest = &rest[end + 1..];
+
+    let closing = tag.starts_with('/');
+    let name = tag
+      .trim_start_matches('/')
+      .split(|c: char| c.is_whitespace() || c == '/')
+      .next()
+      .unwrap_or("")
+      .to_lowercase();
+
+    if !closing && SKIPPED_ELEMENTS.contains(&name.as_str()) && !tag.ends_with('/') {
+      let close = format!("</{}", name);
+      rest = rest
+        .to_ascii_lowercase()
+        .find(&close)
+        .and_then(|i| rest[i..].find('>').map(|j| &rest[i + j + 1..]))
+        .unwrap_or("");
+      continue;
+    }
+    match name.as_str() {
+      "li" if !closing => {
+        push_line_break(&mut text);
+        text.push_str("- ");
+      }
+      "td" | "th" if closing => text.push(' '),
+      _ if BLOCK_ELEMENTS.contains(&name.as_str()) => push_line_break(&mut text),
+      _ => {}
+    }
+  }
+  push_collapsed(&mut text, &decode_entities(rest));
+
+  tidy_lines(&text)
+}
+
+/// End the current line, unless nothing has been written on it yet
+fn push_line_break(out: &mut String) {
+  out.truncate(out.trim_end_matches(' ').len());
+  if !out.is_empty() && !out.ends_with('\n') {
+    out.push('\n');
+  }
+}
+
+/// Append text with HTML whitespace rules: any run of whitespace is one space
+fn push_collapsed(out: &mut String, text: &str) {
+  for c in text.chars() {
+    if c.is_whitespace() && c != '\u{a0}' {
+      if !out.ends_with([' ', '\n']) && !out.is_empty() {
+        out.push(' ');
+      }
+    } else {
+      out.push(if c == '\u{a0}' { ' ' } else { c });
+    }
+  }
+}
+
+fn decode_entities(text: &str) -> String {
+  let mut out = String::new();
+  let mut rest = text;
+  while let Some(start) = rest.find('&') {
+    out.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let decoded = rest[1..]
+      .find(';')
+      .filter(|&end| end <= 10)
+      .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+    match decoded {
+      Some((c, len)) => {
+        out.push(c);
+        rest = &rest[len..];
+      }
+      None => {
+        out.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  out.push_str(rest);
+  out
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+  if let Some(number) = name.strip_prefix('#') {
+    let code = match number.strip_prefix(['x', 'X']) {
+      Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+      None => number.parse().ok()?,
+    };
+    return char::from_u32(code);
+  }
+  Some(match name {
+    "nbsp" => '\u{a0}',
+    "amp" => '&',
+    "lt" => '<',
+    "gt" => '>',
+    "quot" => '"',
+    "apos" => '\'',
+    "mdash" => '—',
+    "ndash" => '–',
+    "hellip" => '…',
+    "middot" => '·',
+    "lsquo" => '‘',
+    "rsquo" => '’',
+    "ldquo" => '“',
+    "rdquo" => '”',
+    "copy" => '©',
+    "reg" => '®',
+    "times" => '×',
+    _ => return None,
+  })
+}
+
+/// Trim every line and keep at most one blank line in a row
+fn tidy_lines(text: &str) -> String {
+  let mut out: Vec<&str> = Vec::new();
+  for line in text.lines().map(str::trim) {
+    if line.is_empty() && out.last().is_none_or(|last| last.is_empty()) {
+      continue;
+    }
+    out.push(line);
+  }
+  while out.last().is_some_and(|last| last.is_empty()) {
+    out.pop();
+  }
+  out.join("\n")
+}
+
+/// Whether a subject marks its email as forwarded (`Fwd:`, `FW:`, `转发：`)
+pub fn is_forward(subject: &str) -> bool {
+  let lower = subject.trim_start().to_lowercase();
+  FORWARD_PREFIXES
+    .iter()
+    .any(|prefix| lower.starts_with(prefix))
+}
+
+/// Remove the parts of a plain-text body that carry no event information.
+///
+/// Everything from a signature delimiter (`-- `), a reply header
+/// (`On ... wrote:`, `在 ... 写道：`, `-----Original Message-----`, an Outlook
+/// `____` rule followed by `From:`) or a legal footer onward is cut, and
+/// `>`-quoted lines are dropped. Forwarded content is the announcement itself,
+/// so Gmail's `Forwarded message` separator never cuts, and in a `forwarded`
+/// email neither do the Outlook separators, which forwards share with replies.
+///
+/// # Arguments
+/// * `text` - The plain-text body
+/// * `forwarded` - Whether the email is a forward, see [`is_forward`]
+pub fn clean_body(text: &str, forwarded: bool) -> String {
+  let lines: Vec<&str> = text.lines().collect();
+  let mut kept = Vec::new();
+
+  for (i, line) in lines.iter().enumerate() {
+    let trimmed = line.trim();
+    let lower = trimmed.to_lowercase();
+
+    let is_signature = line.trim_end() == "--";
+    let is_outlook_header = REPLY_SEPARATORS.contains(&lower.as_str())
+      || (trimmed.len() >= 20
+        && trimmed.chars().all(|c| c == '_')
+        && lines[i + 1..]
+          .iter()
+          .map(|next| next.trim())
+          .find(|next| !next.is_empty())
+          .is_some_and(|next| next.starts_with("From:") || next.starts_with("发件人")));
+    let is_reply_header = (lower.starts_with("on ") && lower.ends_with("wrote:"))
+      || (trimmed.starts_with('在') && (trimmed.ends_with("写道：") || trimmed.ends_with("写道:")))
+      || (is_outlook_header && !forwarded);
+    let is_footer = FOOTER_MARKERS
+      .iter()
+      .any(|marker| lower.starts_with(marker));
+    if is_signature || is_reply_header || is_footer {
+      break;
+    }
+
+    if !trimmed.starts_with('>') {
+      kept.push(*line);
+    }
+  }
+
+  tidy_lines(&kept.join("\n"))
+}
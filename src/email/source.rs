@@ -0,0 +1,249 @@
+use super::{inner_fetch_emails, process_email, EmailFilter, EmailTable};
+use chrono::{DateTime, Duration, Utc};
+use mailparse::{dateparse, parse_headers, parse_mail, MailHeaderMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where `query` reads emails from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceKind {
+  /// IMAP over TLS on port 993
+  #[default]
+  Imap,
+  /// A Maildir directory (`cur/` and `new/`)
+  Maildir,
+  /// A single mbox file
+  Mbox,
+  /// A directory of `.eml` files
+  Eml,
+}
+
+/// A place emails can be read from.
+///
+/// Every source parses messages with `mailparse` and hands them to
+/// `process_email`, so filtering and body extraction are identical no matter
+/// where the mail comes from.
+pub trait EmailSource {
+  /// Short human-readable description, used in progress messages
+  fn name(&self) -> String;
+
+  /// Read all emails the filter accepts
+  fn fetch(&self, filter: &EmailFilter) -> Result<Vec<EmailTable>, Box<dyn Error>>;
+}
+
+/// Emails from the last `days_ago` days of an IMAP inbox
+pub struct ImapSource {
+  pub email_address: String,
+  pub password: String,
+  pub imap_server: String,
+  pub days_ago: u64,
+}
+
+impl EmailSource for ImapSource {
+  fn name(&self) -> String {
+    format!("IMAP {}", self.imap_server)
+  }
+
+  fn fetch(&self, filter: &EmailFilter) -> Result<Vec<EmailTable>, Box<dyn Error>> {
+    inner_fetch_emails(
+      &self.email_address,
+      &self.password,
+      self.days_ago,
+      &self.imap_server,
+      filter,
+    )
+  }
+}
+
+/// Emails delivered to a Maildir, read from its `new/` and `cur/` folders
+pub struct MaildirSource {
+  pub path: PathBuf,
+  /// Only keep emails dated within this many days, `None` reads everything
+  pub days_ago: Option<u64>,
+}
+
+impl EmailSource for MaildirSource {
+  fn name(&self) -> String {
+    format!("Maildir {}", self.path.display())
+  }
+
+  fn fetch(&self, filter: &EmailFilter) -> Result<Vec<EmailTable>, Box<dyn Error>> {
+    if !self.path.join("cur").is_dir() && !self.path.join("new").is_dir() {
+      return Err(format!("Not a Maildir (no cur/ or new/): {}", self.path.display()).into());
+    }
+
+    let mut files = Vec::new();
+    for folder in ["new", "cur"] {
+      let folder = self.path.join(folder);
+      if folder.is_dir() {
+        files.extend(list_files(&folder, |_| true)?);
+      }
+    }
+    let messages = read_files(&files)?;
+    Ok(process_messages(
+      &messages,
+      &self.path,
+      self.days_ago,
+      filter,
+    ))
+  }
+}
+
+/// Emails stored in one mbox file
+pub struct MboxSource {
+  pub path: PathBuf,
+  /// Only keep emails dated within this many days, `None` reads everything
+  pub days_ago: Option<u64>,
+}
+
+impl EmailSource for MboxSource {
+  fn name(&self) -> String {
+    format!("mbox {}", self.path.display())
+  }
+
+  fn fetch(&self, filter: &EmailFilter) -> Result<Vec<EmailTable>, Box<dyn Error>> {
+    let contents = fs::read(&self.path)
+      .map_err(|e| format!("Failed to read mbox {}: {}", self.path.display(), e))?;
+    Ok(process_messages(
+      &split_mbox(&contents),
+      &self.path,
+      self.days_ago,
+      filter,
+    ))
+  }
+}
+
+/// Emails saved as individual `.eml` files in one directory
+pub struct EmlDirSource {
+  pub path: PathBuf,
+  /// Only keep emails dated within this many days, `None` reads everything
+  pub days_ago: Option<u64>,
+}
+
+impl EmailSource for EmlDirSource {
+  fn name(&self) -> String {
+    format!(".eml files in {}", self.path.display())
+  }
+
+  fn fetch(&self, filter: &EmailFilter) -> Result<Vec<EmailTable>, Box<dyn Error>> {
+    let files = list_files(&self.path, |path| {
+      path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+    })?;
+    let messages = read_files(&files)?;
+    Ok(process_messages(
+      &messages,
+      &self.path,
+      self.days_ago,
+      filter,
+    ))
+  }
+}
+
+/// Regular files directly inside `dir` accepted by `keep`, sorted by name
+fn list_files(dir: &Path, keep: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+  let entries =
+    fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+  let mut files = Vec::new();
+  for entry in entries {
+    let path = entry?.path();
+    if path.is_file() && keep(&path) {
+      files.push(path);
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+fn read_files(files: &[PathBuf]) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+  files
+    .iter()
+    .map(|path| {
+      fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e).into())
+    })
+    .collect()
+}
+
+/// Parse raw messages and keep the ones inside the date window
+///
+/// The window is checked on the `Date` header alone, so older messages are
+/// skipped without parsing their bodies and attachments. Messages whose date
+/// cannot be parsed are kept rather than silently lost.
+fn process_messages(
+  messages: &[Vec<u8>],
+  mailbox: &Path,
+  days_ago: Option<u64>,
+  filter: &EmailFilter,
+) -> Vec<EmailTable> {
+  let mailbox = mailbox.display().to_string();
+  let cutoff = days_ago.map(|days_ago| Utc::now() - Duration::days(days_ago as i64));
+  let mut email_tables = Vec::new();
+  for raw in messages {
+    if !cutoff.is_none_or(|cutoff| sent_since(raw, cutoff)) {
+      continue;
+    }
+    match parse_mail(raw) {
+      Ok(parsed) => process_email(&parsed, &mailbox, None, filter, &mut email_tables),
+      Err(e) => eprintln!("Skipping unparsable email in {}: {}", mailbox, e),
+    }
+  }
+  email_tables
+}
+
+/// Whether a raw message's `Date` header is at or after `cutoff`, `true` when
+/// there is no date to compare
+fn sent_since(raw: &[u8], cutoff: DateTime<Utc>) -> bool {
+  let Ok((headers, _)) = parse_headers(raw) else {
+    return true;
+  };
+  headers
+    .get_first_value("Date")
+    .and_then(|date| dateparse(&date).ok())
+    .is_none_or(|timestamp| timestamp >= cutoff.timestamp())
+}
+
+/// Split an mbox file into raw messages.
+///
+/// Messages start at a `From ` line at the beginning of the file or after a
+/// blank line; `>From ` quoting (mboxrd) inside bodies is undone.
+pub fn split_mbox(contents: &[u8]) -> Vec<Vec<u8>> {
+  let mut messages = Vec::new();
+  let mut current: Option<Vec<u8>> = None;
+  let mut previous_blank = true;
+
+  for line in contents.split_inclusive(|&b| b == b'\n') {
+    if previous_blank && line.starts_with(b"From ") {
+      if let Some(message) = current.take() {
+        messages.push(message);
+      }
+      current = Some(Vec::new());
+      previous_blank = false;
+      continue;
+    }
+
+    previous_blank = line == b"\n" || line == b"\r\n";
+    let Some(message) = current.as_mut() else {
+      // Anything before the first separator is not part of a message
+      continue;
+    };
+    let quoted = line.iter().take_while(|&&b| b == b'>').count();
+    if quoted > 0 && line[quoted..].starts_with(b"From ") {
+      message.extend_from_slice(&line[1..]);
+    } else {
+      message.extend_from_slice(line);
+    }
+  }
+  messages.extend(current);
+
+  // The blank line before the next separator belongs to the mbox format
+  for message in &mut messages {
+    if message.ends_with(b"\r\n") {
+      message.truncate(message.len() - 2);
+    } else if message.ends_with(b"\n") {
+      message.truncate(message.len() - 1);
+    }
+  }
+  messages
+}
@@ -1,7 +1,8 @@
 use crate::email::EmailTable;
+use crate::event::{Event, ParseError, SourceEmail};
 
 /// Format a vector of EmailTable into a string representation
-pub fn format_emails(emails: &Vec<EmailTable>) -> String {
+pub fn format_emails(emails: &[EmailTable]) -> String {
   let mut formatted = String::from("mails = {");
 
   for (i, email) in emails.iter().enumerate() {
@@ -9,30 +10,238 @@ pub fn format_emails(emails: &Vec<EmailTable>) -> String {
       formatted.push_str(", ");
     }
 
-    formatted.push_str(&format!(
-      "{{sender: \"{}\", subject: \"{}\", body: \"{}\"}}",
-      clean_string(&email.sender),
-      clean_string(&email.subject),
-      clean_string(&email.body)
-    ));
+    formatted.push_str(&format_email(&(i + 1).to_string(), email));
   }
 
-  formatted.push_str("}");
+  formatted.push('}');
   formatted
 }
 
+/// Format a single email the way it appears inside `format_emails`.
+///
+/// `id` is the email's position in the batch, which the model echoes back as
+/// `source_id` so events can be traced to their email.
+fn format_email(id: &str, email: &EmailTable) -> String {
+  let attachments = if email.attachment_text.is_empty() {
+    String::new()
+  } else {
+    format!(
+      ", attachments: \"{}\"",
+      clean_string(&email.attachment_text)
+    )
+  };
+  format!(
+    "{{id: \"{}\", sender: \"{}\", subject: \"{}\", body: \"{}\"{}}}",
+    id,
+    clean_string(&email.sender),
+    clean_string(&email.subject),
+    clean_string(&email.body),
+    attachments
+  )
+}
+
 /// Clean a string to make it suitable for inclusion in the prompt
 fn clean_string(s: &str) -> String {
   s.replace("\"", "'").replace("\n", " ").replace("\r", " ")
 }
 
 /// Create a prompt for summarizing emails
-pub fn generate_summary_prompt(emails: &Vec<EmailTable>) -> String {
+pub fn generate_summary_prompt(emails: &[EmailTable]) -> String {
   let formatted_emails = format_emails(emails);
-  let config = crate::config::Config::get();
+  // A broken config file already failed the run when the options were resolved
+  let config = crate::config::Config::get().unwrap_or_default();
+
+  config.prompt.replace("{emails_input}", &formatted_emails)
+}
+
+/// Create a follow-up prompt asking the model to fix output that failed validation
+///
+/// # Arguments
+///
+/// * `original_prompt` - The prompt that produced the bad output
+/// * `output` - The model's previous reply
+/// * `error` - What was wrong with it
+pub fn generate_repair_prompt(original_prompt: &str, output: &str, error: &ParseError) -> String {
+  let problems = match error {
+    ParseError::Json(e) => format!("- JSON 解析失败：{}", e),
+    ParseError::Schema(errors) => errors
+      .iter()
+      .map(|e| format!("- {}", e))
+      .collect::<Vec<_>>()
+      .join("\n"),
+  };
+
+  format!(
+    "{original_prompt}
+
+你上一次的输出如下：
+{output}
+
+该输出未通过格式校验，问题如下：
+{problems}
+
+请修正以上问题并重新输出。要求：只输出纯JSON数组，每个对象必须包含 sender, event, time_begin, time_end, position, speaker_name, speaker_title, abstract 字段且值均为字符串，不要包含markdown代码块或任何额外说明。"
+  )
+}
+
+/// Roughly estimate how many tokens `text` costs.
+///
+/// CJK characters are counted as one token each, everything else as one token
+/// per four characters. This errs on the high side for both DeepSeek and
+/// OpenAI tokenizers, which is what a budget needs.
+pub fn estimate_tokens(text: &str) -> usize {
+  let mut cjk = 0;
+  let mut other: usize = 0;
+  for c in text.chars() {
+    if is_cjk(c) {
+      cjk += 1;
+    } else {
+      other += 1;
+    }
+  }
+  cjk + other.div_ceil(4)
+}
+
+fn is_cjk(c: char) -> bool {
+  matches!(c as u32,
+    0x3000..=0x303F   // CJK punctuation
+    | 0x3040..=0x30FF // Kana
+    | 0x3400..=0x4DBF // CJK extension A
+    | 0x4E00..=0x9FFF // CJK unified ideographs
+    | 0xF900..=0xFAFF // CJK compatibility ideographs
+    | 0xFF00..=0xFFEF // Full-width forms
+  )
+}
+
+/// Estimated tokens one email adds to a prompt
+pub fn estimate_email_tokens(email: &EmailTable) -> usize {
+  estimate_tokens(&format_email("1", email))
+}
+
+/// Split emails into batches whose formatted size stays within `token_budget`.
+///
+/// # Arguments
+///
+/// * `emails` - The emails to split, order is preserved
+/// * `token_budget` - Estimated tokens allowed for the emails of one batch
+/// * `max_emails` - Upper bound on emails per batch, keeps the output within `max_tokens`
+///
+/// # Returns
+///
+/// The batches. An email that is larger than the whole budget gets a batch of
+/// its own with its body truncated to fit. An error if the budget does not
+/// even cover an email's sender and subject, which would send it without a body.
+pub fn batch_emails(
+  emails: &[EmailTable],
+  token_budget: usize,
+  max_emails: usize,
+) -> Result<Vec<Vec<EmailTable>>, Box<dyn std::error::Error>> {
+  let max_emails = max_emails.max(1);
+  let mut batches = Vec::new();
+  let mut current: Vec<EmailTable> = Vec::new();
+  let mut current_tokens = 0;
+
+  for email in emails {
+    let tokens = estimate_tokens(&format_email(&max_emails.to_string(), email));
 
-  return format!(
-    "{}",
-    config.prompt.replace("{emails_input}", &formatted_emails)
+    if !current.is_empty()
+      && (current_tokens + tokens > token_budget || current.len() >= max_emails)
+    {
+      batches.push(std::mem::take(&mut current));
+      current_tokens = 0;
+    }
+
+    if tokens > token_budget {
+      batches.push(vec![truncate_email(email, token_budget)?]);
+      continue;
+    }
+
+    current_tokens += tokens;
+    current.push(email.clone());
+  }
+
+  if !current.is_empty() {
+    batches.push(current);
+  }
+  Ok(batches)
+}
+
+/// Shorten the body, then the attachment text, of `email` until its formatted size fits `token_budget`
+fn truncate_email(
+  email: &EmailTable,
+  token_budget: usize,
+) -> Result<EmailTable, Box<dyn std::error::Error>> {
+  let mut truncated = email.clone();
+  let overhead = estimate_tokens(&format_email(
+    "1",
+    &EmailTable {
+      body: String::new(),
+      attachment_text: String::new(),
+      ..email.clone()
+    },
+  ));
+  if overhead >= token_budget {
+    return Err(
+      format!(
+        "A token budget of {} leaves no room for the body of \"{}\" (~{} tokens without it), raise token_budget",
+        token_budget, email.subject, overhead
+      )
+      .into(),
+    );
+  }
+  // Non-CJK characters cost a quarter token each; count in quarters to stay exact
+  let mut quarters_left = (token_budget - overhead) * 4;
+
+  let (end, used) = prefix_within(&email.body, quarters_left);
+  truncated.body.truncate(end);
+  quarters_left -= used;
+
+  let attachments_overhead = estimate_tokens(", attachments: \"\"") * 4;
+  let (end, _) = prefix_within(
+    &email.attachment_text,
+    quarters_left.saturating_sub(attachments_overhead),
   );
+  truncated.attachment_text.truncate(end);
+  Ok(truncated)
+}
+
+/// Byte length of the longest prefix of `text` costing at most `quarters`
+/// quarter tokens, and what it costs
+fn prefix_within(text: &str, quarters: usize) -> (usize, usize) {
+  let mut used = 0;
+  let mut end = 0;
+  for (idx, c) in text.char_indices() {
+    let cost = if is_cjk(c) { 4 } else { 1 };
+    if used + cost > quarters {
+      break;
+    }
+    used += cost;
+    end = idx + c.len_utf8();
+  }
+  (end, used)
+}
+
+/// Resolve the `source_id` each event echoed back to the email it came from.
+///
+/// # Arguments
+///
+/// * `events` - Events parsed from the reply to the prompt built from `batch`
+/// * `batch` - The emails in the order they were formatted into the prompt
+pub fn attach_sources(events: &mut [Event], batch: &[EmailTable]) {
+  for event in events {
+    let email = event
+      .source_id
+      .as_deref()
+      .and_then(|id| id.trim().parse::<usize>().ok())
+      .and_then(|id| id.checked_sub(1))
+      .and_then(|idx| batch.get(idx))
+      // A one-email batch needs no echo to be unambiguous
+      .or(if batch.len() == 1 {
+        batch.first()
+      } else {
+        None
+      });
+
+    event.source = email.map(SourceEmail::from);
+  }
 }
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An event extracted from an email, as stored in the `events` table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+  /// Row id, only set for events read back from the database
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<i64>,
+  pub sender: String,
+  pub event: String,
+  pub time_begin: String,
+  pub time_end: String,
+  pub position: String,
+  pub r#abstract: String,
+  pub speaker_name: String,
+  pub speaker_title: String,
+  /// `time_begin` as an RFC 3339 timestamp in Asia/Shanghai, if it could be parsed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub time_begin_iso: Option<String>,
+  /// `time_end` as an RFC 3339 timestamp in Asia/Shanghai, if it could be parsed
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub time_end_iso: Option<String>,
+  /// Per-prompt id of the email the event came from, echoed back by the model
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_id: Option<String>,
+  /// The email the event was extracted from
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<SourceEmail>,
+  /// When the row was inserted or last changed, RFC 3339 in UTC; only set for
+  /// events read back from the database
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub updated_at: Option<String>,
+  /// The row's stored `event_key`, which differs from `data_sql::event_key`
+  /// for old duplicates kept by the key migration; only set for events read
+  /// back from the database
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub event_key: Option<String>,
+}
+
+/// Provenance of an event, stored in the `emails` table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceEmail {
+  pub message_id: String,
+  pub subject: String,
+  pub date: String,
+  pub mailbox: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub uid: Option<u32>,
+}
+
+/// Fields every event object must carry, and whether they may be empty
+const EVENT_FIELDS: &[(&str, bool)] = &[
+  ("sender", true),
+  ("event", false),
+  ("time_begin", false),
+  ("time_end", true),
+  ("position", true),
+  ("speaker_name", true),
+  ("speaker_title", true),
+  ("abstract", true),
+];
+
+/// Why the model output could not be turned into events
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+  /// The output is not valid JSON
+  Json(String),
+  /// The output is JSON but does not match the event schema, one entry per problem
+  Schema(Vec<String>),
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::Json(e) => write!(f, "invalid JSON: {}", e),
+      ParseError::Schema(errors) => write!(f, "schema violations: {}", errors.join("; ")),
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Remove a surrounding markdown code fence (```json ... ```) if present
+pub fn strip_code_fences(raw: &str) -> &str {
+  let trimmed = raw.trim();
+  let Some(rest) = trimmed.strip_prefix("```") else {
+    return trimmed;
+  };
+  // Skip the info string such as `json` on the opening line
+  let rest = rest.split_once('\n').map(|(_, body)| body).unwrap_or("");
+  rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+}
+
+/// Check a parsed JSON value against the event schema.
+///
+/// # Returns
+///
+/// Every violation found, formatted as `[index].field: problem`
+pub fn validate_events(value: &serde_json::Value) -> Vec<String> {
+  let Some(items) = value.as_array() else {
+    return vec!["top level: expected a JSON array of events".to_string()];
+  };
+
+  let mut errors = Vec::new();
+  for (i, item) in items.iter().enumerate() {
+    let Some(object) = item.as_object() else {
+      errors.push(format!("[{}]: expected an object", i));
+      continue;
+    };
+    for (field, may_be_empty) in EVENT_FIELDS {
+      match object.get(*field) {
+        None => errors.push(format!("[{}].{}: missing required field", i, field)),
+        Some(serde_json::Value::String(s)) if s.trim().is_empty() && !may_be_empty => {
+          errors.push(format!("[{}].{}: must not be empty", i, field))
+        }
+        Some(serde_json::Value::String(_)) => {}
+        Some(other) => errors.push(format!(
+          "[{}].{}: expected a string, got {}",
+          i,
+          field,
+          json_type_name(other)
+        )),
+      }
+    }
+  }
+  errors
+}
+
+fn json_type_name(value: &serde_json::Value) -> &'static str {
+  match value {
+    serde_json::Value::Null => "null",
+    serde_json::Value::Bool(_) => "boolean",
+    serde_json::Value::Number(_) => "number",
+    serde_json::Value::String(_) => "string",
+    serde_json::Value::Array(_) => "array",
+    serde_json::Value::Object(_) => "object",
+  }
+}
+
+/// One event as the model writes it: only the fields the prompt asks for.
+///
+/// Anything else in the reply, such as an echoed email `id` or a `source`,
+/// belongs to the database and is ignored rather than failing the batch.
+#[derive(Deserialize)]
+struct ModelEvent {
+  sender: String,
+  event: String,
+  time_begin: String,
+  time_end: String,
+  position: String,
+  r#abstract: String,
+  speaker_name: String,
+  speaker_title: String,
+  #[serde(default)]
+  source_id: Option<serde_json::Value>,
+}
+
+impl From<ModelEvent> for Event {
+  fn from(row: ModelEvent) -> Self {
+    // Models like to echo numeric ids as numbers
+    let source_id = match row.source_id {
+      Some(serde_json::Value::String(id)) => Some(id),
+      Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+      _ => None,
+    };
+    Self {
+      sender: row.sender,
+      event: row.event,
+      time_begin: row.time_begin,
+      time_end: row.time_end,
+      position: row.position,
+      r#abstract: row.r#abstract,
+      speaker_name: row.speaker_name,
+      speaker_title: row.speaker_title,
+      source_id,
+      ..Default::default()
+    }
+  }
+}
+
+/// Parse raw model output into typed events.
+///
+/// Code fences are stripped first, then the JSON is validated against the
+/// event schema so that missing or misnamed fields are reported instead of
+/// silently turning into empty strings. Keys outside the schema are ignored.
+pub fn parse_events(raw: &str) -> Result<Vec<Event>, ParseError> {
+  let value: serde_json::Value =
+    serde_json::from_str(strip_code_fences(raw)).map_err(|e| ParseError::Json(e.to_string()))?;
+
+  let errors = validate_events(&value);
+  if !errors.is_empty() {
+    return Err(ParseError::Schema(errors));
+  }
+
+  let rows: Vec<ModelEvent> =
+    serde_json::from_value(value).map_err(|e| ParseError::Json(e.to_string()))?;
+  Ok(rows.into_iter().map(Event::from).collect())
+}
@@ -0,0 +1,193 @@
+use crate::event::{Event, SourceEmail};
+use crate::ics;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Byte order mark written before CSV so Excel detects UTF-8
+const UTF8_BOM: &str = "\u{feff}";
+
+/// File formats for `export` and `import`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+  /// iCalendar (RFC 5545), for calendar clients; export only
+  Ics,
+  /// One JSON array of events
+  Json,
+  /// Comma-separated values, UTF-8 with a BOM
+  Csv,
+  /// JSON Lines, one event per line
+  Jsonl,
+}
+
+impl ExportFormat {
+  /// Guess the format from a file extension
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+      "ics" => Some(Self::Ics),
+      "json" => Some(Self::Json),
+      "csv" => Some(Self::Csv),
+      "jsonl" | "ndjson" => Some(Self::Jsonl),
+      _ => None,
+    }
+  }
+}
+
+/// One event as a flat row; the source email is spread over `source_*` columns.
+///
+/// Written as CSV, and read from every format so that CSV, JSON and JSON
+/// Lines accept the same missing fields. Events exported as JSON carry the
+/// source as a nested `source` object instead, which is read too.
+#[derive(Debug, Serialize, Deserialize)]
+struct EventRow {
+  #[serde(default)]
+  id: Option<i64>,
+  sender: String,
+  event: String,
+  time_begin: String,
+  #[serde(default)]
+  time_end: String,
+  #[serde(default)]
+  position: String,
+  #[serde(default)]
+  r#abstract: String,
+  #[serde(default)]
+  speaker_name: String,
+  #[serde(default)]
+  speaker_title: String,
+  #[serde(default)]
+  time_begin_iso: Option<String>,
+  #[serde(default)]
+  time_end_iso: Option<String>,
+  #[serde(default)]
+  source_message_id: Option<String>,
+  #[serde(default)]
+  source_subject: Option<String>,
+  #[serde(default)]
+  source_date: Option<String>,
+  #[serde(default)]
+  source_mailbox: Option<String>,
+  #[serde(default)]
+  source_uid: Option<u32>,
+  #[serde(default, skip_serializing)]
+  source: Option<SourceEmail>,
+}
+
+impl From<&Event> for EventRow {
+  fn from(event: &Event) -> Self {
+    let source = event.source.as_ref();
+    Self {
+      id: event.id,
+      sender: event.sender.clone(),
+      event: event.event.clone(),
+      time_begin: event.time_begin.clone(),
+      time_end: event.time_end.clone(),
+      position: event.position.clone(),
+      r#abstract: event.r#abstract.clone(),
+      speaker_name: event.speaker_name.clone(),
+      speaker_title: event.speaker_title.clone(),
+      time_begin_iso: event.time_begin_iso.clone(),
+      time_end_iso: event.time_end_iso.clone(),
+      source_message_id: source.map(|s| s.message_id.clone()),
+      source_subject: source.map(|s| s.subject.clone()),
+      source_date: source.map(|s| s.date.clone()),
+      source_mailbox: source.map(|s| s.mailbox.clone()),
+      source_uid: source.and_then(|s| s.uid),
+      source: None,
+    }
+  }
+}
+
+impl From<EventRow> for Event {
+  fn from(row: EventRow) -> Self {
+    let flat_source = row
+      .source_message_id
+      .filter(|id| !id.is_empty())
+      .map(|message_id| SourceEmail {
+        message_id,
+        subject: row.source_subject.unwrap_or_default(),
+        date: row.source_date.unwrap_or_default(),
+        mailbox: row.source_mailbox.unwrap_or_default(),
+        uid: row.source_uid,
+      });
+    let source = row.source.or(flat_source);
+    Self {
+      id: row.id,
+      sender: row.sender,
+      event: row.event,
+      time_begin: row.time_begin,
+      time_end: row.time_end,
+      position: row.position,
+      r#abstract: row.r#abstract,
+      speaker_name: row.speaker_name,
+      speaker_title: row.speaker_title,
+      time_begin_iso: row.time_begin_iso.filter(|t| !t.is_empty()),
+      time_end_iso: row.time_end_iso.filter(|t| !t.is_empty()),
+      source_id: None,
+      source,
+      updated_at: None,
+      event_key: None,
+    }
+  }
+}
+
+/// Write events in `format`
+pub fn write_events(events: &[Event], format: ExportFormat) -> Result<String, Box<dyn Error>> {
+  match format {
+    ExportFormat::Ics => Ok(ics::events_to_ics(events)),
+    ExportFormat::Json => Ok(serde_json::to_string_pretty(events)? + "\n"),
+    ExportFormat::Jsonl => {
+      let mut out = String::new();
+      for event in events {
+        out.push_str(&serde_json::to_string(event)?);
+        out.push('\n');
+      }
+      Ok(out)
+    }
+    ExportFormat::Csv => {
+      let mut writer = csv::Writer::from_writer(UTF8_BOM.as_bytes().to_vec());
+      for event in events {
+        writer.serialize(EventRow::from(event))?;
+      }
+      Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+  }
+}
+
+/// Read events written by `write_events`.
+///
+/// Row ids are dropped, the database assigns its own. Every format accepts
+/// the same rows: only `sender`, `event` and `time_begin` are required. A
+/// UTF-8 BOM is ignored in every format.
+///
+/// # Returns
+///
+/// The events, or an error naming the line or record that failed to parse
+pub fn read_events(contents: &str, format: ExportFormat) -> Result<Vec<Event>, Box<dyn Error>> {
+  let contents = contents.strip_prefix(UTF8_BOM).unwrap_or(contents);
+  let rows: Vec<EventRow> = match format {
+    ExportFormat::Ics => return Err("Importing iCalendar files is not supported".into()),
+    ExportFormat::Json => serde_json::from_str(contents)?,
+    ExportFormat::Jsonl => contents
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.trim().is_empty())
+      .map(|(i, line)| {
+        serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e).into())
+      })
+      .collect::<Result<_, Box<dyn Error>>>()?,
+    ExportFormat::Csv => csv::Reader::from_reader(contents.as_bytes())
+      .deserialize()
+      .collect::<Result<_, _>>()?,
+  };
+  Ok(
+    rows
+      .into_iter()
+      .map(|row| Event {
+        id: None,
+        ..Event::from(row)
+      })
+      .collect(),
+  )
+}
@@ -0,0 +1,205 @@
+use crate::{api_req, data_sql, email, email_abstract, event, progress};
+use futures::stream::{FuturesUnordered, StreamExt};
+use indicatif::{MultiProgress, ProgressBar};
+
+/// Query API with progress indication; the spinner only stays while the
+/// request is in flight, or to show why it failed
+async fn query_api_with_progress(
+  m: &MultiProgress,
+  provider: &dyn api_req::LlmProvider,
+  request: &api_req::CompletionRequest,
+  label: &str,
+) -> Result<api_req::Completion, Box<dyn std::error::Error>> {
+  let pb = progress::create_progress_bar(
+    m,
+    &format!("Querying {} API ({label})...", provider.name()),
+    "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏",
+    "yellow",
+  );
+
+  match provider.complete(request).await {
+    Ok(result) => {
+      pb.finish_and_clear();
+      Ok(result)
+    }
+    Err(e) => {
+      pb.finish_with_message(format!("✗ API request failed ({label}): {}", e));
+      Err(e)
+    }
+  }
+}
+
+/// Query one batch and parse the reply into events, re-prompting the model
+/// with the validation errors when the reply does not match the event schema.
+/// Tokens of every request, including failed repairs, are added to `usage`.
+pub async fn extract_events_with_progress(
+  m: &MultiProgress,
+  provider: &dyn api_req::LlmProvider,
+  request: &api_req::CompletionRequest,
+  label: &str,
+  repair_attempts: u32,
+  usage: &mut api_req::TokenUsage,
+) -> Result<Vec<event::Event>, Box<dyn std::error::Error>> {
+  let completion = query_api_with_progress(m, provider, request, label).await?;
+  *usage += completion.usage;
+  let mut api_result = completion.text;
+
+  let mut attempt = 0;
+  loop {
+    match event::parse_events(&api_result) {
+      Ok(events) => return Ok(events),
+      Err(e) if attempt < repair_attempts => {
+        attempt += 1;
+        eprintln!("✗ Invalid API result ({label}): {e}, asking the model to repair it");
+        let repair_request = api_req::CompletionRequest {
+          prompt: email_abstract::generate_repair_prompt(&request.prompt, &api_result, &e),
+          ..request.clone()
+        };
+        let repair_label = format!("{label}, repair {attempt}");
+        let completion =
+          query_api_with_progress(m, provider, &repair_request, &repair_label).await?;
+        *usage += completion.usage;
+        api_result = completion.text;
+      }
+      Err(e) => return Err(format!("Invalid API result ({label}): {e}\n{api_result}").into()),
+    }
+  }
+}
+
+/// How `extract_batches_with_progress` builds and handles requests
+pub struct ExtractOptions {
+  pub model: String,
+  pub max_tokens: i32,
+  pub temperature: f32,
+  /// See `extract_events_with_progress`
+  pub repair_attempts: u32,
+  /// Maximum number of requests in flight at once
+  pub concurrency: usize,
+  /// Every batch holds a single email, only changes the progress labels
+  pub per_email: bool,
+  /// Database the events of each batch are stored in, `None` collects them
+  /// in `Extraction::unstored` instead
+  pub path_to_db: Option<String>,
+}
+
+/// What `extract_batches_with_progress` did
+#[derive(Default)]
+pub struct Extraction {
+  pub usage: api_req::TokenUsage,
+  /// Events extracted, stored or not
+  pub events: usize,
+  pub stored: data_sql::StoreStats,
+  /// Events kept back because there was no database to store them in
+  pub unstored: Vec<event::Event>,
+  /// Batches whose events could not be extracted or stored
+  pub failed: usize,
+  /// UID of every email in a failed batch, with the error
+  pub failed_uids: Vec<(u32, String)>,
+}
+
+impl Extraction {
+  /// Count `batch` as failed with `error`
+  fn fail(&mut self, batch: &[email::EmailTable], error: &str) {
+    self.failed += 1;
+    self.failed_uids.extend(
+      batch
+        .iter()
+        .filter_map(|email| email.uid)
+        .map(|uid| (uid, error.to_string())),
+    );
+  }
+}
+
+/// Extract events from all batches with up to `opts.concurrency` requests in
+/// flight, storing each batch's events as soon as they arrive, or collecting
+/// them in `Extraction::unstored` when `opts.path_to_db` is `None`.
+///
+/// A failing batch is reported and counted but does not stop the others. Only
+/// replies that parse are cached, so a rerun asks the model again for batches
+/// whose reply was invalid but does not pay again for ones that failed to store.
+///
+/// # Arguments
+///
+/// * `m` - Where the spinner of each request is shown
+/// * `pb` - Counts finished batches, failed ones included, and is finished at the end
+pub async fn extract_batches_with_progress(
+  m: &MultiProgress,
+  pb: &ProgressBar,
+  provider: &dyn api_req::LlmProvider,
+  batches: &[Vec<email::EmailTable>],
+  opts: &ExtractOptions,
+) -> Extraction {
+  let unit = if opts.per_email { "email" } else { "batch" };
+  let semaphore = tokio::sync::Semaphore::new(opts.concurrency);
+
+  let mut pending: FuturesUnordered<_> = batches
+    .iter()
+    .enumerate()
+    .map(|(i, batch)| {
+      let semaphore = &semaphore;
+      async move {
+        let _permit = semaphore
+          .acquire()
+          .await
+          .expect("semaphore is never closed");
+        let label = format!("{unit} {}/{}", i + 1, batches.len());
+        let request = api_req::CompletionRequest {
+          prompt: email_abstract::generate_summary_prompt(batch),
+          model: opts.model.clone(),
+          max_tokens: opts.max_tokens,
+          temperature: opts.temperature,
+        };
+        let mut usage = api_req::TokenUsage::default();
+        let events = extract_events_with_progress(
+          m,
+          provider,
+          &request,
+          &label,
+          opts.repair_attempts,
+          &mut usage,
+        )
+        .await;
+        (batch, label, events, usage)
+      }
+    })
+    .collect();
+
+  let mut extraction = Extraction::default();
+  while let Some((batch, label, events, usage)) = pending.next().await {
+    extraction.usage += usage;
+    match events {
+      Ok(mut events) => {
+        email_abstract::attach_sources(&mut events, batch);
+        extraction.events += events.len();
+        let Some(path_to_db) = &opts.path_to_db else {
+          extraction.unstored.append(&mut events);
+          pb.inc(1);
+          continue;
+        };
+        match data_sql::store_json_to_db(events, path_to_db).await {
+          Ok(stats) => extraction.stored += stats,
+          Err(e) => {
+            pb.println(format!("✗ Could not store {label}: {e}"));
+            extraction.fail(batch, &e.to_string());
+          }
+        }
+      }
+      Err(e) => {
+        pb.println(format!("✗ Skipping {label}: {e}"));
+        extraction.fail(batch, &e.to_string());
+      }
+    }
+    pb.inc(1);
+  }
+
+  let stored = extraction.stored;
+  if opts.path_to_db.is_none() {
+    pb.finish_with_message(format!("✓ {} events extracted!", extraction.events));
+  } else {
+    pb.finish_with_message(format!(
+      "✓ {} rows inserted, {} rows updated, {} unchanged in database!",
+      stored.inserted, stored.updated, stored.unchanged
+    ));
+  }
+  extraction
+}
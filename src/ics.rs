@@ -0,0 +1,146 @@
+use crate::data_sql::event_key;
+use crate::event::Event;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use sha2::{Digest, Sha256};
+
+/// Length assumed for events whose end time is unknown
+pub const DEFAULT_DURATION_MINUTES: i64 = 60;
+
+/// Name calendar clients show for the subscribed feed
+const CALENDAR_NAME: &str = "Email Events";
+
+/// Stable UID of an event.
+///
+/// Hashes the event's key rather than the row id, so the same event extracted
+/// again from a resent email keeps its UID and calendar clients update the
+/// entry instead of adding a duplicate. The stored key is used when there is
+/// one, since old duplicates kept by the key migration share a computed key.
+pub fn event_uid(event: &Event) -> String {
+  let key = event.event_key.clone().unwrap_or_else(|| event_key(event));
+  let digest = Sha256::digest(key.as_bytes());
+  let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+  format!("{}@email_abstract_rs", hex)
+}
+
+/// Render events as an RFC 5545 calendar.
+///
+/// Times are written in UTC from `time_begin_iso` and `time_end_iso`; events
+/// whose begin time could not be normalized are left out, since a calendar
+/// entry needs a real time. Without a valid end time an event lasts
+/// `DEFAULT_DURATION_MINUTES`. Events read from the database carry their
+/// last change as `LAST-MODIFIED`, so clients refresh entries that changed.
+///
+/// # Returns
+///
+/// The `VCALENDAR` text with CRLF line endings
+pub fn events_to_ics(events: &[Event]) -> String {
+  let mut lines = vec![
+    "BEGIN:VCALENDAR".to_string(),
+    "VERSION:2.0".to_string(),
+    "PRODID:-//email_abstract_rs//Events//ZH".to_string(),
+    "CALSCALE:GREGORIAN".to_string(),
+    "METHOD:PUBLISH".to_string(),
+    format!("X-WR-CALNAME:{}", escape_text(CALENDAR_NAME)),
+    "X-WR-TIMEZONE:Asia/Shanghai".to_string(),
+  ];
+  for event in events {
+    lines.extend(vevent(event));
+  }
+  lines.push("END:VCALENDAR".to_string());
+
+  let mut ics = String::new();
+  for line in &lines {
+    ics.push_str(&fold_line(line));
+    ics.push_str("\r\n");
+  }
+  ics
+}
+
+/// Content lines of one VEVENT, or nothing if the event has no usable time
+fn vevent(event: &Event) -> Vec<String> {
+  let Some(begin) = parse_iso(event.time_begin_iso.as_deref()) else {
+    return Vec::new();
+  };
+  let end = parse_iso(event.time_end_iso.as_deref())
+    .filter(|end| *end > begin)
+    .unwrap_or(begin + Duration::minutes(DEFAULT_DURATION_MINUTES));
+  // DTSTAMP has to be stable too, or every export would look like a change
+  let stamp = event
+    .source
+    .as_ref()
+    .and_then(|source| DateTime::parse_from_rfc3339(&source.date).ok())
+    .unwrap_or(begin);
+
+  let mut lines = vec![
+    "BEGIN:VEVENT".to_string(),
+    format!("UID:{}", event_uid(event)),
+    format!("DTSTAMP:{}", utc(stamp)),
+    format!("DTSTART:{}", utc(begin)),
+    format!("DTEND:{}", utc(end)),
+    format!("SUMMARY:{}", escape_text(&event.event)),
+  ];
+  if let Some(updated) = parse_iso(event.updated_at.as_deref()) {
+    lines.push(format!("LAST-MODIFIED:{}", utc(updated)));
+  }
+  if !event.position.trim().is_empty() {
+    lines.push(format!("LOCATION:{}", escape_text(event.position.trim())));
+  }
+  let description = description(event);
+  if !description.is_empty() {
+    lines.push(format!("DESCRIPTION:{}", escape_text(&description)));
+  }
+  lines.push("END:VEVENT".to_string());
+  lines
+}
+
+/// Abstract followed by the speaker line
+fn description(event: &Event) -> String {
+  let speaker = match (event.speaker_name.trim(), event.speaker_title.trim()) {
+    ("", _) => String::new(),
+    (name, "") => format!("报告人：{}", name),
+    (name, title) => format!("报告人：{}（{}）", name, title),
+  };
+  [event.r#abstract.trim(), speaker.as_str()]
+    .iter()
+    .filter(|part| !part.is_empty())
+    .copied()
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+fn parse_iso(value: Option<&str>) -> Option<DateTime<FixedOffset>> {
+  value.and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+}
+
+fn utc(time: DateTime<FixedOffset>) -> String {
+  time
+    .with_timezone(&Utc)
+    .format("%Y%m%dT%H%M%SZ")
+    .to_string()
+}
+
+/// Escape a TEXT value (`\`, `;`, `,` and newlines)
+fn escape_text(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace(';', "\\;")
+    .replace(',', "\\,")
+    .replace("\r\n", "\\n")
+    .replace(['\n', '\r'], "\\n")
+}
+
+/// Fold a content line into chunks of at most 75 octets without splitting a character
+fn fold_line(line: &str) -> String {
+  let mut folded = String::new();
+  let mut width = 0;
+  for c in line.chars() {
+    if width + c.len_utf8() > 75 {
+      folded.push_str("\r\n ");
+      // The leading space counts toward the continuation line
+      width = 1;
+    }
+    folded.push(c);
+    width += c.len_utf8();
+  }
+  folded
+}
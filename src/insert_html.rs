@@ -1,124 +1,106 @@
+use crate::event::Event;
+use chrono::DateTime;
+use minijinja::{AutoEscape, Environment, Value};
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
 
+/// Format an RFC 3339 timestamp with a strftime-style format string
+///
+/// Usage in templates: `{{ event.time_begin_iso | datetime("%m月%d日 %H:%M") }}`
+fn datetime_filter(value: String, format: Option<String>) -> Result<Value, minijinja::Error> {
+  let parsed = DateTime::parse_from_rfc3339(&value).map_err(|e| {
+    minijinja::Error::new(
+      minijinja::ErrorKind::InvalidOperation,
+      format!("not an RFC 3339 timestamp: {value}"),
+    )
+    .with_source(e)
+  })?;
+  // Built from a valid timestamp and the template's own format string, so no escaping needed
+  Ok(Value::from_safe_string(
+    parsed
+      .format(format.as_deref().unwrap_or("%Y年%m月%d日 %H:%M"))
+      .to_string(),
+  ))
+}
+
+/// Human-readable time span of an event
+///
+/// Uses the normalized times when available (`2025年03月14日 14:00-16:00`)
+/// and falls back to the model's `time_begin` text otherwise.
+///
+/// Usage in templates: `{{ event | event_time }}`
+fn event_time_filter(event: Value) -> String {
+  let field = |name: &str| {
+    event
+      .get_attr(name)
+      .ok()
+      .and_then(|v| v.as_str().map(str::to_string))
+  };
+  let begin = field("time_begin_iso").and_then(|s| DateTime::parse_from_rfc3339(&s).ok());
+  let end = field("time_end_iso").and_then(|s| DateTime::parse_from_rfc3339(&s).ok());
+
+  match (begin, end) {
+    (Some(begin), Some(end)) if begin.date_naive() == end.date_naive() => format!(
+      "{}-{}",
+      begin.format("%Y年%m月%d日 %H:%M"),
+      end.format("%H:%M")
+    ),
+    (Some(begin), _) => begin.format("%Y年%m月%d日 %H:%M").to_string(),
+    (None, _) => field("time_begin").unwrap_or_default(),
+  }
+}
+
+/// Template environment with the filters event templates may use.
+///
+/// Event fields come from the model, which reads arbitrary emails, so every
+/// value is HTML-escaped regardless of the template's file name. Templates
+/// opt in to trusted markup per value with `{{ value | safe }}`.
+fn environment() -> Environment<'static> {
+  let mut env = Environment::new();
+  env.set_auto_escape_callback(|_| AutoEscape::Html);
+  env.add_filter("datetime", datetime_filter);
+  env.add_filter("event_time", event_time_filter);
+  env
+}
+
+/// Render events with a template.
+///
+/// The template receives `events`, a list of events with every field of
+/// `Event`, and owns all of their markup. Values are HTML-escaped unless the
+/// template marks them `| safe`.
+///
+/// # Arguments
+///
+/// * `events` - Events to render
+/// * `template` - Template source in minijinja (Jinja2) syntax
+///
+/// # Returns
+///
+/// The rendered document
+pub fn render_events_html(
+  events: &[Event],
+  template: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+  let mut env = environment();
+  env.add_template("announcement", template)?;
+
+  let html = env
+    .get_template("announcement")?
+    .render(minijinja::context! { events => events })?;
+  Ok(html)
+}
+
 /// Generates HTML content for events and saves it to a file
 pub async fn generate_events_html(
-  events: &Vec<serde_json::Value>,
+  events: &[Event],
   template_path: &str,
   output_path: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
   // Read template file
   let template = fs::read_to_string(template_path)?;
 
-  // Create event sections for each event
-  let mut event_sections = String::new();
-
-  for event in events {
-    let event_title = event
-      .get("event")
-      .and_then(|v| v.as_str())
-      .unwrap_or("未知事件");
-    let speaker_name = event
-      .get("speaker_name")
-      .and_then(|v| v.as_str())
-      .unwrap_or("");
-    let speaker_title = event
-      .get("speaker_title")
-      .and_then(|v| v.as_str())
-      .unwrap_or("");
-    let time_begin = event
-      .get("time_begin")
-      .and_then(|v| v.as_str())
-      .unwrap_or("");
-    let position = event.get("position").and_then(|v| v.as_str()).unwrap_or("");
-
-    // Create a section for this event
-    let event_section = format!(
-      r#"        <!-- Event -->
-        <section class="content-section box-sizing-border">
-          <p class="no-margin box-sizing-border">
-            <span class="section-title box-sizing-border"><strong class="box-sizing-border"> {event} </strong></span>
-          </p>
-          <p class="no-margin box-sizing-border">
-            报告人：{speaker_name} {speaker_title}
-          </p>
-          <p class="no-margin box-sizing-border">
-            时间:<span class="highlight-text box-sizing-border"> {time_begin} </span>
-          </p>
-          <p class="no-margin box-sizing-border">
-            地点: {position} </span>
-          </p>
-        </section>
-        <!-- Divider -->
-        <section class="divider box-sizing-border">
-          <section class="dotted-line box-sizing-border">
-            <svg viewbox="0 0 1 1" style="float:left;line-height:0;width:0;vertical-align:top;box-sizing:border-box;" xml:space="default"></svg>
-          </section>
-        </section>
-        "#,
-      event = event_title,
-      speaker_name = speaker_name,
-      speaker_title = speaker_title,
-      time_begin = time_begin,
-      position = position
-    );
-
-    event_sections.push_str(&event_section);
-  }
-
-  // Insert the event sections into the template
-  // First, mark the template type
-  let is_full_template = template_path.contains("full");
-
-  // Find the section to replace based on markers
-  let start_marker = "<!-- First Seminar -->";
-  let end_marker = if is_full_template {
-    "<!-- End of the first seminar -->"
-  } else {
-    "<!-- End of the first seminar -->"
-  };
-
-  // If we can find both markers, do a more precise replacement
-  let final_html = if let (Some(start_idx), Some(end_idx)) =
-    (template.find(start_marker), template.find(end_marker))
-  {
-    // Find the end of the end marker
-    let end_marker_end = end_idx + end_marker.len();
-
-    // Replace just the content between markers
-    format!(
-      "{}<!-- Events -->{}{}",
-      &template[0..start_idx + start_marker.len()],
-      &event_sections,
-      &template[end_marker_end..]
-    )
-  } else {
-    // Fallback to the old replacement method
-    template
-      .replace("<!-- First Seminar -->", "<!-- Events -->")
-      .replace(r#"        <section class="content-section box-sizing-border">
-          <p class="no-margin box-sizing-border">
-            <span class="section-title box-sizing-border"><strong class="box-sizing-border"> {event} </strong></span>
-          </p>
-          <p class="no-margin box-sizing-border">
-            报告人：{speaker_name} {speaker_title}
-          </p>
-          <p class="no-margin box-sizing-border">
-            时间:<span class="highlight-text box-sizing-border"> {time_begin} </span>
-          </p>
-          <p class="no-margin box-sizing-border">
-            地点: {position} </span>
-        </p>
-      </section>
-      <!-- Divider -->
-      <section class="divider box-sizing-border">
-        <section class="dotted-line box-sizing-border">
-          <svg viewbox="0 0 1 1" style="float:left;line-height:0;width:0;vertical-align:top;box-sizing:border-box;" xml:space="default"></svg>
-        </section>
-      </section>
-      <!-- End of the first seminar -->"#, &event_sections)
-  };
+  let final_html = render_events_html(events, &template)?;
 
   // Create output directory if it doesn't exist
   if let Some(parent) = Path::new(output_path).parent() {
@@ -3,4 +3,10 @@ pub mod config;
 pub mod data_sql;
 pub mod email;
 pub mod email_abstract;
+pub mod event;
+pub mod export;
+pub mod extract;
+pub mod ics;
 pub mod insert_html;
+pub mod progress;
+pub mod server;
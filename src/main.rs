@@ -1,79 +1,250 @@
 use clap::Parser;
-use data_sql::search_events_by_time_begin;
 use dotenv::dotenv;
 use indicatif::MultiProgress;
 
 pub mod api_req;
 pub mod cli;
 pub mod config;
+pub mod daemon;
 pub mod data_sql;
 pub mod email;
 pub mod email_abstract;
+pub mod event;
+pub mod export;
+pub mod extract;
+pub mod ics;
 pub mod insert_html;
+pub mod progress;
+pub mod server;
 
 /// Fetch emails with progress indication
-async fn fetch_emails_with_progress(
+fn fetch_emails_with_progress(
   m: &MultiProgress,
-  email_address: &str,
-  email_password: &str,
-  days: u64,
-  mail_server: &str,
-) -> Vec<email::EmailTable> {
-  let pb = cli::create_progress_bar(m, "Fetching emails...", "⠁⠂⠄⡀⢀⠠⠐⠈ ", "blue");
+  source: &dyn email::source::EmailSource,
+  filter: &email::EmailFilter,
+) -> Result<Vec<email::EmailTable>, Box<dyn std::error::Error>> {
+  let pb = progress::create_progress_bar(
+    m,
+    &format!("Fetching emails from {}...", source.name()),
+    "⠁⠂⠄⡀⢀⠠⠐⠈ ",
+    "blue",
+  );
 
-  let emails = email::fetch_emails(email_address, email_password, days, mail_server).await;
-  let num_emails = emails.len();
+  match source.fetch(filter) {
+    Ok(emails) => {
+      pb.finish_with_message(format!("✓ {} emails fetched successfully!", emails.len()));
+      Ok(emails)
+    }
+    Err(e) => {
+      pb.finish_with_message(format!("✗ Error: {}", e));
+      Err(e)
+    }
+  }
+}
+
+/// Fetch only emails newer than the saved sync state, with progress indication
+async fn fetch_new_emails_with_progress(
+  m: &MultiProgress,
+  opts: &QueryOptions,
+) -> Result<email::IncrementalFetch, Box<dyn std::error::Error>> {
+  let pb = progress::create_progress_bar(m, "Fetching new emails...", "⠁⠂⠄⡀⢀⠠⠐⠈ ", "blue");
+
+  let account = email::account_key(&opts.email_address, &opts.mail_server);
+  let previous = data_sql::get_sync_state(&account, email::MAILBOX, &opts.path_to_db).await?;
+  let result = email::fetch_emails_incremental(
+    &opts.email_address,
+    &opts.email_password,
+    &opts.mail_server,
+    previous.as_ref(),
+    opts.days,
+    &opts.filter,
+  )
+  .await;
 
-  pb.finish_with_message(format!("✓ {} emails fetched successfully!", num_emails));
-  emails
+  match result {
+    Ok(fetched) => {
+      pb.finish_with_message(format!(
+        "✓ {} new emails fetched (up to UID {})!",
+        fetched.emails.len(),
+        fetched.state.last_uid
+      ));
+      Ok(fetched)
+    }
+    Err(e) => {
+      pb.finish_with_message(format!("✗ Error: {}", e));
+      Err(e)
+    }
+  }
+}
+
+/// Print the estimated prompt size of every email
+fn report_email_tokens(m: &MultiProgress, emails: &[email::EmailTable]) {
+  let mut total = 0;
+  for email in emails {
+    let tokens = email_abstract::estimate_email_tokens(email);
+    total += tokens;
+    m.println(format!(
+      "  ~{:>6} tokens  {}  {}",
+      tokens, email.sender, email.subject
+    ))
+    .ok();
+  }
+  if !emails.is_empty() {
+    m.println(format!("  ~{:>6} tokens in total", total)).ok();
+  }
 }
 
-/// Generate email summary with progress indication
-fn generate_summary_with_progress(m: &MultiProgress, emails: &Vec<email::EmailTable>) -> String {
-  let pb = cli::create_progress_bar(m, "Generating email summary...", "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏", "green");
+/// Take the events of calendar invites, which need no model call, with progress indication
+///
+/// # Returns
+///
+/// The invite events, the events invites cancel, and the emails that still
+/// have to go to the model
+fn take_calendar_events_with_progress(
+  m: &MultiProgress,
+  emails: Vec<email::EmailTable>,
+) -> (Vec<event::Event>, Vec<event::Event>, Vec<email::EmailTable>) {
+  let pb = progress::create_progress_bar(m, "Reading calendar invites...", "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏", "cyan");
 
-  let prompt = email_abstract::generate_summary_prompt(emails);
+  let (invites, rest): (Vec<_>, Vec<_>) = emails
+    .into_iter()
+    .partition(|email| !email.calendar_events.is_empty() || !email.cancelled_events.is_empty());
+  let mut events = Vec::new();
+  let mut cancelled = Vec::new();
+  for email in invites {
+    events.extend(email.calendar_events);
+    cancelled.extend(email.cancelled_events);
+  }
 
-  pb.finish_with_message("✓ Summary generated!");
-  prompt
+  pb.finish_with_message(format!(
+    "✓ {} events read from calendar invites, {} cancelled!",
+    events.len(),
+    cancelled.len()
+  ));
+  (events, cancelled, rest)
 }
 
-/// Query API with progress indication
-async fn query_api_with_progress(
+/// Split emails into token-budgeted batches with progress indication
+fn batch_emails_with_progress(
   m: &MultiProgress,
-  prompt: &str,
-  api_key: &str,
-  model: &str,
-  max_tokens: i32,
-  temperature: f32,
-) -> Result<String, Box<dyn std::error::Error>> {
-  let pb = cli::create_progress_bar(m, "Querying API...", "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏", "yellow");
+  emails: &[email::EmailTable],
+  token_budget: usize,
+  batch_size: usize,
+) -> Result<Vec<Vec<email::EmailTable>>, Box<dyn std::error::Error>> {
+  let pb =
+    progress::create_progress_bar(m, "Splitting emails into batches...", "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏", "green");
 
-  let result = api_req::query_openai(prompt, api_key, model, max_tokens, temperature).await?;
+  match email_abstract::batch_emails(emails, token_budget, batch_size) {
+    Ok(batches) => {
+      pb.finish_with_message(format!(
+        "✓ {} emails split into {} batches!",
+        emails.len(),
+        batches.len()
+      ));
+      Ok(batches)
+    }
+    Err(e) => {
+      pb.finish_with_message(format!("✗ Error: {}", e));
+      Err(e)
+    }
+  }
+}
+
+/// Print the prompt of every batch with its token estimate, for `--dry-run`
+fn print_prompts(batches: &[Vec<email::EmailTable>], calendar_count: usize, opts: &QueryOptions) {
+  let unit = if opts.per_email { "email" } else { "batch" };
+  let mut total = 0;
+  for (i, batch) in batches.iter().enumerate() {
+    let prompt = email_abstract::generate_summary_prompt(batch);
+    let tokens = email_abstract::estimate_tokens(&prompt);
+    total += tokens;
+    println!(
+      "\n=== {unit} {}/{}: {} emails, ~{} prompt tokens ===\n{}",
+      i + 1,
+      batches.len(),
+      batch.len(),
+      tokens,
+      prompt
+    );
+  }
+
+  let cost = opts
+    .price
+    .map(|price| format!(", estimated prompt cost {:.4}", price.cost(total as u64, 0)))
+    .unwrap_or_default();
+  println!(
+    "\nDry run: {} requests with ~{} prompt tokens would be sent{}",
+    batches.len(),
+    total,
+    cost
+  );
+  if calendar_count > 0 {
+    println!(
+      "{} events from calendar invites would be stored without the model",
+      calendar_count
+    );
+  }
+}
+
+/// Print what storing the extracted events would change, for `--no-store`
+fn print_store_plan(plan: &[data_sql::StoreChange]) {
+  use data_sql::StoreChange;
 
-  pb.finish_with_message("✓ API response received!");
-  Ok(result)
+  let describe = |event: &event::Event| {
+    let id = event.id.map(|id| format!("#{} ", id)).unwrap_or_default();
+    format!(
+      "{}{}  {}  {}",
+      id, event.time_begin, event.event, event.position
+    )
+  };
+  let (mut inserts, mut updates, mut unchanged) = (0, 0, 0);
+  println!();
+  for change in plan {
+    match change {
+      StoreChange::Insert(event) => {
+        inserts += 1;
+        println!("+ {}", describe(event));
+      }
+      StoreChange::Update { stored, changes } => {
+        updates += 1;
+        println!("~ {}", describe(stored));
+        for change in changes {
+          println!(
+            "    {}: {:?} -> {:?}",
+            change.field, change.before, change.after
+          );
+        }
+      }
+      StoreChange::Unchanged(event) => {
+        unchanged += 1;
+        println!("= {}", describe(event));
+      }
+    }
+  }
+  println!(
+    "\nWould insert {}, update {}, leave {} unchanged; nothing was stored",
+    inserts, updates, unchanged
+  );
 }
 
 /// Store data in database with progress indication
 async fn store_data_with_progress(
   m: &MultiProgress,
-  result: &str,
+  events: Vec<event::Event>,
   path_to_db: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-  let pb = cli::create_progress_bar(
+  let pb = progress::create_progress_bar(
     m,
     "Processing and storing results...",
     "⣾⣽⣻⢿⡿⣟⣯⣷",
     "magenta",
   );
 
-  let events: Vec<serde_json::Value> = serde_json::from_str(result)?;
-
   match data_sql::store_json_to_db(events, path_to_db).await {
-    Ok((inserted, updated)) => {
+    Ok(stats) => {
       pb.finish_with_message(format!(
-        "✓ {inserted} rows inserted, {updated} rows updated in database!"
+        "✓ {} rows inserted, {} rows updated, {} unchanged in database!",
+        stats.inserted, stats.updated, stats.unchanged
       ));
       Ok(())
     }
@@ -84,35 +255,287 @@ async fn store_data_with_progress(
   }
 }
 
-/// Process emails and generate summary
-async fn process_query(
-  api_key: &str,
-  email_address: &str,
-  email_password: &str,
-  path_to_db: &str,
+/// Report the token usage of a run and record it for `stats`; the API has
+/// already been paid for, so a failure here only warns
+async fn record_run(run: &data_sql::Run, path_to_db: &str) {
+  let cost = run
+    .cost
+    .map(|cost| format!(", estimated cost {:.4}", cost))
+    .unwrap_or_default();
+  println!(
+    "✓ Used {} prompt and {} completion tokens{}",
+    run.prompt_tokens, run.completion_tokens, cost
+  );
+  if let Err(e) = data_sql::save_run(run, path_to_db).await {
+    eprintln!("✗ Could not record the run: {}", e);
+  }
+}
+
+/// Resolve CLI args, env vars and config into the provider and options of a run
+fn resolve_query(
+  args: cli::QueryArgs,
+  incremental: bool,
+) -> Result<(Box<dyn api_req::LlmProvider>, QueryOptions), Box<dyn std::error::Error>> {
+  // Get configuration values from CLI args or env vars
+  let (api_key, email_address, email_password, path_to_db) =
+    cli::get_config_values(args.api_key, args.mail_address, args.mail_pwd, args.db_path)?;
+
+  // Get config and override with CLI args if provided
+  let config = config::Config::get()?;
+  let mut provider = api_req::provider_from_config(&config, &api_key)?;
+  if !args.no_cache {
+    provider = Box::new(api_req::CachedProvider::new(
+      provider,
+      &path_to_db,
+      |reply| event::parse_events(reply).is_ok(),
+    ));
+  }
+
+  // The prompt template is sent with every batch, so it comes out of the budget
+  let template_tokens = email_abstract::estimate_tokens(&config.prompt);
+  if template_tokens >= config.token_budget {
+    return Err(
+      format!(
+        "token_budget ({}) does not cover the prompt template (~{} tokens), raise it",
+        config.token_budget, template_tokens
+      )
+      .into(),
+    );
+  }
+
+  let model = args.model.unwrap_or_else(|| config.model.clone());
+  let opts = QueryOptions {
+    email_address,
+    email_password,
+    path_to_db,
+    days: args.date.unwrap_or(config.dates),
+    price: config.prices.get(&model).copied(),
+    model,
+    max_tokens: args.max_tokens.unwrap_or(config.max_tokens),
+    temperature: args.temperature.unwrap_or(config.temperature),
+    mail_server: args.mail_server,
+    token_budget: config.token_budget - template_tokens,
+    batch_size: config.batch_size,
+    repair_attempts: config.repair_attempts,
+    per_email: args.per_email || config.per_email,
+    concurrency: args.concurrency.unwrap_or(config.concurrency).max(1),
+    incremental,
+    max_message_attempts: config.max_message_attempts.max(1),
+    dry_run: false,
+    no_store: false,
+    filter: email::EmailFilter::from_config(&config.filters)?,
+  };
+  Ok((provider, opts))
+}
+
+/// Build the email source selected with `--source`
+///
+/// # Arguments
+///
+/// * `days_ago` - The `--date` flag; local archives are only limited by date when it is given
+fn build_email_source(
+  kind: email::source::SourceKind,
+  path: Option<std::path::PathBuf>,
+  opts: &QueryOptions,
+  days_ago: Option<u64>,
+) -> Result<Box<dyn email::source::EmailSource>, Box<dyn std::error::Error>> {
+  use email::source::{EmlDirSource, ImapSource, MaildirSource, MboxSource, SourceKind};
+
+  if opts.incremental && kind != SourceKind::Imap {
+    return Err("--incremental is only supported for the IMAP source".into());
+  }
+  let local_path = || {
+    path
+      .clone()
+      .ok_or("--source-path is required for local sources")
+  };
+
+  Ok(match kind {
+    SourceKind::Imap => {
+      if opts.email_address.is_empty() || opts.email_password.is_empty() {
+        return Err(
+          "Email address or password not found, set MAIL_ADDRESS and MAIL_PASSWORD".into(),
+        );
+      }
+      Box::new(ImapSource {
+        email_address: opts.email_address.clone(),
+        password: opts.email_password.clone(),
+        imap_server: opts.mail_server.clone(),
+        days_ago: opts.days,
+      })
+    }
+    SourceKind::Maildir => Box::new(MaildirSource {
+      path: local_path()?,
+      days_ago,
+    }),
+    SourceKind::Mbox => Box::new(MboxSource {
+      path: local_path()?,
+      days_ago,
+    }),
+    SourceKind::Eml => Box::new(EmlDirSource {
+      path: local_path()?,
+      days_ago,
+    }),
+  })
+}
+
+/// Settings for a single `query` run, resolved from CLI args, env vars and config
+struct QueryOptions {
+  email_address: String,
+  email_password: String,
+  path_to_db: String,
   days: u64,
-  model: &str,
+  model: String,
   max_tokens: i32,
   temperature: f32,
-  mail_server: &str,
+  mail_server: String,
+  token_budget: usize,
+  batch_size: usize,
+  repair_attempts: u32,
+  per_email: bool,
+  concurrency: usize,
+  incremental: bool,
+  /// Failed runs after which a message is skipped, see `email::resume_uid`
+  max_message_attempts: u32,
+  /// Stop before calling the model, see `print_prompts`
+  dry_run: bool,
+  /// Print the store plan instead of storing, see `print_store_plan`
+  no_store: bool,
+  filter: email::EmailFilter,
+  /// Price of `model` from the config, if listed
+  price: Option<config::ModelPrice>,
+}
+
+/// Record this run's failed messages and report those that are now skipped
+///
+/// # Returns
+///
+/// The UID to resume from, see `email::resume_uid`
+async fn count_failures(
+  state: &data_sql::SyncState,
+  failures: &[(u32, String)],
+  opts: &QueryOptions,
+) -> Result<u32, Box<dyn std::error::Error>> {
+  let mut attempts = Vec::new();
+  for (uid, error) in failures {
+    let count = data_sql::record_uid_failure(state, *uid, error, &opts.path_to_db).await?;
+    if count >= opts.max_message_attempts {
+      eprintln!(
+        "✗ Skipping message {} after {} failed attempts: {}",
+        uid, count, error
+      );
+    }
+    attempts.push((*uid, count));
+  }
+  Ok(email::resume_uid(
+    state.last_uid,
+    &attempts,
+    opts.max_message_attempts,
+  ))
+}
+
+/// Process emails and generate summary
+async fn process_query(
+  provider: &dyn api_req::LlmProvider,
+  source: &dyn email::source::EmailSource,
+  opts: &QueryOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
   // Set up progress display
   let m = MultiProgress::new();
+  let started_at = chrono::Local::now();
 
   // Process emails
-  let emails =
-    fetch_emails_with_progress(&m, email_address, email_password, days, mail_server).await;
+  let (emails, sync_state, fetch_failures) = if opts.incremental {
+    let fetched = fetch_new_emails_with_progress(&m, opts).await?;
+    (fetched.emails, Some(fetched.state), fetched.failed)
+  } else {
+    let emails = fetch_emails_with_progress(&m, source, &opts.filter)?;
+    (emails, None, Vec::new())
+  };
 
-  // Generate summary
-  let prompt = generate_summary_with_progress(&m, &emails);
+  report_email_tokens(&m, &emails);
+  let email_count = emails.len();
 
-  // Query API
-  let api_result =
-    query_api_with_progress(&m, &prompt, api_key, model, max_tokens, temperature).await?;
-  println!("\n✓ API Result: {}", api_result);
+  // Emails with a calendar invite are stored as-is
+  let (calendar_events, cancelled_events, emails) = take_calendar_events_with_progress(&m, emails);
+  let calendar_count = calendar_events.len();
 
-  // Store data
-  store_data_with_progress(&m, &api_result, path_to_db).await?;
+  // Split into batches that fit the model's context
+  let batch_size = if opts.per_email { 1 } else { opts.batch_size };
+  let batches = batch_emails_with_progress(&m, &emails, opts.token_budget, batch_size)?;
+
+  if opts.dry_run {
+    print_prompts(&batches, calendar_count, opts);
+    return Ok(());
+  }
+
+  let mut unstored = Vec::new();
+  if opts.no_store {
+    unstored = calendar_events;
+  } else {
+    if calendar_count > 0 {
+      store_data_with_progress(&m, calendar_events, &opts.path_to_db).await?;
+    }
+    if !cancelled_events.is_empty() {
+      let deleted = data_sql::delete_events(&cancelled_events, &opts.path_to_db).await?;
+      println!("✓ Removed {} stored events that invites cancel", deleted);
+    }
+  }
+
+  let extract_opts = extract::ExtractOptions {
+    model: opts.model.clone(),
+    max_tokens: opts.max_tokens,
+    temperature: opts.temperature,
+    repair_attempts: opts.repair_attempts,
+    concurrency: opts.concurrency,
+    per_email: opts.per_email,
+    path_to_db: (!opts.no_store).then(|| opts.path_to_db.clone()),
+  };
+  let pb =
+    progress::create_count_progress_bar(&m, "Extracting events...", batches.len() as u64, "blue");
+  let mut extraction =
+    extract::extract_batches_with_progress(&m, &pb, provider, &batches, &extract_opts).await;
+
+  if email_count > 0 {
+    let usage = extraction.usage;
+    let run = data_sql::Run {
+      started_at: started_at.to_rfc3339(),
+      provider: provider.name().to_string(),
+      model: opts.model.clone(),
+      prompt_tokens: usage.prompt_tokens,
+      completion_tokens: usage.completion_tokens,
+      cost: opts
+        .price
+        .map(|price| price.cost(usage.prompt_tokens, usage.completion_tokens)),
+      email_count,
+      event_count: calendar_count + extraction.events,
+    };
+    record_run(&run, &opts.path_to_db).await;
+  }
+
+  if opts.no_store {
+    unstored.append(&mut extraction.unstored);
+    let plan = data_sql::plan_store(&unstored, &opts.path_to_db).await?;
+    print_store_plan(&plan);
+  }
+
+  // With `--no-store` nothing was stored, so the sync position stays where it
+  // is; otherwise it only moves past messages that are stored or skipped
+  if let (Some(mut state), false) = (sync_state, opts.no_store) {
+    let failures = [fetch_failures.as_slice(), &extraction.failed_uids].concat();
+    state.last_uid = count_failures(&state, &failures, opts).await?;
+    data_sql::save_sync_state(&state, &opts.path_to_db).await?;
+  }
+
+  if !fetch_failures.is_empty() {
+    return Err(format!("{} messages could not be fetched", fetch_failures.len()).into());
+  }
+  if extraction.failed > 0 {
+    return Err(format!("{} of {} batches failed", extraction.failed, batches.len()).into());
+  }
+  if opts.no_store {
+    return Ok(());
+  }
 
   println!("\n✅ Process completed successfully!");
   Ok(())
@@ -125,50 +548,229 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
   match app.command {
     cli::Commands::Query {
-      date,
-      api_key,
-      mail_address,
-      mail_pwd,
+      args,
+      incremental,
+      source,
+      source_path,
+      dry_run,
+      no_store,
+    } => {
+      let date = args.date;
+      let (provider, mut opts) = resolve_query(args, incremental)?;
+      opts.dry_run = dry_run;
+      opts.no_store = no_store;
+      let source = build_email_source(source, source_path, &opts, date)?;
+      process_query(provider.as_ref(), source.as_ref(), &opts).await?;
+    }
+    cli::Commands::Daemon {
+      args,
+      poll_interval,
+      max_backoff,
+    } => {
+      let (provider, opts) = resolve_query(args, true)?;
+      let settings = daemon::DaemonSettings {
+        poll_interval: std::time::Duration::from_secs(poll_interval.max(1)),
+        max_backoff: std::time::Duration::from_secs(max_backoff.max(1)),
+      };
+      daemon::run(provider.as_ref(), &opts, &settings).await?;
+      // Dropping the runtime would wait for the abandoned blocking IDLE wait
+      std::process::exit(0);
+    }
+    cli::Commands::Search {
+      query,
+      from,
+      to,
+      speaker,
+      position,
+      sender,
+      keyword,
+      text,
+      sort,
+      desc,
+      limit,
       db_path,
-      model,
-      max_tokens,
-      temperature,
-      mail_server,
     } => {
-      // Get configuration values from CLI args or env vars
-      let (api_key, email_address, email_password, path_to_db) =
-        cli::get_config_values(api_key, mail_address, mail_pwd, db_path)?;
-
-      // Get config and override with CLI args if provided
-      let config = config::Config::get();
-      let days = date.unwrap_or(config.dates);
-      let model = model.unwrap_or_else(|| config.model.clone());
-      let max_tokens = max_tokens.unwrap_or(config.max_tokens);
-      let temperature = temperature.unwrap_or(config.temperature);
-
-      process_query(
-        &api_key,
-        &email_address,
-        &email_password,
-        &path_to_db,
-        days,
-        &model,
-        max_tokens,
-        temperature,
-        &mail_server,
-      )
-      .await?;
+      let path_to_db =
+        db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+
+      let query = data_sql::EventQuery {
+        from,
+        to,
+        time_begin: query,
+        speaker,
+        position,
+        sender,
+        keyword,
+        sort,
+        descending: desc,
+        limit,
+        ..Default::default()
+      };
+      if let Some(text) = text {
+        let matches = data_sql::search_events_fulltext(&text, &query, &path_to_db).await?;
+        println!("Found {} events matching '{}':", matches.len(), text);
+        for m in matches {
+          println!("\n{}", m.snippet);
+          println!("{}", serde_json::to_string_pretty(&m.event)?);
+        }
+      } else {
+        let events = data_sql::search_events(&query, &path_to_db).await?;
+        println!("Found {} events:", events.len());
+        for event in events {
+          println!("{}", serde_json::to_string_pretty(&event)?);
+        }
+      }
+    }
+    cli::Commands::Export {
+      format,
+      output,
+      from,
+      to,
+      db_path,
+    } => {
+      let path_to_db =
+        db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+      let format = format
+        .or_else(|| output.as_deref().and_then(export::ExportFormat::from_path))
+        .unwrap_or(export::ExportFormat::Json);
+
+      let query = data_sql::EventQuery {
+        from,
+        to,
+        sort: data_sql::SortKey::Time,
+        ..Default::default()
+      };
+      let events = data_sql::search_events(&query, &path_to_db).await?;
+      let contents = export::write_events(&events, format)?;
+      match output {
+        Some(path) => {
+          std::fs::write(&path, contents)?;
+          eprintln!("✓ Exported {} events to {}", events.len(), path.display());
+        }
+        None => print!("{}", contents),
+      }
     }
-    cli::Commands::Search { query, db_path } => {
+    cli::Commands::Import {
+      file,
+      format,
+      db_path,
+    } => {
       let path_to_db =
         db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+      let format = format
+        .or_else(|| export::ExportFormat::from_path(&file))
+        .ok_or("Cannot tell the format from the file extension, pass --format")?;
 
-      let events = search_events_by_time_begin(&query, &path_to_db).await?;
-      println!("Found {} events containing '{}':", events.len(), query);
-      for event in events {
-        println!("{}", serde_json::to_string_pretty(&event)?);
+      let contents = std::fs::read_to_string(&file)
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+      let events = export::read_events(&contents, format)
+        .map_err(|e| format!("Failed to parse {}: {}", file.display(), e))?;
+      let stats = data_sql::store_json_to_db(events, &path_to_db).await?;
+      println!(
+        "✓ Imported {} new, {} updated and {} unchanged events from {}",
+        stats.inserted,
+        stats.updated,
+        stats.unchanged,
+        file.display()
+      );
+    }
+    cli::Commands::Serve { addr, db_path } => {
+      let path_to_db =
+        db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+      server::serve(&addr, &path_to_db).await?;
+    }
+    cli::Commands::Cache { action } => match action {
+      cli::CacheCommand::Prune {
+        older_than,
+        db_path,
+      } => {
+        let path_to_db =
+          db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+        let deleted =
+          data_sql::prune_cache(chrono::Duration::days(older_than.into()), &path_to_db).await?;
+        println!("✓ Removed {} cached responses", deleted);
+      }
+    },
+    cli::Commands::Stats { db_path } => {
+      let path_to_db =
+        db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+      let spend = data_sql::spend_by_week(&path_to_db).await?;
+      if spend.is_empty() {
+        println!("No runs recorded yet");
+        return Ok(());
+      }
+      let cost = |cost: Option<f64>| cost.map_or_else(|| "-".to_string(), |c| format!("{:.4}", c));
+      println!(
+        "{:<10}  {:<24}  {:>5}  {:>12}  {:>12}  {:>10}  {:>6}  {:>6}",
+        "week", "model", "runs", "prompt", "completion", "cost", "emails", "events"
+      );
+      for row in &spend {
+        println!(
+          "{:<10}  {:<24}  {:>5}  {:>12}  {:>12}  {:>10}  {:>6}  {:>6}",
+          row.week,
+          row.model,
+          row.runs,
+          row.prompt_tokens,
+          row.completion_tokens,
+          cost(row.cost),
+          row.email_count,
+          row.event_count
+        );
       }
+      let total: Vec<f64> = spend.iter().filter_map(|row| row.cost).collect();
+      println!(
+        "\nTotal: {} prompt and {} completion tokens, estimated cost {}",
+        spend.iter().map(|row| row.prompt_tokens).sum::<u64>(),
+        spend.iter().map(|row| row.completion_tokens).sum::<u64>(),
+        cost((!total.is_empty()).then(|| total.iter().sum()))
+      );
     }
+    cli::Commands::Db { action } => match action {
+      cli::DbCommand::Migrate { db_path } => {
+        let path_to_db =
+          db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+        let mut conn = rusqlite::Connection::open(&path_to_db)?;
+        let applied = data_sql::migrations::migrate(&mut conn)?;
+        if applied.is_empty() {
+          println!(
+            "✓ Schema is up to date (version {})",
+            data_sql::migrations::latest_version()
+          );
+        }
+        for migration in applied {
+          println!(
+            "✓ Applied migration {}: {}",
+            migration.version, migration.description
+          );
+        }
+      }
+      cli::DbCommand::Status { db_path } => {
+        let path_to_db =
+          db_path.unwrap_or_else(|| dotenv::var("PATH_TO_DB").expect("Path to DB not found"));
+        // Read-only: open_db would migrate, and a missing file should not be created
+        let conn = rusqlite::Connection::open_with_flags(
+          &path_to_db,
+          rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        println!(
+          "Schema version {} of {}",
+          data_sql::migrations::current_version(&conn)?,
+          data_sql::migrations::latest_version()
+        );
+        for migration in data_sql::migrations::applied(&conn)? {
+          println!(
+            "  ✓ {} {} (applied {})",
+            migration.version, migration.description, migration.applied_at
+          );
+        }
+        for migration in data_sql::migrations::pending(&conn)? {
+          println!(
+            "  · {} {} (pending)",
+            migration.version, migration.description
+          );
+        }
+      }
+    },
     cli::Commands::Generate {
       date,
       db_path,
@@ -0,0 +1,42 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::time::Duration;
+
+/// Create a styled progress bar
+pub fn create_progress_bar(
+  m: &MultiProgress,
+  message: &str,
+  spinner_style: &str,
+  color: &str,
+) -> ProgressBar {
+  let pb = m.add(ProgressBar::new_spinner());
+  pb.set_style(
+    ProgressStyle::default_spinner()
+      .tick_chars(spinner_style)
+      .template(&format!("{{spinner:.{color}}} {{msg}}"))
+      .unwrap(),
+  );
+  pb.set_message(message.to_string());
+  pb.enable_steady_tick(Duration::from_millis(100));
+  pb
+}
+
+/// Create a progress bar counting finished items out of `total`
+pub fn create_count_progress_bar(
+  m: &MultiProgress,
+  message: &str,
+  total: u64,
+  color: &str,
+) -> ProgressBar {
+  let pb = m.add(ProgressBar::new(total));
+  pb.set_style(
+    ProgressStyle::default_bar()
+      .template(&format!(
+        "{{spinner:.{color}}} [{{bar:30.{color}}}] {{pos}}/{{len}} {{msg}}"
+      ))
+      .unwrap()
+      .progress_chars("=> "),
+  );
+  pb.set_message(message.to_string());
+  pb.enable_steady_tick(Duration::from_millis(100));
+  pb
+}
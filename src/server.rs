@@ -0,0 +1,356 @@
+use crate::data_sql::{self, EventQuery, SortKey};
+use crate::event::Event;
+use crate::ics;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Page size when the client does not ask for one
+pub const DEFAULT_PER_PAGE: usize = 50;
+/// Largest page size a client may ask for
+pub const MAX_PER_PAGE: usize = 200;
+
+#[derive(Clone)]
+struct AppState {
+  path_to_db: Arc<str>,
+}
+
+/// Routes of the read-only events API.
+///
+/// * `GET /events` - events by date range and field filters, paginated
+/// * `GET /events.ics` - the same filters as an iCalendar feed, unpaginated
+/// * `GET /events/upcoming` - the next events from now on
+/// * `GET /events/{id}` - one event
+/// * `GET /search?q=...` - full-text search, best match first, paginated
+///
+/// Every response carries an `ETag`; a request whose `If-None-Match`
+/// matches gets an empty `304 Not Modified`.
+pub fn router(path_to_db: &str) -> Router {
+  Router::new()
+    .route("/events", get(list_events))
+    .route("/events.ics", get(calendar_feed))
+    .route("/events/upcoming", get(upcoming_events))
+    .route("/events/{id}", get(get_event))
+    .route("/search", get(search))
+    .with_state(AppState {
+      path_to_db: Arc::from(path_to_db),
+    })
+}
+
+/// Serve the API on `addr` until Ctrl-C or SIGTERM
+pub async fn serve(addr: &str, path_to_db: &str) -> Result<(), Box<dyn std::error::Error>> {
+  // Create the tables up front so the first request does not pay for it
+  data_sql::open_db(path_to_db)?;
+  let listener = tokio::net::TcpListener::bind(addr).await?;
+  println!("Serving events on http://{}", listener.local_addr()?);
+  axum::serve(listener, router(path_to_db))
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+  Ok(())
+}
+
+async fn shutdown_signal() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+      Ok(mut terminate) => {
+        tokio::select! {
+          _ = terminate.recv() => {}
+          _ = tokio::signal::ctrl_c() => {}
+        }
+      }
+      Err(_) => {
+        tokio::signal::ctrl_c().await.ok();
+      }
+    }
+  }
+  #[cfg(not(unix))]
+  tokio::signal::ctrl_c().await.ok();
+}
+
+/// An error as a JSON body, `{"error": "..."}`
+struct ApiError {
+  status: StatusCode,
+  message: String,
+}
+
+impl ApiError {
+  fn bad_request(message: impl Into<String>) -> Self {
+    Self {
+      status: StatusCode::BAD_REQUEST,
+      message: message.into(),
+    }
+  }
+
+  fn not_found(message: impl Into<String>) -> Self {
+    Self {
+      status: StatusCode::NOT_FOUND,
+      message: message.into(),
+    }
+  }
+}
+
+impl From<Box<dyn std::error::Error>> for ApiError {
+  fn from(e: Box<dyn std::error::Error>) -> Self {
+    Self {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      message: e.to_string(),
+    }
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    let body = serde_json::json!({ "error": self.message });
+    (self.status, axum::Json(body)).into_response()
+  }
+}
+
+/// Serialize `value` with an ETag, or answer 304 if the client already has it
+fn json_with_etag(headers: &HeaderMap, value: &impl Serialize) -> Result<Response, ApiError> {
+  let body = serde_json::to_vec(value).map_err(|e| ApiError {
+    status: StatusCode::INTERNAL_SERVER_ERROR,
+    message: e.to_string(),
+  })?;
+  Ok(with_etag(headers, body, "application/json"))
+}
+
+/// Send `body` with an ETag, or answer 304 if the client already has it
+fn with_etag(headers: &HeaderMap, body: Vec<u8>, content_type: &'static str) -> Response {
+  let digest = Sha256::digest(&body);
+  let etag = format!(
+    "\"{}\"",
+    digest[..16]
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect::<String>()
+  );
+
+  let client_has_it = headers
+    .get_all(header::IF_NONE_MATCH)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .map(|tag| tag.trim().trim_start_matches("W/"))
+    .any(|tag| tag == etag || tag == "*");
+
+  let etag = HeaderValue::from_str(&etag).expect("hex ETag is a valid header value");
+  // Make caches revalidate every time; the ETag keeps that cheap
+  let cache_control = HeaderValue::from_static("no-cache");
+  let mut response = if client_has_it {
+    StatusCode::NOT_MODIFIED.into_response()
+  } else {
+    (
+      [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+      body,
+    )
+      .into_response()
+  };
+  let response_headers = response.headers_mut();
+  response_headers.insert(header::ETAG, etag);
+  response_headers.insert(header::CACHE_CONTROL, cache_control);
+  // Read-only public data, so embedding widgets on other origins may read it
+  response_headers.insert(
+    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+    HeaderValue::from_static("*"),
+  );
+  response
+}
+
+/// `page` (1-based) and `per_page` query parameters, extracted separately
+/// because `serde(flatten)` loses the number parsing of query strings
+#[derive(Debug, Deserialize)]
+struct Pagination {
+  page: Option<usize>,
+  per_page: Option<usize>,
+}
+
+impl Pagination {
+  /// Validated page number and size, and the number of results before the page
+  fn resolve(&self) -> Result<(usize, usize, usize), ApiError> {
+    let page = self.page.unwrap_or(1);
+    let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page == 0 {
+      return Err(ApiError::bad_request("page starts at 1"));
+    }
+    if per_page == 0 || per_page > MAX_PER_PAGE {
+      return Err(ApiError::bad_request(format!(
+        "per_page must be between 1 and {}",
+        MAX_PER_PAGE
+      )));
+    }
+    // SQLite offsets are signed 64-bit integers
+    let offset = (page - 1)
+      .checked_mul(per_page)
+      .filter(|offset| i64::try_from(*offset).is_ok())
+      .ok_or_else(|| ApiError::bad_request("page is too large"))?;
+    Ok((page, per_page, offset))
+  }
+}
+
+/// A page of results
+#[derive(Debug, Serialize)]
+struct Page<T> {
+  items: Vec<T>,
+  page: usize,
+  per_page: usize,
+  total: usize,
+}
+
+fn parse_date(name: &str, value: Option<&str>) -> Result<Option<NaiveDate>, ApiError> {
+  value
+    .map(|value| {
+      NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| ApiError::bad_request(format!("{} must be a date like 2025-03-14", name)))
+    })
+    .transpose()
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+  from: Option<String>,
+  to: Option<String>,
+  speaker: Option<String>,
+  position: Option<String>,
+  sender: Option<String>,
+  keyword: Option<String>,
+}
+
+async fn list_events(
+  State(state): State<AppState>,
+  Query(params): Query<ListParams>,
+  Query(pagination): Query<Pagination>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let (page, per_page, offset) = pagination.resolve()?;
+  let query = EventQuery {
+    from: parse_date("from", params.from.as_deref())?,
+    to: parse_date("to", params.to.as_deref())?,
+    speaker: params.speaker,
+    position: params.position,
+    sender: params.sender,
+    keyword: params.keyword,
+    sort: SortKey::Time,
+    limit: Some(per_page),
+    offset: Some(offset),
+    ..Default::default()
+  };
+
+  let total = data_sql::count_events(&query, &state.path_to_db).await?;
+  let items = data_sql::search_events(&query, &state.path_to_db).await?;
+  json_with_etag(
+    &headers,
+    &Page {
+      items,
+      page,
+      per_page,
+      total,
+    },
+  )
+}
+
+async fn calendar_feed(
+  State(state): State<AppState>,
+  Query(params): Query<ListParams>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let query = EventQuery {
+    from: parse_date("from", params.from.as_deref())?,
+    to: parse_date("to", params.to.as_deref())?,
+    speaker: params.speaker,
+    position: params.position,
+    sender: params.sender,
+    keyword: params.keyword,
+    sort: SortKey::Time,
+    ..Default::default()
+  };
+
+  let events = data_sql::search_events(&query, &state.path_to_db).await?;
+  Ok(with_etag(
+    &headers,
+    ics::events_to_ics(&events).into_bytes(),
+    "text/calendar; charset=utf-8",
+  ))
+}
+
+#[derive(Debug, Deserialize)]
+struct UpcomingParams {
+  limit: Option<usize>,
+}
+
+async fn upcoming_events(
+  State(state): State<AppState>,
+  Query(params): Query<UpcomingParams>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let limit = params.limit.unwrap_or(10);
+  if limit == 0 || limit > MAX_PER_PAGE {
+    return Err(ApiError::bad_request(format!(
+      "limit must be between 1 and {}",
+      MAX_PER_PAGE
+    )));
+  }
+  let query = EventQuery {
+    since: Some(chrono::Utc::now().fixed_offset()),
+    sort: SortKey::Time,
+    limit: Some(limit),
+    ..Default::default()
+  };
+
+  let events: Vec<Event> = data_sql::search_events(&query, &state.path_to_db).await?;
+  json_with_etag(&headers, &events)
+}
+
+async fn get_event(
+  State(state): State<AppState>,
+  Path(id): Path<String>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let id: i64 = id
+    .parse()
+    .map_err(|_| ApiError::bad_request("event id must be an integer"))?;
+  match data_sql::get_event(id, &state.path_to_db).await? {
+    Some(event) => json_with_etag(&headers, &event),
+    None => Err(ApiError::not_found(format!("no event with id {}", id))),
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+  q: Option<String>,
+}
+
+async fn search(
+  State(state): State<AppState>,
+  Query(params): Query<SearchParams>,
+  Query(pagination): Query<Pagination>,
+  headers: HeaderMap,
+) -> Result<Response, ApiError> {
+  let (page, per_page, offset) = pagination.resolve()?;
+  let text = params
+    .q
+    .filter(|q| !q.trim().is_empty())
+    .ok_or_else(|| ApiError::bad_request("q is required"))?;
+
+  // Ranked results cannot be counted separately, so page through them here
+  let matches =
+    data_sql::search_events_fulltext(&text, &EventQuery::default(), &state.path_to_db).await?;
+  let total = matches.len();
+  let items = matches.into_iter().skip(offset).take(per_page).collect();
+  json_with_etag(
+    &headers,
+    &Page {
+      items,
+      page,
+      per_page,
+      total,
+    },
+  )
+}
@@ -1,47 +1,292 @@
-//// TODO: I dont know how to test this yet
-
-// use email_abstract_rs::api_req::query_openai;
-// use email_abstract_rs::config::Config;
-// use mockito::{mock, server_url};
-//
-// // #[tokio::test]
-// async fn test_query_openai_success() {
-//   let mock_server = mock("POST", "/v1/chat/completions")
-//     .with_status(200)
-//     .with_header("content-type", "application/json")
-//     .with_body(r#"{"choices":[{"message":{"content":"Test response"}}]}"#)
-//     .create();
-//
-//   let result = query_openai(
-//     "Test prompt",
-//     &Config {
-//       api_base_url: Some(server_url()),
-//       ..Config::default()
-//     },
-//   )
-//   .await;
-//
-//   assert!(result.is_ok());
-//   assert_eq!(result.unwrap(), "Test response");
-//   mock_server.assert();
-// }
-//
-// // #[tokio::test]
-// async fn test_error_handling() {
-//   let mock_server = mock("POST", "/v1/chat/completions")
-//     .with_status(401)
-//     .with_body("Unauthorized")
-//     .create();
-//
-//   let result = query_openai(
-//     "Test prompt",
-//     &Config {
-//       api_base_url: Some(server_url()),
-//       ..Config::default()
-//     },
-//   )
-//   .await;
-//
-//   assert!(result.is_err());
-//   mock_server.assert();
-// }
+use email_abstract_rs::api_req::{
+  cache_key, parse_retry_after, provider_from_config, ApiError, CachedProvider, CompletionRequest,
+  LlmProvider, RetryPolicy, TokenUsage,
+};
+use email_abstract_rs::config::{Config, ProviderKind};
+use mockito::{mock, server_url, Matcher};
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+fn request() -> CompletionRequest {
+  CompletionRequest {
+    prompt: "Test prompt".to_string(),
+    model: "test-model".to_string(),
+    max_tokens: 16,
+    temperature: 0.0,
+  }
+}
+
+fn config(provider: ProviderKind) -> Config {
+  Config {
+    provider,
+    base_url: Some(server_url()),
+    ..Config::default()
+  }
+}
+
+#[tokio::test]
+async fn test_openai_provider_success() {
+  let mock_server = mock("POST", "/chat/completions")
+    .match_header("authorization", "Bearer test-key")
+    .match_header("x-team", "physics")
+    .match_body(Matcher::PartialJsonString(
+      r#"{"model":"test-model","messages":[{"role":"user","content":"Test prompt"}]}"#.to_string(),
+    ))
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(
+      r#"{"choices":[{"message":{"content":"Test response"}}],"usage":{"prompt_tokens":12,"completion_tokens":3}}"#,
+    )
+    .create();
+
+  let mut config = config(ProviderKind::OpenAi);
+  config
+    .headers
+    .insert("X-Team".to_string(), "physics".to_string());
+  let provider = provider_from_config(&config, "test-key").unwrap();
+  let result = provider.complete(&request()).await;
+
+  let completion = result.unwrap();
+  assert_eq!(completion.text, "Test response");
+  assert_eq!(
+    completion.usage,
+    TokenUsage {
+      prompt_tokens: 12,
+      completion_tokens: 3
+    }
+  );
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_ollama_provider_success() {
+  let mock_server = mock("POST", "/api/chat")
+    .match_body(Matcher::PartialJsonString(
+      r#"{"model":"test-model","stream":false,"options":{"num_predict":16}}"#.to_string(),
+    ))
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(
+      r#"{"message":{"role":"assistant","content":"Ollama response"},"done":true,"prompt_eval_count":20,"eval_count":5}"#,
+    )
+    .create();
+
+  let provider = provider_from_config(&config(ProviderKind::Ollama), "").unwrap();
+  let result = provider.complete(&request()).await;
+
+  let completion = result.unwrap();
+  assert_eq!(completion.text, "Ollama response");
+  assert_eq!(completion.usage.prompt_tokens, 20);
+  assert_eq!(completion.usage.completion_tokens, 5);
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_anthropic_provider_success() {
+  let mock_server = mock("POST", "/messages")
+    .match_header("x-api-key", "test-key")
+    .match_header("anthropic-version", Matcher::Any)
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(
+      r#"{"content":[{"type":"text","text":"Anthropic "},{"type":"text","text":"response"}],"usage":{"input_tokens":30,"output_tokens":7}}"#,
+    )
+    .create();
+
+  let provider = provider_from_config(&config(ProviderKind::Anthropic), "test-key").unwrap();
+  let result = provider.complete(&request()).await;
+
+  let completion = result.unwrap();
+  assert_eq!(completion.text, "Anthropic response");
+  assert_eq!(completion.usage.prompt_tokens, 30);
+  assert_eq!(completion.usage.completion_tokens, 7);
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_error_handling() {
+  let mock_server = mock("POST", "/chat/completions")
+    .with_status(401)
+    .with_body("Unauthorized")
+    .create();
+
+  let provider = provider_from_config(&config(ProviderKind::OpenAi), "bad-key").unwrap();
+  let result = provider.complete(&request()).await;
+
+  assert!(result.is_err());
+  let err = result.unwrap_err();
+  assert!(err.to_string().contains("401"));
+  // Auth failures are not retried
+  assert!(matches!(
+    err.downcast_ref::<ApiError>(),
+    Some(ApiError::Auth { .. })
+  ));
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_retries_after_rate_limit() {
+  let rate_limited = mock("POST", "/messages")
+    .with_status(429)
+    .with_header("retry-after", "0")
+    .with_body("slow down")
+    .expect(1)
+    .create();
+  let success = mock("POST", "/messages")
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(r#"{"content":[{"type":"text","text":"After retry"}]}"#)
+    .create();
+
+  let provider = provider_from_config(&config(ProviderKind::Anthropic), "test-key").unwrap();
+  let result = provider.complete(&request()).await;
+
+  let completion = result.unwrap();
+  assert_eq!(completion.text, "After retry");
+  // A reply without a usage block counts as zero tokens
+  assert_eq!(completion.usage, TokenUsage::default());
+  rate_limited.assert();
+  success.assert();
+}
+
+#[tokio::test]
+async fn test_gives_up_after_max_attempts() {
+  let mock_server = mock("POST", "/api/chat")
+    .with_status(503)
+    .with_header("retry-after", "0")
+    .with_body("overloaded")
+    .expect(3)
+    .create();
+
+  let config = Config {
+    max_attempts: 3,
+    ..config(ProviderKind::Ollama)
+  };
+  let provider = provider_from_config(&config, "").unwrap();
+  let err = provider.complete(&request()).await.unwrap_err();
+
+  let api_error = err.downcast_ref::<ApiError>().unwrap();
+  assert!(api_error.is_transient());
+  assert!(err.to_string().contains("503"));
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_long_retry_after_is_not_retried() {
+  let mock_server = mock("POST", "/api/chat")
+    .with_status(429)
+    .with_header("retry-after", "3600")
+    .with_body("daily quota exceeded")
+    .expect(1)
+    .create();
+
+  let config = Config {
+    max_retry_after: 60,
+    ..config(ProviderKind::Ollama)
+  };
+  let provider = provider_from_config(&config, "").unwrap();
+  let err = provider.complete(&request()).await.unwrap_err();
+
+  assert!(err.to_string().contains("429"));
+  mock_server.assert();
+
+  let policy = RetryPolicy::from_config(&config);
+  assert!(policy.should_retry(1, Some(Duration::from_secs(60))));
+  assert!(!policy.should_retry(1, Some(Duration::from_secs(61))));
+  assert!(!policy.should_retry(config.max_attempts, None));
+}
+
+#[test]
+fn test_retry_delay() {
+  let policy = RetryPolicy {
+    max_attempts: 5,
+    base_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(4),
+    max_retry_after: Duration::from_secs(60),
+  };
+  for _ in 0..20 {
+    let first = policy.delay(1, None);
+    assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+    let third = policy.delay(3, None);
+    assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+    // Capped at max_delay
+    assert!(policy.delay(10, None) <= Duration::from_secs(4));
+  }
+  // The server's Retry-After wins, even above max_delay
+  assert_eq!(
+    policy.delay(1, Some(Duration::from_secs(30))),
+    Duration::from_secs(30)
+  );
+
+  assert_eq!(parse_retry_after("12"), Some(Duration::from_secs(12)));
+  assert_eq!(
+    parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+    Some(Duration::ZERO)
+  );
+  assert_eq!(parse_retry_after("soon"), None);
+}
+
+#[tokio::test]
+async fn test_cached_provider_reuses_responses() {
+  let mock_server = mock("POST", "/chat/completions")
+    .match_header("authorization", "Bearer cache-key")
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(
+      r#"{"choices":[{"message":{"content":"Cached response"}}],"usage":{"prompt_tokens":12,"completion_tokens":3}}"#,
+    )
+    .expect(2)
+    .create();
+
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  let inner = provider_from_config(&config(ProviderKind::OpenAi), "cache-key").unwrap();
+  let provider = CachedProvider::new(inner, db_path, |reply| reply.starts_with("Cached"));
+
+  let first = provider.complete(&request()).await.unwrap();
+  assert_eq!(first.text, "Cached response");
+  assert_eq!(first.usage.prompt_tokens, 12);
+  // Served from the cache, so nothing is billed
+  let second = provider.complete(&request()).await.unwrap();
+  assert_eq!(second.text, "Cached response");
+  assert_eq!(second.usage, TokenUsage::default());
+  // A different temperature is a different request
+  let warmer = CompletionRequest {
+    temperature: 0.5,
+    ..request()
+  };
+  assert_ne!(
+    cache_key("openai", "http://localhost:8000", &warmer),
+    cache_key("openai", "http://localhost:8000", &request())
+  );
+  // So is the same request to another server
+  assert_ne!(
+    cache_key("openai", "http://localhost:8000", &request()),
+    cache_key("openai", "https://api.deepseek.com/v1", &request())
+  );
+  provider.complete(&warmer).await.unwrap();
+
+  mock_server.assert();
+}
+
+#[tokio::test]
+async fn test_cached_provider_skips_invalid_responses() {
+  let mock_server = mock("POST", "/chat/completions")
+    .match_header("authorization", "Bearer invalid-cache-key")
+    .with_status(200)
+    .with_header("content-type", "application/json")
+    .with_body(r#"{"choices":[{"message":{"content":"not json"}}]}"#)
+    .expect(2)
+    .create();
+
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  let inner = provider_from_config(&config(ProviderKind::OpenAi), "invalid-cache-key").unwrap();
+  let provider = CachedProvider::new(inner, db_path, |reply| reply.starts_with('['));
+
+  // A reply that fails validation is asked for again instead of replayed
+  provider.complete(&request()).await.unwrap();
+  provider.complete(&request()).await.unwrap();
+
+  mock_server.assert();
+}
@@ -1,4 +1,4 @@
-use email_abstract_rs::config::Config;
+use email_abstract_rs::config::{Config, ProviderKind};
 use std::fs;
 
 #[test]
@@ -28,3 +28,99 @@ dates = 11134
   // Clean up
   fs::remove_file("test_config.toml").expect("Failed to remove test file");
 }
+
+#[test]
+fn test_load_provider_config_from_file() {
+  let test_config = r#"
+provider = "ollama"
+base_url = "http://localhost:11434"
+max_attempts = 6
+request_timeout = 600
+per_email = true
+concurrency = 8
+max_message_attempts = 5
+
+[prices.llama3]
+prompt = 0.5
+completion = 2
+
+[headers]
+X-Team = "physics"
+"#;
+
+  fs::write("test_provider_config.toml", test_config).expect("Failed to write test config file");
+
+  let config = Config::load_from_file("test_provider_config.toml").expect("Failed to load config");
+
+  assert_eq!(config.provider, ProviderKind::Ollama);
+  assert_eq!(config.base_url.as_deref(), Some("http://localhost:11434"));
+  assert_eq!(
+    config.headers.get("X-Team").map(String::as_str),
+    Some("physics")
+  );
+  let price = config.prices["llama3"];
+  assert_eq!(price.completion, 2.0);
+  assert!((price.cost(1_000_000, 500_000) - 1.5).abs() < 1e-9);
+  assert!(config.per_email);
+  assert_eq!(config.concurrency, 8);
+  assert_eq!(config.max_attempts, 6);
+  assert_eq!(config.max_message_attempts, 5);
+  assert_eq!(config.request_timeout, 600);
+  // Unset keys keep their defaults
+  assert_eq!(config.model, "deepseek-chat");
+  assert_eq!(config.connect_timeout, 10);
+
+  fs::remove_file("test_provider_config.toml").expect("Failed to remove test file");
+}
+
+#[test]
+fn test_default_provider_is_deepseek() {
+  let config = Config::default();
+  assert_eq!(config.provider, ProviderKind::OpenAi);
+  assert_eq!(
+    config.provider.default_base_url(),
+    "https://api.deepseek.com/v1"
+  );
+}
+
+#[test]
+fn test_load_filter_config_from_file() {
+  let test_config = r#"
+[filters]
+allow_senders = ["pku.edu.cn"]
+deny_subjects = ["取消"]
+
+[filters.allow_headers]
+List-Id = "seminar"
+"#;
+
+  fs::write("test_filter_config.toml", test_config).expect("Failed to write test config file");
+
+  let config = Config::load_from_file("test_filter_config.toml").expect("Failed to load config");
+
+  assert_eq!(config.filters.allow_senders, vec!["pku.edu.cn"]);
+  assert_eq!(config.filters.deny_subjects, vec!["取消"]);
+  assert_eq!(
+    config
+      .filters
+      .allow_headers
+      .get("List-Id")
+      .map(String::as_str),
+    Some("seminar")
+  );
+  assert!(config.filters.deny_senders.is_empty());
+
+  fs::remove_file("test_filter_config.toml").expect("Failed to remove test file");
+}
+
+#[test]
+fn test_unknown_provider_is_an_error() {
+  fs::write("test_bad_provider_config.toml", "provider = \"gemini\"\n")
+    .expect("Failed to write test config file");
+
+  let result = Config::load_from_file("test_bad_provider_config.toml");
+  fs::remove_file("test_bad_provider_config.toml").expect("Failed to remove test file");
+
+  let error = result.expect_err("an unknown provider must not fall back to the default");
+  assert!(error.to_string().contains("gemini"));
+}
@@ -1,8 +1,24 @@
-use email_abstract_rs::data_sql::store_json_to_db;
+use email_abstract_rs::data_sql::migrations;
+use email_abstract_rs::data_sql::time::parse_event_time;
+use email_abstract_rs::data_sql::{
+  count_events, delete_events, event_key, get_cached_response, get_event, get_sync_state,
+  plan_store, prune_cache, record_uid_failure, save_cached_response, save_run, save_sync_state,
+  search_events, search_events_by_time_begin, search_events_fulltext, spend_by_week,
+  store_json_to_db, EventQuery, FieldChange, Run, SortKey, StoreChange, StoreStats, SyncState,
+};
+use email_abstract_rs::event::{Event, SourceEmail};
 use rusqlite::Connection;
 use serde_json::json;
 use tempfile::NamedTempFile;
 
+/// Deserialize test fixtures the same way model output is deserialized
+fn to_events(values: Vec<serde_json::Value>) -> Vec<Event> {
+  values
+    .into_iter()
+    .map(|v| serde_json::from_value(v).unwrap())
+    .collect()
+}
+
 #[tokio::test]
 async fn test_store_json_to_db_english() {
   let db_file = NamedTempFile::new().unwrap();
@@ -19,7 +35,7 @@ async fn test_store_json_to_db_english() {
     "speaker_title": "Professor",
   })];
 
-  let result = store_json_to_db(test_events.clone(), db_path).await;
+  let result = store_json_to_db(to_events(test_events), db_path).await;
   assert!(result.is_ok());
 
   // Verify database contents
@@ -66,7 +82,7 @@ async fn test_store_json_to_db_chinese() {
     "speaker_title": "清华大学"
   })];
 
-  let result = store_json_to_db(test_events.clone(), db_path).await;
+  let result = store_json_to_db(to_events(test_events), db_path).await;
   assert!(result.is_ok());
 
   // Verify database contents
@@ -110,7 +126,8 @@ async fn test_store_json_to_db_multiple_events() {
       "time_end": "2023-01-01T11:00:00",
       "position": "Room 101",
       "abstract": "English abstract text.",
-      "speaker": "Dr. Smith, MIT"
+      "speaker_name": "Dr. Smith",
+      "speaker_title": "MIT"
     }),
     json!({
       "sender": "chinese@example.com",
@@ -119,7 +136,8 @@ async fn test_store_json_to_db_multiple_events() {
       "time_end": "2023-01-02T16:00:00",
       "position": "图书馆",
       "abstract": "中文摘要内容。",
-      "speaker": "李教授，北京大学"
+      "speaker_name": "李教授",
+      "speaker_title": "北京大学"
     }),
     json!({
       "sender": "mixed@example.com",
@@ -128,11 +146,12 @@ async fn test_store_json_to_db_multiple_events() {
       "time_end": "2023-01-03T10:30:00",
       "position": "Conference Room 会议室",
       "abstract": "This is a mixed language abstract 这是一个混合语言的摘要。",
-      "speaker": "Prof. Wang, Stanford University"
+      "speaker_name": "Prof. Wang",
+      "speaker_title": "Stanford University"
     }),
   ];
 
-  let result = store_json_to_db(test_events.clone(), db_path).await;
+  let result = store_json_to_db(to_events(test_events), db_path).await;
   assert!(result.is_ok());
 
   // Verify database contents - count records
@@ -165,3 +184,1015 @@ async fn test_store_json_to_db_multiple_events() {
   assert_eq!(results[2].0, "mixed@example.com");
   assert_eq!(results[2].1, "Mixed 混合 Event");
 }
+
+#[tokio::test]
+async fn test_sync_state_roundtrip() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let account = "me@example.com@imap.example.com";
+  assert_eq!(
+    get_sync_state(account, "INBOX", db_path).await.unwrap(),
+    None
+  );
+
+  let mut state = SyncState {
+    account: account.to_string(),
+    mailbox: "INBOX".to_string(),
+    uid_validity: 42,
+    last_uid: 100,
+  };
+  save_sync_state(&state, db_path).await.unwrap();
+  assert_eq!(
+    get_sync_state(account, "INBOX", db_path).await.unwrap(),
+    Some(state.clone())
+  );
+
+  // A later sync, or a UIDVALIDITY reset, overwrites the previous position
+  state.uid_validity = 43;
+  state.last_uid = 7;
+  save_sync_state(&state, db_path).await.unwrap();
+  assert_eq!(
+    get_sync_state(account, "INBOX", db_path).await.unwrap(),
+    Some(state)
+  );
+  assert_eq!(
+    get_sync_state("other@example.com@imap.example.com", "INBOX", db_path)
+      .await
+      .unwrap(),
+    None
+  );
+}
+
+#[tokio::test]
+async fn test_record_uid_failure_counts_attempts() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let mut state = SyncState {
+    account: "me@example.com@imap.example.com".to_string(),
+    mailbox: "INBOX".to_string(),
+    uid_validity: 42,
+    last_uid: 100,
+  };
+  assert_eq!(
+    record_uid_failure(&state, 7, "timeout", db_path)
+      .await
+      .unwrap(),
+    1
+  );
+  assert_eq!(
+    record_uid_failure(&state, 7, "timeout", db_path)
+      .await
+      .unwrap(),
+    2
+  );
+  assert_eq!(
+    record_uid_failure(&state, 8, "bad reply", db_path)
+      .await
+      .unwrap(),
+    1
+  );
+
+  // After a UIDVALIDITY reset the same UID is another message
+  state.uid_validity = 43;
+  assert_eq!(
+    record_uid_failure(&state, 7, "timeout", db_path)
+      .await
+      .unwrap(),
+    1
+  );
+}
+
+#[tokio::test]
+async fn test_store_and_search_event_source() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let source = SourceEmail {
+    message_id: "<abc@mails.tsinghua.edu.cn>".to_string(),
+    subject: "学术报告通知".to_string(),
+    date: "2025-03-10T02:00:00+00:00".to_string(),
+    mailbox: "INBOX".to_string(),
+    uid: Some(42),
+  };
+  let mut events = to_events(vec![json!({
+    "sender": "phys@mails.tsinghua.edu.cn",
+    "event": "拓扑绝缘体",
+    "time_begin": "2025年03月14日 14时00分",
+    "time_end": "2025年03月14日 16时00分",
+    "position": "理科楼C302",
+    "abstract": "摘要",
+    "speaker_name": "张三",
+    "speaker_title": "教授"
+  })]);
+  events[0].source = Some(source.clone());
+
+  store_json_to_db(events.clone(), db_path).await.unwrap();
+  // Storing the same email twice must not duplicate it
+  store_json_to_db(events, db_path).await.unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  let count: i64 = conn
+    .query_row("SELECT COUNT(*) FROM emails", [], |row| row.get(0))
+    .unwrap();
+  assert_eq!(count, 1);
+
+  let found = search_events_by_time_begin("2025年03月14日", db_path)
+    .await
+    .unwrap();
+  assert_eq!(found.len(), 1);
+  assert_eq!(found[0].source, Some(source));
+}
+
+#[tokio::test]
+async fn test_open_db_adds_email_id_to_old_schema() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute_batch(
+      "CREATE TABLE events (
+        id INTEGER PRIMARY KEY, sender TEXT NOT NULL, event TEXT NOT NULL,
+        time_begin TEXT NOT NULL, time_end TEXT NOT NULL, position TEXT NOT NULL,
+        \"abstract\" TEXT NOT NULL, speaker_name TEXT NOT NULL, speaker_title TEXT NOT NULL);
+      INSERT INTO events VALUES (1, 's', 'e', 'b', 'e', 'p', 'a', 'n', 't');",
+    )
+    .unwrap();
+  drop(conn);
+
+  let found = search_events_by_time_begin("b", db_path).await.unwrap();
+  assert_eq!(found.len(), 1);
+  assert_eq!(found[0].source, None);
+}
+
+#[tokio::test]
+async fn test_store_upserts_by_event_key() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let talk = |title: &str, begin: &str, abstract_: &str| Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: title.to_string(),
+    time_begin: begin.to_string(),
+    position: "理科楼C302".to_string(),
+    r#abstract: abstract_.to_string(),
+    ..Default::default()
+  };
+  let stats = store_json_to_db(
+    vec![
+      talk("拓扑绝缘体", "2025年03月14日 14时00分", "摘要"),
+      talk("代数几何", "2025年03月20日 10时00分", "摘要"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+  assert_eq!(
+    stats,
+    StoreStats {
+      inserted: 2,
+      ..Default::default()
+    }
+  );
+
+  // Same sender, time and place: a renamed title updates the stored row,
+  // and the same time written differently is still the same event
+  let stats = store_json_to_db(
+    vec![
+      talk("拓扑绝缘体前沿进展", "2025-03-14 14:00", "摘要"),
+      talk("代数几何", "2025年03月20日 10时00分", "摘要"),
+      talk("超导", "2025年04月02日 15时30分", "摘要"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+  assert_eq!(
+    stats,
+    StoreStats {
+      inserted: 1,
+      updated: 1,
+      unchanged: 1,
+    }
+  );
+
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(
+    titles(&events),
+    vec!["拓扑绝缘体前沿进展", "代数几何", "超导"]
+  );
+  assert_eq!(
+    event_key(&events[0]),
+    event_key(&talk("x", "2025年03月14日 14时00分", ""))
+  );
+}
+
+#[tokio::test]
+async fn test_store_records_last_change() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let talk = |abstract_: &str| Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: "拓扑绝缘体".to_string(),
+    time_begin: "2025年03月14日 14时00分".to_string(),
+    position: "理科楼C302".to_string(),
+    r#abstract: abstract_.to_string(),
+    ..Default::default()
+  };
+  store_json_to_db(vec![talk("摘要")], db_path).await.unwrap();
+  let stored = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert!(stored[0].updated_at.is_some());
+
+  let old = "2000-01-01T00:00:00Z";
+  Connection::open(db_path)
+    .unwrap()
+    .execute("UPDATE events SET updated_at = ?1", [old])
+    .unwrap();
+  let updated_at = |events: Vec<Event>| events[0].updated_at.clone().unwrap();
+
+  // Storing the same event again is not a change
+  store_json_to_db(vec![talk("摘要")], db_path).await.unwrap();
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(updated_at(events), old);
+
+  store_json_to_db(vec![talk("新摘要")], db_path)
+    .await
+    .unwrap();
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert!(updated_at(events).as_str() > old);
+}
+
+#[tokio::test]
+async fn test_delete_events_by_key() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let talk = |title: &str, begin: &str| Event {
+    sender: "math@mail.tsinghua.edu.cn".to_string(),
+    event: title.to_string(),
+    time_begin: begin.to_string(),
+    position: "A304".to_string(),
+    ..Default::default()
+  };
+  store_json_to_db(
+    vec![
+      talk("讨论班", "2025年03月14日 14时00分"),
+      talk("讨论班", "2025年03月21日 14时00分"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  // A cancellation finds the stored talk even if its title was reworded
+  let deleted = delete_events(&[talk("讨论班（取消）", "2025-03-14 14:00")], db_path)
+    .await
+    .unwrap();
+  assert_eq!(deleted, 1);
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(events.len(), 1);
+  assert_eq!(events[0].time_begin, "2025年03月21日 14时00分");
+}
+
+#[tokio::test]
+async fn test_store_keeps_talks_without_time_or_place_apart() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let talk = |title: &str, begin: &str, position: &str| Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: title.to_string(),
+    time_begin: begin.to_string(),
+    position: position.to_string(),
+    ..Default::default()
+  };
+  // Only a date, or a time but no place: the titles tell the talks apart
+  let stats = store_json_to_db(
+    vec![
+      talk("Talk A", "2025年03月14日", "理科楼C302"),
+      talk("Talk B", "2025年03月14日", "理科楼C302"),
+      talk("Talk C", "2025年03月15日 14时00分", ""),
+      talk("Talk D", "2025年03月15日 14时00分", ""),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+  assert_eq!(
+    stats,
+    StoreStats {
+      inserted: 4,
+      ..Default::default()
+    }
+  );
+}
+
+#[tokio::test]
+async fn test_open_db_keeps_colliding_old_rows() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  // Older versions could store the same event twice
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute_batch(
+      "CREATE TABLE events (
+        id INTEGER PRIMARY KEY, sender TEXT NOT NULL, event TEXT NOT NULL,
+        time_begin TEXT NOT NULL, time_end TEXT NOT NULL, position TEXT NOT NULL,
+        \"abstract\" TEXT NOT NULL, speaker_name TEXT NOT NULL, speaker_title TEXT NOT NULL);
+      INSERT INTO events VALUES (1, 's', '旧标题', '2025-03-14 14:00', '', 'p', 'a', 'n', 't');
+      INSERT INTO events VALUES (2, 'S', '新标题', '2025年03月14日 14时00分', '', 'p', 'a', 'n', 't');
+      INSERT INTO events VALUES (3, 's', '待定活动', '待定', '', 'p', 'a', 'n', 't');",
+    )
+    .unwrap();
+  drop(conn);
+
+  // Both copies survive; the newer one owns the key
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(titles(&events), vec!["旧标题", "新标题", "待定活动"]);
+  let stats = store_json_to_db(vec![events[1].clone()], db_path)
+    .await
+    .unwrap();
+  assert_eq!(stats.unchanged, 1);
+
+  let conn = Connection::open(db_path).unwrap();
+  let result = conn.execute(
+    "INSERT INTO events (sender, event, time_begin, time_end, position, \"abstract\", speaker_name, speaker_title, event_key)
+     SELECT sender, event, time_begin, time_end, position, \"abstract\", speaker_name, speaker_title, event_key FROM events WHERE id = 2",
+    [],
+  );
+  assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_migrate_baseline_fixture() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  let mut conn = Connection::open(db_path).unwrap();
+  conn
+    .execute_batch(include_str!("fixtures/baseline_schema.sql"))
+    .unwrap();
+
+  assert_eq!(migrations::current_version(&conn).unwrap(), 0);
+  assert_eq!(
+    migrations::pending(&conn).unwrap().len(),
+    migrations::MIGRATIONS.len()
+  );
+
+  let applied = migrations::migrate(&mut conn).unwrap();
+  assert_eq!(applied.len(), migrations::MIGRATIONS.len());
+  assert_eq!(
+    migrations::current_version(&conn).unwrap(),
+    migrations::latest_version()
+  );
+  assert!(migrations::pending(&conn).unwrap().is_empty());
+  // Running again is a no-op
+  assert!(migrations::migrate(&mut conn).unwrap().is_empty());
+  drop(conn);
+
+  // The existing rows and the full-text index survive, with times normalized
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(
+    titles(&events),
+    vec!["拓扑绝缘体", "数论讨论班", "几何讨论班", "代数几何"]
+  );
+  assert_eq!(
+    events[0].time_begin_iso.as_deref(),
+    Some("2025-03-14T14:00:00+08:00")
+  );
+  let matches = search_events_fulltext("绝缘体", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(matches.len(), 1);
+
+  // Two talks on the same day shared a key at first; the later keys tell
+  // them apart again, and match the ones new writes compute
+  for event in &events {
+    assert_eq!(event.event_key.as_deref(), Some(event_key(event).as_str()));
+  }
+  let stats = store_json_to_db(events, db_path).await.unwrap();
+  assert_eq!(stats.unchanged, 4);
+}
+
+#[tokio::test]
+async fn test_migrate_rejects_newer_schema() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute(
+      "INSERT INTO schema_version VALUES (?1, 'from the future', '2030-01-01T00:00:00+00:00')",
+      [migrations::latest_version() + 1],
+    )
+    .unwrap();
+  drop(conn);
+
+  let err = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap_err();
+  assert!(err.to_string().contains("newer than this build"));
+}
+
+#[tokio::test]
+async fn test_plan_store_does_not_write() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let talk = |title: &str, begin: &str, abstract_: &str| Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: title.to_string(),
+    time_begin: begin.to_string(),
+    position: "理科楼C302".to_string(),
+    r#abstract: abstract_.to_string(),
+    ..Default::default()
+  };
+  store_json_to_db(
+    vec![
+      talk("拓扑绝缘体", "2025年03月14日 14时00分", "摘要"),
+      talk("代数几何", "2025年03月20日 10时00分", "摘要"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  let plan = plan_store(
+    &[
+      talk("拓扑绝缘体", "2025-03-14 14:00", "新摘要"),
+      talk("代数几何", "2025年03月20日 10时00分", "摘要"),
+      talk("超导", "2025年04月02日 15时30分", "摘要"),
+      // The same new event twice is one insert, then unchanged
+      talk("超导", "2025年04月02日 15时30分", "摘要"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  match &plan[0] {
+    StoreChange::Update { stored, changes } => {
+      assert_eq!(stored.event, "拓扑绝缘体");
+      assert_eq!(
+        changes,
+        &vec![
+          FieldChange {
+            field: "time_begin",
+            before: "2025年03月14日 14时00分".to_string(),
+            after: "2025-03-14 14:00".to_string(),
+          },
+          FieldChange {
+            field: "abstract",
+            before: "摘要".to_string(),
+            after: "新摘要".to_string(),
+          },
+        ]
+      );
+    }
+    other => panic!("expected an update, got {:?}", other),
+  }
+  assert!(matches!(&plan[1], StoreChange::Unchanged(e) if e.id.is_some()));
+  assert!(
+    matches!(&plan[2], StoreChange::Insert(e) if e.id.is_none() && e.time_begin_iso.is_some())
+  );
+  assert!(matches!(&plan[3], StoreChange::Unchanged(_)));
+
+  // Nothing was written
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(titles(&events), vec!["拓扑绝缘体", "代数几何"]);
+  assert_eq!(events[0].r#abstract, "摘要");
+}
+
+#[tokio::test]
+async fn test_prune_cache() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  save_cached_response("old", "deepseek-chat", "[]", db_path)
+    .await
+    .unwrap();
+  save_cached_response("new", "deepseek-chat", "[{}]", db_path)
+    .await
+    .unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute(
+      "UPDATE llm_cache SET created_at = '2020-01-01T00:00:00Z' WHERE key = 'old'",
+      [],
+    )
+    .unwrap();
+  drop(conn);
+
+  let deleted = prune_cache(chrono::Duration::days(30), db_path)
+    .await
+    .unwrap();
+  assert_eq!(deleted, 1);
+  assert_eq!(get_cached_response("old", db_path).await.unwrap(), None);
+  assert_eq!(
+    get_cached_response("new", db_path)
+      .await
+      .unwrap()
+      .as_deref(),
+    Some("[{}]")
+  );
+}
+
+#[tokio::test]
+async fn test_spend_by_week() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  let run = |started_at: &str, model: &str, cost: Option<f64>| Run {
+    started_at: started_at.to_string(),
+    provider: "OpenAI-compatible".to_string(),
+    model: model.to_string(),
+    prompt_tokens: 1000,
+    completion_tokens: 200,
+    cost,
+    email_count: 5,
+    event_count: 2,
+  };
+  // Monday and Sunday of one week, then the following Monday
+  for r in [
+    run("2025-03-10T02:00:00+08:00", "deepseek-chat", Some(0.5)),
+    run("2025-03-16T23:00:00+08:00", "deepseek-chat", Some(0.25)),
+    run("2025-03-16T23:00:00+08:00", "llama3", None),
+    run("2025-03-17T02:00:00+08:00", "deepseek-chat", Some(1.0)),
+  ] {
+    save_run(&r, db_path).await.unwrap();
+  }
+
+  let spend = spend_by_week(db_path).await.unwrap();
+  let summary: Vec<(&str, &str, usize, Option<f64>)> = spend
+    .iter()
+    .map(|s| (s.week.as_str(), s.model.as_str(), s.runs, s.cost))
+    .collect();
+  assert_eq!(
+    summary,
+    vec![
+      ("2025-03-17", "deepseek-chat", 1, Some(1.0)),
+      ("2025-03-10", "deepseek-chat", 2, Some(0.75)),
+      ("2025-03-10", "llama3", 1, None),
+    ]
+  );
+  assert_eq!(spend[1].prompt_tokens, 2000);
+  assert_eq!(spend[1].event_count, 4);
+}
+
+#[test]
+fn test_parse_event_time_variants() {
+  let parse = |text: &str| parse_event_time(text, None).map(|dt| dt.to_rfc3339());
+
+  let expected = Some("2025-03-14T14:00:00+08:00".to_string());
+  assert_eq!(parse("2025年03月14日 14时00分"), expected);
+  assert_eq!(parse("2025年3月14日14:00"), expected);
+  assert_eq!(parse("2025-03-14 14:00"), expected);
+  assert_eq!(parse("2025/03/14 下午2点"), expected);
+  assert_eq!(parse("2025-03-14T06:00:00Z"), expected);
+  assert_eq!(parse("２０２５年０３月１４日 １４时００分"), expected);
+  assert_eq!(
+    parse("2025年03月14日 下午2点半"),
+    Some("2025-03-14T14:30:00+08:00".to_string())
+  );
+  assert_eq!(
+    parse("2025年03月14日"),
+    Some("2025-03-14T00:00:00+08:00".to_string())
+  );
+  assert_eq!(
+    parse("2025年03月14日（周五）14:00-16:00"),
+    Some("2025-03-14T14:00:00+08:00".to_string())
+  );
+
+  assert_eq!(parse(""), None);
+  assert_eq!(parse("待定"), None);
+  assert_eq!(parse("3月14日 14:00"), None);
+  assert_eq!(parse("2025年13月40日"), None);
+}
+
+#[test]
+fn test_parse_event_time_uses_default_date() {
+  let date = chrono::NaiveDate::from_ymd_opt(2025, 3, 14);
+
+  assert_eq!(
+    parse_event_time("16时00分", date).map(|dt| dt.to_rfc3339()),
+    Some("2025-03-14T16:00:00+08:00".to_string())
+  );
+  assert_eq!(parse_event_time("16时00分", None), None);
+}
+
+#[tokio::test]
+async fn test_store_normalizes_times() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let base = json!({
+    "sender": "a@example.com",
+    "position": "Room 1",
+    "abstract": "",
+    "speaker_name": "",
+    "speaker_title": ""
+  });
+  let with = |event: &str, begin: &str, end: &str| {
+    let mut value = base.clone();
+    value["event"] = json!(event);
+    value["time_begin"] = json!(begin);
+    value["time_end"] = json!(end);
+    value
+  };
+  store_json_to_db(
+    to_events(vec![
+      with("parsed", "2025年03月14日 14时00分", "16时00分"),
+      with("no end", "2025-03-15 09:00", ""),
+      with("unparsed", "下周五下午", ""),
+    ]),
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  let mut stmt = conn
+    .prepare(
+      "SELECT event, time_begin, time_begin_iso, time_end_iso, time_parse_failed FROM events ORDER BY id",
+    )
+    .unwrap();
+  type Row = (String, String, Option<String>, Option<String>, bool);
+  let rows: Vec<Row> = stmt
+    .query_map([], |row| {
+      Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+      ))
+    })
+    .unwrap()
+    .map(|r| r.unwrap())
+    .collect();
+
+  assert_eq!(
+    rows[0],
+    (
+      "parsed".to_string(),
+      "2025年03月14日 14时00分".to_string(),
+      Some("2025-03-14T14:00:00+08:00".to_string()),
+      Some("2025-03-14T16:00:00+08:00".to_string()),
+      false
+    )
+  );
+  assert_eq!(rows[1].2, Some("2025-03-15T09:00:00+08:00".to_string()));
+  assert_eq!(rows[1].3, None);
+  assert!(!rows[1].4);
+  // The original text is kept and the row is flagged
+  assert_eq!(rows[2].1, "下周五下午");
+  assert_eq!(rows[2].2, None);
+  assert!(rows[2].4);
+}
+
+/// Store a small set of events covering every search filter
+async fn seed_search_db(db_path: &str) {
+  let event = |sender: &str, title: &str, begin: &str, position: &str, speaker: &str| {
+    json!({
+      "sender": sender,
+      "event": title,
+      "time_begin": begin,
+      "time_end": "",
+      "position": position,
+      "abstract": format!("{} 的摘要", title),
+      "speaker_name": speaker,
+      "speaker_title": "教授"
+    })
+  };
+  store_json_to_db(
+    to_events(vec![
+      event(
+        "phys@mails.tsinghua.edu.cn",
+        "拓扑绝缘体",
+        "2025年03月14日 14时00分",
+        "理科楼C302",
+        "张三",
+      ),
+      event(
+        "math@mails.tsinghua.edu.cn",
+        "代数几何",
+        "2025年03月20日 10时00分",
+        "近春园",
+        "李四",
+      ),
+      event(
+        "phys@mails.tsinghua.edu.cn",
+        "超导_100%",
+        "2025年04月02日 15时30分",
+        "理科楼C109",
+        "王五",
+      ),
+      event(
+        "phys@mails.tsinghua.edu.cn",
+        "时间待定",
+        "待定",
+        "理科楼",
+        "赵六",
+      ),
+    ]),
+    db_path,
+  )
+  .await
+  .unwrap();
+}
+
+fn titles(events: &[Event]) -> Vec<&str> {
+  events.iter().map(|e| e.event.as_str()).collect()
+}
+
+#[tokio::test]
+async fn test_store_prefers_structured_times() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let event = Event {
+    sender: "a@example.com".to_string(),
+    event: "invite".to_string(),
+    time_begin: "2025年03月17日 09时00分".to_string(),
+    time_begin_iso: Some("2025-03-17T22:00:00+08:00".to_string()),
+    ..Default::default()
+  };
+  store_json_to_db(vec![event], db_path).await.unwrap();
+
+  let conn = Connection::open(db_path).unwrap();
+  let (begin_iso, failed): (Option<String>, bool) = conn
+    .query_row(
+      "SELECT time_begin_iso, time_parse_failed FROM events",
+      [],
+      |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .unwrap();
+  assert_eq!(begin_iso.as_deref(), Some("2025-03-17T22:00:00+08:00"));
+  assert!(!failed);
+}
+
+#[tokio::test]
+async fn test_search_events_by_date_range() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_search_db(db_path).await;
+
+  let query = EventQuery {
+    from: chrono::NaiveDate::from_ymd_opt(2025, 3, 14),
+    to: chrono::NaiveDate::from_ymd_opt(2025, 3, 20),
+    ..Default::default()
+  };
+  let events = search_events(&query, db_path).await.unwrap();
+  assert_eq!(titles(&events), vec!["拓扑绝缘体", "代数几何"]);
+
+  let query = EventQuery {
+    from: chrono::NaiveDate::from_ymd_opt(2025, 3, 21),
+    ..Default::default()
+  };
+  let events = search_events(&query, db_path).await.unwrap();
+  assert_eq!(titles(&events), vec!["超导_100%"]);
+}
+
+#[tokio::test]
+async fn test_search_events_by_fields() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_search_db(db_path).await;
+
+  let search = |query: EventQuery| async move { search_events(&query, db_path).await.unwrap() };
+
+  let events = search(EventQuery {
+    sender: Some("phys@".to_string()),
+    position: Some("理科楼C".to_string()),
+    ..Default::default()
+  })
+  .await;
+  assert_eq!(titles(&events), vec!["拓扑绝缘体", "超导_100%"]);
+
+  let events = search(EventQuery {
+    speaker: Some("李四".to_string()),
+    ..Default::default()
+  })
+  .await;
+  assert_eq!(titles(&events), vec!["代数几何"]);
+
+  // Keyword matches the abstract too, and LIKE wildcards are taken literally
+  let events = search(EventQuery {
+    keyword: Some("_100%".to_string()),
+    ..Default::default()
+  })
+  .await;
+  assert_eq!(titles(&events), vec!["超导_100%"]);
+  let events = search(EventQuery {
+    keyword: Some("几何 的摘要".to_string()),
+    ..Default::default()
+  })
+  .await;
+  assert_eq!(titles(&events), vec!["代数几何"]);
+
+  let events = search(EventQuery {
+    time_begin: Some("03月".to_string()),
+    ..Default::default()
+  })
+  .await;
+  assert_eq!(events.len(), 2);
+}
+
+#[tokio::test]
+async fn test_search_events_sort_and_limit() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_search_db(db_path).await;
+
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  // Unparsed times sort last
+  assert_eq!(
+    titles(&events),
+    vec!["拓扑绝缘体", "代数几何", "超导_100%", "时间待定"]
+  );
+
+  let query = EventQuery {
+    sort: SortKey::Time,
+    descending: true,
+    limit: Some(2),
+    from: chrono::NaiveDate::from_ymd_opt(2025, 1, 1),
+    ..Default::default()
+  };
+  let events = search_events(&query, db_path).await.unwrap();
+  assert_eq!(titles(&events), vec!["超导_100%", "代数几何"]);
+}
+
+#[tokio::test]
+async fn test_search_events_offset_count_and_get() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_search_db(db_path).await;
+
+  let query = EventQuery {
+    sender: Some("phys".to_string()),
+    limit: Some(1),
+    offset: Some(1),
+    ..Default::default()
+  };
+  let events = search_events(&query, db_path).await.unwrap();
+  assert_eq!(titles(&events), vec!["超导_100%"]);
+  // The count ignores the page
+  assert_eq!(count_events(&query, db_path).await.unwrap(), 3);
+
+  let since = chrono::DateTime::parse_from_rfc3339("2025-03-20T10:00:00+08:00").unwrap();
+  let query = EventQuery {
+    since: Some(since),
+    ..Default::default()
+  };
+  let events = search_events(&query, db_path).await.unwrap();
+  assert_eq!(titles(&events), vec!["代数几何", "超导_100%"]);
+
+  let id = events[0].id.unwrap();
+  let event = get_event(id, db_path).await.unwrap().unwrap();
+  assert_eq!(event.event, "代数几何");
+  assert!(get_event(id + 100, db_path).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_search_events_fulltext_ranked() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  let event = |title: &str, abstract_: &str| {
+    json!({
+      "sender": "phys@mails.tsinghua.edu.cn",
+      "event": title,
+      "time_begin": "2025年03月14日 14时00分",
+      "time_end": "",
+      "position": format!("{} 会议室", title),
+      "abstract": abstract_,
+      "speaker_name": "张三",
+      "speaker_title": "教授"
+    })
+  };
+  store_json_to_db(
+    to_events(vec![
+      event("凝聚态物理进展", "介绍了拓扑绝缘体表面态的输运实验。"),
+      event("拓扑绝缘体前沿", "Topological insulators: recent progress."),
+      event("代数几何", "模空间与相交理论。"),
+    ]),
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  let matches = search_events_fulltext("绝缘体", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  // A title match outranks an abstract match
+  assert_eq!(matches.len(), 2);
+  assert_eq!(matches[0].event.event, "拓扑绝缘体前沿");
+  assert!(matches[0].rank.is_some());
+  assert!(matches[1].snippet.contains("【绝缘体】"));
+
+  let matches = search_events_fulltext("topological", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(matches.len(), 1);
+  assert!(matches[0].snippet.contains("【Topological】"));
+
+  // Two-character terms fall back to substring matching
+  let matches = search_events_fulltext("几何 相交", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(matches.len(), 1);
+  assert_eq!(matches[0].event.event, "代数几何");
+  assert_eq!(matches[0].rank, None);
+  assert_eq!(matches[0].snippet, "代数【几何】");
+
+  // Query syntax in user input is taken literally
+  let matches = search_events_fulltext("\"OR\" NEAR(", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert!(matches.is_empty());
+}
+
+#[tokio::test]
+async fn test_short_term_snippet_ignores_case() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  store_json_to_db(
+    to_events(vec![json!({
+      "sender": "phys@mails.tsinghua.edu.cn",
+      "event": "QFT 与弦论 qft",
+      "time_begin": "2025年03月14日 14时00分",
+      "time_end": "",
+      "position": "理科楼C302",
+      "abstract": "",
+      "speaker_name": "张三",
+      "speaker_title": "教授"
+    })]),
+    db_path,
+  )
+  .await
+  .unwrap();
+
+  // Matched like the search itself, highlighted in the text's own case
+  let matches = search_events_fulltext("qF", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(matches.len(), 1);
+  assert_eq!(matches[0].snippet, "【QF】T 与弦论 【qf】t");
+}
+
+#[tokio::test]
+async fn test_fulltext_index_follows_updates_and_old_databases() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  // A database created before the index existed
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute_batch(
+      "CREATE TABLE events (
+        id INTEGER PRIMARY KEY, sender TEXT NOT NULL, event TEXT NOT NULL,
+        time_begin TEXT NOT NULL, time_end TEXT NOT NULL, position TEXT NOT NULL,
+        \"abstract\" TEXT NOT NULL, speaker_name TEXT NOT NULL, speaker_title TEXT NOT NULL);
+      INSERT INTO events VALUES (1, 's', '超导量子比特', 'b', 'e', 'p', 'a', 'n', 't');",
+    )
+    .unwrap();
+  drop(conn);
+
+  let matches = search_events_fulltext("量子比特", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(matches.len(), 1);
+
+  let conn = Connection::open(db_path).unwrap();
+  conn
+    .execute("UPDATE events SET event = '离子阱' WHERE id = 1", [])
+    .unwrap();
+  drop(conn);
+
+  let matches = search_events_fulltext("量子比特", &EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert!(matches.is_empty());
+}
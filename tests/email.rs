@@ -1,6 +1,49 @@
 #[cfg(test)]
 mod tests {
-  use email_abstract_rs::email::{extract_email, is_tsinghua_sender, EmailTable};
+  use email_abstract_rs::config::FilterConfig;
+  use email_abstract_rs::email::attachment::{docx_text, parse_ics, pdf_text};
+  use email_abstract_rs::email::body::{clean_body, html_to_text, is_forward};
+  use email_abstract_rs::email::source::{EmailSource, EmlDirSource, MaildirSource, MboxSource};
+  use email_abstract_rs::email::{extract_email, resume_uid, Backoff, EmailFilter, EmailTable};
+  use std::collections::HashMap;
+  use std::time::Duration;
+
+  fn accepts(filter: &EmailFilter, from: &str, subject: &str, extra_headers: &str) -> bool {
+    let raw = format!(
+      "From: {}\r\nSubject: {}\r\n{}\r\nbody\r\n",
+      from, subject, extra_headers
+    );
+    filter.accepts(&mailparse::parse_mail(raw.as_bytes()).unwrap())
+  }
+  #[test]
+  fn test_resume_uid_retries_until_the_attempt_cap() {
+    // Nothing failed: the run got through everything
+    assert_eq!(resume_uid(20, &[], 3), 20);
+    // A message that can still be retried holds the position just below it
+    assert_eq!(resume_uid(20, &[(12, 1)], 3), 11);
+    assert_eq!(resume_uid(20, &[(15, 2), (12, 1)], 3), 11);
+    // Once it failed often enough it is skipped, and later mail goes through
+    assert_eq!(resume_uid(20, &[(12, 3)], 3), 20);
+    assert_eq!(resume_uid(20, &[(12, 3), (15, 1)], 3), 14);
+  }
+
+  #[test]
+  fn test_backoff_doubles_up_to_max_and_resets() {
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(30));
+    let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, [5, 10, 20, 30, 30]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+
+    // A huge maximum saturates instead of overflowing
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::MAX);
+    for _ in 0..80 {
+      backoff.next_delay();
+    }
+    assert_eq!(backoff.next_delay(), Duration::MAX);
+  }
+
   #[test]
   fn test_extract_email() {
     assert_eq!(
@@ -18,10 +61,71 @@ mod tests {
   }
 
   #[test]
-  fn test_is_tsinghua_sender() {
-    assert!(is_tsinghua_sender("someone@mail.tsinghua.edu.cn"));
-    assert!(is_tsinghua_sender("someone@mails.tsinghua.edu.cn"));
-    assert!(!is_tsinghua_sender("someone@example.com"));
+  fn test_default_filter_accepts_tsinghua_senders() {
+    let filter = EmailFilter::from_config(&FilterConfig::default()).unwrap();
+
+    assert!(accepts(&filter, "someone@mail.tsinghua.edu.cn", "讲座", ""));
+    assert!(accepts(
+      &filter,
+      "Someone <someone@MAILS.tsinghua.edu.cn>",
+      "讲座",
+      ""
+    ));
+    assert!(!accepts(&filter, "someone@example.com", "讲座", ""));
+    // Domain rules must match whole labels, not substrings
+    assert!(!accepts(
+      &filter,
+      "someone@mail.tsinghua.edu.cn.evil.com",
+      "讲座",
+      ""
+    ));
+  }
+
+  #[test]
+  fn test_filter_sender_allow_and_deny_lists() {
+    let filter = EmailFilter::from_config(&FilterConfig {
+      allow_senders: vec!["@pku.edu.cn".to_string(), "news@example.com".to_string()],
+      deny_senders: vec!["spam.pku.edu.cn".to_string()],
+      ..Default::default()
+    })
+    .unwrap();
+
+    assert!(accepts(&filter, "a@pku.edu.cn", "x", ""));
+    assert!(accepts(&filter, "a@math.pku.edu.cn", "x", ""));
+    assert!(accepts(&filter, "news@example.com", "x", ""));
+    assert!(!accepts(&filter, "other@example.com", "x", ""));
+    assert!(!accepts(&filter, "a@spam.pku.edu.cn", "x", ""));
+  }
+
+  #[test]
+  fn test_filter_subject_and_header_rules() {
+    let filter = EmailFilter::from_config(&FilterConfig {
+      allow_senders: Vec::new(),
+      allow_subjects: vec!["(?i)seminar|讲座".to_string()],
+      deny_subjects: vec!["取消".to_string()],
+      allow_headers: HashMap::from([("List-Id".to_string(), r"math\.example\.edu".to_string())]),
+      ..Default::default()
+    })
+    .unwrap();
+    let list = "List-Id: Math <seminar.math.example.edu>\r\n";
+
+    assert!(accepts(&filter, "a@example.edu", "Weekly Seminar", list));
+    assert!(accepts(&filter, "a@example.edu", "数学讲座", list));
+    // Every non-empty allow list has to match
+    assert!(!accepts(&filter, "a@example.edu", "Weekly Seminar", ""));
+    assert!(!accepts(&filter, "a@example.edu", "Newsletter", list));
+    // Deny rules win over allow rules
+    assert!(!accepts(&filter, "a@example.edu", "讲座取消", list));
+  }
+
+  #[test]
+  fn test_filter_rejects_invalid_pattern() {
+    let result = EmailFilter::from_config(&FilterConfig {
+      deny_subjects: vec!["(unclosed".to_string()],
+      ..Default::default()
+    });
+
+    assert!(result.unwrap_err().to_string().contains("(unclosed"));
   }
 
   #[test]
@@ -30,10 +134,551 @@ mod tests {
       sender: "test@example.com".to_string(),
       subject: "Test Subject".to_string(),
       body: "Test Body".to_string(),
+      ..Default::default()
     };
 
     assert_eq!(email.sender, "test@example.com");
     assert_eq!(email.subject, "Test Subject");
     assert_eq!(email.body, "Test Body");
   }
+
+  fn raw_email(from: &str, subject: &str, date: &str, body: &str) -> String {
+    format!(
+      "From: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@test>\r\n\r\n{}\r\n",
+      from,
+      subject,
+      date,
+      subject.replace(' ', "-"),
+      body
+    )
+  }
+
+  fn default_filter() -> EmailFilter {
+    EmailFilter::from_config(&FilterConfig::default()).unwrap()
+  }
+
+  #[test]
+  fn test_maildir_source_reads_new_and_cur() {
+    let dir = tempfile::tempdir().unwrap();
+    for folder in ["new", "cur", "tmp"] {
+      std::fs::create_dir(dir.path().join(folder)).unwrap();
+    }
+    let date = "Fri, 14 Mar 2025 10:00:00 +0800";
+    let files = [
+      (
+        "new/1",
+        raw_email("a@mail.tsinghua.edu.cn", "New talk", date, "n"),
+      ),
+      (
+        "cur/2:2,S",
+        raw_email("b@mail.tsinghua.edu.cn", "Old talk", date, "c"),
+      ),
+      (
+        "cur/3:2,S",
+        raw_email("c@example.com", "Filtered", date, "x"),
+      ),
+      (
+        "tmp/4",
+        raw_email("d@mail.tsinghua.edu.cn", "In delivery", date, "t"),
+      ),
+    ];
+    for (name, contents) in files {
+      std::fs::write(dir.path().join(name), contents).unwrap();
+    }
+
+    let source = MaildirSource {
+      path: dir.path().to_path_buf(),
+      days_ago: None,
+    };
+    let emails = source.fetch(&default_filter()).unwrap();
+
+    let subjects: Vec<_> = emails.iter().map(|e| e.subject.as_str()).collect();
+    assert_eq!(subjects, vec!["New talk", "Old talk"]);
+    assert_eq!(emails[0].message_id, "<New-talk@test>");
+    assert_eq!(emails[0].date, "2025-03-14T02:00:00+00:00");
+    assert_eq!(emails[0].mailbox, dir.path().display().to_string());
+    assert_eq!(emails[0].uid, None);
+  }
+
+  #[test]
+  fn test_maildir_source_rejects_plain_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = MaildirSource {
+      path: dir.path().to_path_buf(),
+      days_ago: None,
+    };
+
+    assert!(source.fetch(&default_filter()).is_err());
+  }
+
+  #[test]
+  fn test_split_mbox_unquotes_from_lines() {
+    let mbox = "From a@mail.tsinghua.edu.cn Fri Mar 14 10:00:00 2025\n\
+Subject: One\n\
+\n\
+>From the abstract\n\
+\n\
+From b@mail.tsinghua.edu.cn Fri Mar 14 11:00:00 2025\n\
+Subject: Two\n\
+\n\
+body\n";
+
+    let messages = email_abstract_rs::email::source::split_mbox(mbox.as_bytes());
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(
+      String::from_utf8_lossy(&messages[0]),
+      "Subject: One\n\nFrom the abstract\n"
+    );
+    assert_eq!(
+      String::from_utf8_lossy(&messages[1]),
+      "Subject: Two\n\nbody"
+    );
+  }
+
+  #[test]
+  fn test_mbox_source_applies_date_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let recent = chrono::Utc::now().to_rfc2822();
+    let mbox = format!(
+      "From x Thu Jan  1 00:00:00 2015\n{}\nFrom x Thu Jan  1 00:00:00 2015\n{}",
+      raw_email("a@mail.tsinghua.edu.cn", "Recent", &recent, "r"),
+      raw_email(
+        "b@mail.tsinghua.edu.cn",
+        "Ancient",
+        "Thu, 1 Jan 2015 08:00:00 +0800",
+        "a"
+      ),
+    );
+    let path = dir.path().join("archive.mbox");
+    std::fs::write(&path, mbox).unwrap();
+
+    let all = MboxSource {
+      path: path.clone(),
+      days_ago: None,
+    }
+    .fetch(&default_filter())
+    .unwrap();
+    let recent = MboxSource {
+      path,
+      days_ago: Some(7),
+    }
+    .fetch(&default_filter())
+    .unwrap();
+
+    assert_eq!(all.len(), 2);
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent[0].subject, "Recent");
+  }
+
+  #[test]
+  fn test_eml_dir_source_only_reads_eml_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let date = "Fri, 14 Mar 2025 10:00:00 +0800";
+    std::fs::write(
+      dir.path().join("b.eml"),
+      raw_email("b@mails.tsinghua.edu.cn", "Second", date, "2"),
+    )
+    .unwrap();
+    std::fs::write(
+      dir.path().join("a.EML"),
+      raw_email("a@mails.tsinghua.edu.cn", "First", date, "1"),
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "not an email").unwrap();
+
+    let source = EmlDirSource {
+      path: dir.path().to_path_buf(),
+      days_ago: None,
+    };
+    let emails = source.fetch(&default_filter()).unwrap();
+
+    let subjects: Vec<_> = emails.iter().map(|e| e.subject.as_str()).collect();
+    assert_eq!(subjects, vec!["First", "Second"]);
+    assert_eq!(emails[0].body.trim(), "1");
+  }
+
+  const INVITE: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+METHOD:REQUEST\r
+BEGIN:VTIMEZONE\r
+TZID:China Standard Time\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+SUMMARY:代数几何讨论班\r
+DTSTART;TZID=\"China Standard Time\":20250314T140000\r
+DTEND;TZID=\"China Standard Time\":20250314T160000\r
+LOCATION:理科楼 A304\\, 清华大学\r
+DESCRIPTION:报告人：张三\\n题目：模空间\r
+ 的紧化\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Colloquium\r
+DTSTART:20250315T020000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Open day\r
+DTSTART;VALUE=DATE:20250316\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Elsewhere\r
+DTSTART;TZID=America/New_York:20250314T090000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+  #[test]
+  fn test_parse_ics_events() {
+    let events = parse_ics(INVITE).events;
+
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0].event, "代数几何讨论班");
+    assert_eq!(events[0].time_begin, "2025年03月14日 14时00分");
+    assert_eq!(events[0].time_end, "2025年03月14日 16时00分");
+    assert_eq!(
+      events[0].time_begin_iso.as_deref(),
+      Some("2025-03-14T14:00:00+08:00")
+    );
+    assert_eq!(
+      events[0].time_end_iso.as_deref(),
+      Some("2025-03-14T16:00:00+08:00")
+    );
+    assert_eq!(events[0].position, "理科楼 A304, 清华大学");
+    assert_eq!(events[0].r#abstract, "报告人：张三\n题目：模空间的紧化");
+
+    // UTC is converted to Shanghai time
+    assert_eq!(events[1].time_begin, "2025年03月15日 10时00分");
+    assert_eq!(
+      events[1].time_begin_iso.as_deref(),
+      Some("2025-03-15T10:00:00+08:00")
+    );
+    assert_eq!(events[1].time_end, "");
+    assert_eq!(events[1].time_end_iso, None);
+
+    assert_eq!(events[2].time_begin, "2025年03月16日");
+    assert_eq!(
+      events[2].time_begin_iso.as_deref(),
+      Some("2025-03-16T00:00:00+08:00")
+    );
+
+    // Other IANA zones are converted with their DST rules
+    assert_eq!(events[3].time_begin, "2025年03月14日 21时00分");
+    assert_eq!(
+      events[3].time_begin_iso.as_deref(),
+      Some("2025-03-14T21:00:00+08:00")
+    );
+  }
+
+  #[test]
+  fn test_parse_ics_zones_from_vtimezone() {
+    let ics = "BEGIN:VCALENDAR\r
+BEGIN:VTIMEZONE\r
+TZID:Tokyo Standard Time\r
+BEGIN:STANDARD\r
+DTSTART:16010101T000000\r
+TZOFFSETFROM:+0900\r
+TZOFFSETTO:+0900\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VTIMEZONE\r
+TZID:Eastern Standard Time\r
+BEGIN:STANDARD\r
+TZOFFSETTO:-0500\r
+END:STANDARD\r
+BEGIN:DAYLIGHT\r
+TZOFFSETTO:-0400\r
+END:DAYLIGHT\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+SUMMARY:Workshop\r
+DTSTART;TZID=Tokyo Standard Time:20250314T100000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Seminar\r
+DTSTART;TZID=Eastern Standard Time:20250314T090000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+    let events = parse_ics(ics).events;
+
+    // A zone without DST uses its offset; one with DST rules is not guessed
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event, "Workshop");
+    assert_eq!(
+      events[0].time_begin_iso.as_deref(),
+      Some("2025-03-14T09:00:00+08:00")
+    );
+  }
+
+  #[test]
+  fn test_parse_ics_reports_cancellations() {
+    let cancel = INVITE.replace("METHOD:REQUEST", "METHOD:CANCEL");
+    let invite = parse_ics(&cancel);
+    assert!(invite.events.is_empty());
+    assert_eq!(invite.cancelled, parse_ics(INVITE).events);
+
+    let cancelled =
+      "BEGIN:VEVENT\nSUMMARY:Talk\nSTATUS:CANCELLED\nDTSTART:20250315T020000Z\nEND:VEVENT\n";
+    let invite = parse_ics(cancelled);
+    assert!(invite.events.is_empty());
+    assert_eq!(invite.cancelled[0].event, "Talk");
+  }
+
+  #[test]
+  fn test_cancel_invite_is_not_sent_to_the_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let raw = format!(
+      "From: Math <math@mail.tsinghua.edu.cn>\r
+Subject: Cancelled: Seminar\r
+Content-Type: multipart/mixed; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/calendar; method=CANCEL; charset=utf-8\r
+\r
+{}\r
+--b--\r
+",
+      INVITE.replace("METHOD:REQUEST", "METHOD:CANCEL")
+    );
+    let email = single_eml(dir.path(), &raw);
+
+    assert!(email.calendar_events.is_empty());
+    assert_eq!(email.cancelled_events.len(), 4);
+    assert_eq!(
+      email.cancelled_events[0].sender,
+      "math@mail.tsinghua.edu.cn"
+    );
+    assert_eq!(email.attachment_text, "");
+  }
+
+  #[test]
+  fn test_unnamed_invite_text_is_headed_by_its_type() {
+    let dir = tempfile::tempdir().unwrap();
+    let email = single_eml(
+      dir.path(),
+      "From: a@mail.tsinghua.edu.cn\r
+Subject: Talk\r
+Content-Type: multipart/mixed; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/calendar; charset=utf-8\r
+\r
+BEGIN:VCALENDAR\r
+X-NOTE:no events here\r
+END:VCALENDAR\r
+--b--\r
+",
+    );
+
+    assert!(email.attachment_text.starts_with("[text/calendar]\n"));
+  }
+
+  #[test]
+  fn test_calendar_invite_becomes_events_with_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let raw = format!(
+      "From: Math <math@mail.tsinghua.edu.cn>\r
+Subject: Seminar invite\r
+Date: Fri, 14 Mar 2025 10:00:00 +0800\r
+Message-ID: <invite@test>\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/calendar; method=REQUEST; charset=utf-8\r
+\r
+{}\r
+--b\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+欢迎参加\r
+--b--\r
+",
+      INVITE
+    );
+    std::fs::write(dir.path().join("invite.eml"), raw).unwrap();
+
+    let emails = EmlDirSource {
+      path: dir.path().to_path_buf(),
+      days_ago: None,
+    }
+    .fetch(&default_filter())
+    .unwrap();
+
+    assert_eq!(emails.len(), 1);
+    // The invite is not mistaken for the body
+    assert_eq!(emails[0].body.trim(), "欢迎参加");
+    assert_eq!(emails[0].calendar_events.len(), 4);
+    let event = &emails[0].calendar_events[0];
+    assert_eq!(event.sender, "math@mail.tsinghua.edu.cn");
+    assert_eq!(
+      event.source.as_ref().map(|s| s.message_id.as_str()),
+      Some("<invite@test>")
+    );
+  }
+
+  #[test]
+  fn test_docx_attachment_text() {
+    use std::io::Write;
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    let mut zip = zip::ZipWriter::new(&mut buffer);
+    zip
+      .start_file(
+        "word/document.xml",
+        zip::write::SimpleFileOptions::default(),
+      )
+      .unwrap();
+    zip
+      .write_all(
+        br#"<?xml version="1.0"?><w:document><w:body><w:p><w:r><w:t>Speaker:</w:t></w:r><w:r><w:tab/><w:t>Li &amp; Wang</w:t></w:r></w:p><w:p><w:r><w:t xml:space="preserve">Room 101</w:t></w:r></w:p></w:body></w:document>"#,
+      )
+      .unwrap();
+    zip.finish().unwrap();
+
+    let text = docx_text(buffer.get_ref()).unwrap();
+
+    assert_eq!(text, "Speaker:\tLi & Wang\nRoom 101\n");
+  }
+
+  #[test]
+  fn test_broken_documents_are_errors() {
+    assert!(docx_text(b"not a zip").is_err());
+    assert!(pdf_text(b"%PDF-1.4 truncated").is_err());
+  }
+
+  #[test]
+  fn test_html_to_text() {
+    let html = r#"<html><head><title>Newsletter</title><style>p { color: red; }</style></head>
+<body><!-- tracking --><script>track()</script>
+<h1>数学讲座</h1><p>报告人：张三&nbsp;教授<br>地点：理科楼&amp;A304</p>
+<ul><li>14:00 报告</li><li>15:00 讨论</li></ul>
+<table><tr><td>时间</td><td>3月14日</td></tr></table>
+<img src="https://track.example.com/pixel.gif" width="1" height="1">
+<p>Price &lt;5&gt; &#20320;&#x597D; &unknown;</p></body></html>"#;
+
+    assert_eq!(
+      html_to_text(html),
+      "数学讲座\n报告人：张三 教授\n地点：理科楼&A304\n- 14:00 报告\n- 15:00 讨论\n时间 3月14日\nPrice <5> 你好 &unknown;"
+    );
+  }
+
+  #[test]
+  fn test_clean_body_strips_replies_signatures_and_footers() {
+    let reply = "讲座改到周五\n\n> 原定周四\n> 下午两点\n\n在 2025年3月13日 周四 10:00，张三 写道：\n旧的内容";
+    assert_eq!(clean_body(reply, false), "讲座改到周五");
+
+    let english =
+      "See you there.\nOn Thu, Mar 13, 2025 at 10:00 AM Zhang <z@example.com> wrote:\nold";
+    assert_eq!(clean_body(english, false), "See you there.");
+
+    let outlook =
+      "Updated room.\n\n________________________________\nFrom: Zhang\nSent: Thursday\nold";
+    assert_eq!(clean_body(outlook, false), "Updated room.");
+
+    let signature = "时间：周五 14:00\n-- \n张三\n数学系办公室";
+    assert_eq!(clean_body(signature, false), "时间：周五 14:00");
+
+    let footer =
+      "地点：A304\n\n本邮件及其附件含有保密信息，仅限于发送给上面地址中列出的个人或群组。";
+    assert_eq!(clean_body(footer, false), "地点：A304");
+
+    // Ordinary dashes and underscores are kept
+    let plain = "-- 讲座信息 --\n____\n报告人：李四";
+    assert_eq!(clean_body(plain, false), plain);
+  }
+
+  fn single_eml(dir: &std::path::Path, raw: &str) -> EmailTable {
+    std::fs::write(dir.join("mail.eml"), raw).unwrap();
+    let mut emails = EmlDirSource {
+      path: dir.to_path_buf(),
+      days_ago: None,
+    }
+    .fetch(&default_filter())
+    .unwrap();
+    emails.remove(0)
+  }
+
+  #[test]
+  fn test_body_prefers_plain_text_alternative() {
+    let dir = tempfile::tempdir().unwrap();
+    let email = single_eml(
+      dir.path(),
+      "From: a@mail.tsinghua.edu.cn\r
+Subject: Talk\r
+Content-Type: multipart/alternative; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/html; charset=utf-8\r
+\r
+<p>HTML <b>version</b></p>\r
+--b\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+Plain version\r
+\r
+> quoted\r
+--b--\r
+",
+    );
+
+    assert_eq!(email.body, "Plain version");
+  }
+
+  #[test]
+  fn test_body_converts_html_only_email() {
+    let dir = tempfile::tempdir().unwrap();
+    let email = single_eml(
+      dir.path(),
+      "From: a@mail.tsinghua.edu.cn\r
+Subject: Talk\r
+Content-Type: text/html; charset=utf-8\r
+\r
+<style>.x{}</style><p>HTML <b>only</b></p><p>Room&nbsp;1</p>\r
+",
+    );
+
+    assert_eq!(email.body, "HTML only\nRoom 1");
+  }
+
+  #[test]
+  fn test_body_keeps_forwarded_announcement() {
+    let dir = tempfile::tempdir().unwrap();
+    let email = single_eml(
+      dir.path(),
+      "From: a@mail.tsinghua.edu.cn\r
+Subject: Fwd: Colloquium\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+FYI\r
+\r
+---------- Forwarded message ---------\r
+From: Math Dept <math@example.com>\r
+Subject: Colloquium\r
+\r
+Time: Friday 14:00\r
+Place: A304\r
+",
+    );
+
+    assert_eq!(
+      email.body,
+      "FYI\n\n---------- Forwarded message ---------\nFrom: Math Dept <math@example.com>\nSubject: Colloquium\n\nTime: Friday 14:00\nPlace: A304"
+    );
+  }
+
+  #[test]
+  fn test_clean_body_keeps_outlook_forwards_only() {
+    let text =
+      "FYI\n\n________________________________\nFrom: Zhang\nSubject: Talk\n\n时间：周五 14:00";
+    assert_eq!(
+      clean_body(text, true),
+      "FYI\n\n________________________________\nFrom: Zhang\nSubject: Talk\n\n时间：周五 14:00"
+    );
+    assert_eq!(clean_body(text, false), "FYI");
+
+    assert!(is_forward("Fwd: Talk"));
+    assert!(is_forward("转发：讲座"));
+    assert!(!is_forward("Re: Talk"));
+  }
 }
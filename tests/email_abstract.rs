@@ -2,6 +2,7 @@
 mod tests {
   use ::email_abstract_rs::email::EmailTable;
   use ::email_abstract_rs::email_abstract;
+  use ::email_abstract_rs::event::Event;
 
   #[test]
   fn test_format_emails() {
@@ -10,23 +11,25 @@ mod tests {
         sender: "sender1@example.com".to_string(),
         subject: "Subject 1".to_string(),
         body: "Body 1".to_string(),
+        ..Default::default()
       },
       EmailTable {
         sender: "sender2@example.com".to_string(),
         subject: "Subject \"2\"".to_string(),
         body: "Body\n2".to_string(),
+        ..Default::default()
       },
     ];
 
     let formatted = email_abstract::format_emails(&emails);
     assert!(formatted.starts_with("mails = {"));
-    assert!(formatted.contains("sender: \"sender1@example.com\""));
+    assert!(formatted.contains("{id: \"1\", sender: \"sender1@example.com\""));
     assert!(formatted.contains("subject: \"Subject 1\""));
     assert!(formatted.contains("body: \"Body 1\""));
     assert!(formatted.ends_with("}"));
 
     assert!(formatted.starts_with("mails = {"));
-    assert!(formatted.contains("sender: \"sender2@example.com\""));
+    assert!(formatted.contains("{id: \"2\", sender: \"sender2@example.com\""));
     assert!(formatted.contains("subject: \"Subject '2'\""));
     assert!(formatted.contains("body: \"Body 2\""));
     assert!(formatted.ends_with("}"));
@@ -38,11 +41,182 @@ mod tests {
       sender: "sender1@example.com".to_string(),
       subject: "Subject 1".to_string(),
       body: "Body 1".to_string(),
+      ..Default::default()
     }];
-    let config = email_abstract_rs::config::Config::get();
+    let config = email_abstract_rs::config::Config::get().unwrap();
     let prompt = email_abstract::generate_summary_prompt(&emails);
-    let result =
-      "mails = {{sender: \"sender1@example.com\", subject: \"Subject 1\", body: \"Body 1\"}}";
+    let result = "mails = {{id: \"1\", sender: \"sender1@example.com\", subject: \"Subject 1\", body: \"Body 1\"}}";
     assert_eq!(prompt, config.prompt.replace("{emails_input}", result));
   }
+
+  fn email_with_body(body: &str) -> EmailTable {
+    EmailTable {
+      sender: "sender@example.com".to_string(),
+      subject: "Subject".to_string(),
+      body: body.to_string(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_estimate_tokens() {
+    assert_eq!(email_abstract::estimate_tokens(""), 0);
+    assert_eq!(email_abstract::estimate_tokens("abcd"), 1);
+    assert_eq!(email_abstract::estimate_tokens("abcde"), 2);
+    assert_eq!(email_abstract::estimate_tokens("清华大学"), 4);
+    assert_eq!(email_abstract::estimate_tokens("清华 talk"), 4);
+  }
+
+  #[test]
+  fn test_estimate_email_tokens() {
+    let short = email_with_body("讲座");
+    let long = email_with_body(&"讲座".repeat(100));
+
+    assert!(email_abstract::estimate_email_tokens(&short) > 2);
+    assert_eq!(
+      email_abstract::estimate_email_tokens(&long) - email_abstract::estimate_email_tokens(&short),
+      198
+    );
+  }
+
+  #[test]
+  fn test_batch_emails_respects_budget() {
+    let emails: Vec<EmailTable> = (0..10).map(|_| email_with_body(&"a".repeat(400))).collect();
+    let per_email = email_abstract::estimate_tokens(&email_abstract::format_emails(&emails[..1]));
+
+    let batches = email_abstract::batch_emails(&emails, per_email * 3, 100).unwrap();
+
+    assert_eq!(batches.len(), 4);
+    assert_eq!(batches.iter().map(|b| b.len()).sum::<usize>(), 10);
+    assert!(batches.iter().all(|b| b.len() <= 3));
+  }
+
+  #[test]
+  fn test_batch_emails_respects_max_emails() {
+    let emails: Vec<EmailTable> = (0..5).map(|_| email_with_body("short")).collect();
+
+    let batches = email_abstract::batch_emails(&emails, 100_000, 2).unwrap();
+
+    assert_eq!(
+      batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+      vec![2, 2, 1]
+    );
+  }
+
+  #[test]
+  fn test_batch_emails_truncates_oversized_email() {
+    let emails = vec![
+      email_with_body("small"),
+      email_with_body(&"讲座".repeat(1000)),
+      email_with_body("small"),
+    ];
+
+    let batches = email_abstract::batch_emails(&emails, 200, 10).unwrap();
+
+    assert_eq!(batches.len(), 3);
+    assert_eq!(batches[1].len(), 1);
+    let wrapper = email_abstract::estimate_tokens(&email_abstract::format_emails(&[]));
+    let formatted = email_abstract::format_emails(&batches[1]);
+    assert!(email_abstract::estimate_tokens(&formatted) <= 200 + wrapper);
+    assert!(batches[1][0].body.starts_with("讲座"));
+  }
+
+  #[test]
+  fn test_format_and_truncate_attachment_text() {
+    let email = EmailTable {
+      body: "见附件".to_string(),
+      attachment_text: format!("[poster.pdf]\n{}", "地点".repeat(1000)),
+      ..Default::default()
+    };
+
+    let formatted = email_abstract::format_emails(std::slice::from_ref(&email));
+    assert!(formatted.contains("body: \"见附件\", attachments: \"[poster.pdf] 地点"));
+    // Emails without attachments are formatted as before
+    assert!(!email_abstract::format_emails(&[email_with_body("x")]).contains("attachments"));
+
+    let batches = email_abstract::batch_emails(&[email], 200, 10).unwrap();
+    let wrapper = email_abstract::estimate_tokens(&email_abstract::format_emails(&[]));
+    let formatted = email_abstract::format_emails(&batches[0]);
+    assert!(email_abstract::estimate_tokens(&formatted) <= 200 + wrapper);
+    // The body is kept whole, the attachment text is cut
+    assert_eq!(batches[0][0].body, "见附件");
+    assert!(batches[0][0]
+      .attachment_text
+      .starts_with("[poster.pdf]\n地点"));
+  }
+
+  #[test]
+  fn test_batch_emails_rejects_budget_without_room_for_a_body() {
+    let emails = vec![email_with_body(&"讲座".repeat(100))];
+    let overhead = email_abstract::estimate_email_tokens(&email_with_body(""));
+
+    // Truncating to an empty body would go unnoticed, so it is an error
+    let error = email_abstract::batch_emails(&emails, overhead, 10).unwrap_err();
+    assert!(error.to_string().contains("token_budget"));
+
+    // One token more leaves room for the start of the body
+    let batches = email_abstract::batch_emails(&emails, overhead + 1, 10).unwrap();
+    assert_eq!(batches[0][0].body, "讲");
+  }
+
+  #[test]
+  fn test_batch_emails_empty() {
+    assert!(email_abstract::batch_emails(&[], 1000, 10)
+      .unwrap()
+      .is_empty());
+  }
+
+  #[test]
+  fn test_generate_repair_prompt() {
+    let error = email_abstract_rs::event::ParseError::Schema(vec![
+      "[0].speaker_name: missing required field".to_string(),
+    ]);
+
+    let prompt = email_abstract::generate_repair_prompt("original prompt", "[{}]", &error);
+
+    assert!(prompt.starts_with("original prompt"));
+    assert!(prompt.contains("[{}]"));
+    assert!(prompt.contains("- [0].speaker_name: missing required field"));
+  }
+
+  #[test]
+  fn test_attach_sources() {
+    let batch: Vec<EmailTable> = (1..=2)
+      .map(|i| EmailTable {
+        subject: format!("Subject {}", i),
+        message_id: format!("<{}@example.com>", i),
+        mailbox: "INBOX".to_string(),
+        uid: Some(100 + i),
+        ..Default::default()
+      })
+      .collect();
+    let mut events = vec![
+      Event {
+        source_id: Some("2".to_string()),
+        ..Default::default()
+      },
+      Event {
+        source_id: Some("7".to_string()),
+        ..Default::default()
+      },
+      Event::default(),
+    ];
+
+    email_abstract::attach_sources(&mut events, &batch);
+
+    let source = events[0].source.as_ref().unwrap();
+    assert_eq!(source.message_id, "<2@example.com>");
+    assert_eq!(source.subject, "Subject 2");
+    assert_eq!(source.uid, Some(102));
+    assert_eq!(events[1].source, None);
+    assert_eq!(events[2].source, None);
+
+    // With a single email in the batch the source is unambiguous
+    let mut events = vec![Event::default()];
+    email_abstract::attach_sources(&mut events, &batch[..1]);
+    assert_eq!(
+      events[0].source.as_ref().unwrap().message_id,
+      "<1@example.com>"
+    );
+  }
 }
@@ -0,0 +1,97 @@
+use email_abstract_rs::event::{parse_events, strip_code_fences, validate_events, ParseError};
+use serde_json::json;
+
+const VALID_EVENT: &str = r#"[{
+  "sender": "phys@mail.tsinghua.edu.cn",
+  "event": "拓扑绝缘体前沿进展",
+  "time_begin": "2025年03月14日 14时00分",
+  "time_end": "2025年03月14日 16时00分",
+  "position": "理科楼C302",
+  "speaker_name": "张三",
+  "speaker_title": "教授",
+  "abstract": "报告介绍拓扑绝缘体的最新实验进展。"
+}]"#;
+
+#[test]
+fn test_parse_valid_events() {
+  let events = parse_events(VALID_EVENT).unwrap();
+
+  assert_eq!(events.len(), 1);
+  assert_eq!(events[0].event, "拓扑绝缘体前沿进展");
+  assert_eq!(events[0].r#abstract, "报告介绍拓扑绝缘体的最新实验进展。");
+  assert_eq!(events[0].id, None);
+}
+
+#[test]
+fn test_parse_ignores_database_fields() {
+  let reply = VALID_EVENT.replace(
+    "\"sender\"",
+    r#""id": "1", "source": "x", "time_begin_iso": 3, "source_id": 2, "sender""#,
+  );
+  let events = parse_events(&reply).unwrap();
+
+  assert_eq!(events[0].id, None);
+  assert_eq!(events[0].source, None);
+  assert_eq!(events[0].time_begin_iso, None);
+  assert_eq!(events[0].source_id.as_deref(), Some("2"));
+}
+
+#[test]
+fn test_parse_events_strips_code_fences() {
+  let fenced = format!("```json\n{}\n```", VALID_EVENT);
+  assert_eq!(parse_events(&fenced).unwrap().len(), 1);
+
+  assert_eq!(strip_code_fences("```\n[]\n```"), "[]");
+  assert_eq!(strip_code_fences("  []  "), "[]");
+}
+
+#[test]
+fn test_parse_empty_array() {
+  assert!(parse_events("[]").unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_invalid_json() {
+  assert!(matches!(
+    parse_events("[{\"sender\": "),
+    Err(ParseError::Json(_))
+  ));
+}
+
+#[test]
+fn test_validate_reports_failing_fields() {
+  let value = json!([
+    {
+      "sender": "a@example.com",
+      "event": "",
+      "time_begin": "2025年03月14日 14时00分",
+      "time_end": "2025年03月14日 16时00分",
+      "position": "Room 1",
+      "speaker": "Misnamed Field",
+      "speaker_title": null,
+      "abstract": "text"
+    },
+    "not an object"
+  ]);
+
+  let errors = validate_events(&value);
+
+  assert_eq!(
+    errors,
+    vec![
+      "[0].event: must not be empty",
+      "[0].speaker_name: missing required field",
+      "[0].speaker_title: expected a string, got null",
+      "[1]: expected an object",
+    ]
+  );
+}
+
+#[test]
+fn test_validate_rejects_non_array() {
+  let result = parse_events(r#"{"events": []}"#);
+  match result {
+    Err(ParseError::Schema(errors)) => assert_eq!(errors.len(), 1),
+    other => panic!("expected schema error, got {:?}", other),
+  }
+}
@@ -0,0 +1,174 @@
+use email_abstract_rs::data_sql::{search_events, store_json_to_db, EventQuery, StoreStats};
+use email_abstract_rs::event::{Event, SourceEmail};
+use email_abstract_rs::export::{read_events, write_events, ExportFormat};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+fn sample_events() -> Vec<Event> {
+  vec![
+    Event {
+      id: Some(3),
+      sender: "phys@mails.tsinghua.edu.cn".to_string(),
+      event: "拓扑绝缘体, \"前沿\"进展".to_string(),
+      time_begin: "2025年03月14日 14时00分".to_string(),
+      time_end: "2025年03月14日 16时00分".to_string(),
+      position: "理科楼C302".to_string(),
+      r#abstract: "第一行\n第二行".to_string(),
+      speaker_name: "张三".to_string(),
+      speaker_title: "教授".to_string(),
+      time_begin_iso: Some("2025-03-14T14:00:00+08:00".to_string()),
+      time_end_iso: Some("2025-03-14T16:00:00+08:00".to_string()),
+      source: Some(SourceEmail {
+        message_id: "<a@example.com>".to_string(),
+        subject: "讲座通知".to_string(),
+        date: "2025-03-10T09:30:00+08:00".to_string(),
+        mailbox: "INBOX".to_string(),
+        uid: Some(7),
+      }),
+      ..Default::default()
+    },
+    Event {
+      sender: "math@mails.tsinghua.edu.cn".to_string(),
+      event: "代数几何".to_string(),
+      time_begin: "待定".to_string(),
+      ..Default::default()
+    },
+  ]
+}
+
+/// What an import yields: everything but the row id
+fn without_ids(mut events: Vec<Event>) -> Vec<Event> {
+  for event in &mut events {
+    event.id = None;
+  }
+  events
+}
+
+#[test]
+fn test_export_import_roundtrip() {
+  for format in [ExportFormat::Json, ExportFormat::Jsonl, ExportFormat::Csv] {
+    let written = write_events(&sample_events(), format).unwrap();
+    let read = read_events(&written, format).unwrap();
+    assert_eq!(read, without_ids(sample_events()), "{:?}", format);
+  }
+}
+
+#[test]
+fn test_csv_has_bom_and_header() {
+  let csv = write_events(&sample_events(), ExportFormat::Csv).unwrap();
+  assert!(csv.starts_with("\u{feff}id,sender,event,time_begin,"));
+
+  let jsonl = write_events(&sample_events(), ExportFormat::Jsonl).unwrap();
+  assert_eq!(jsonl.lines().count(), 2);
+  assert!(!jsonl.starts_with('\u{feff}'));
+}
+
+#[test]
+fn test_read_events_errors() {
+  let err = read_events("{\"sender\": \"a\"}\nnot json\n", ExportFormat::Jsonl).unwrap_err();
+  assert!(err.to_string().starts_with("line 1"));
+  assert!(read_events("BEGIN:VCALENDAR", ExportFormat::Ics).is_err());
+
+  // Hand-written CSV only needs the required columns
+  let events = read_events(
+    "sender,event,time_begin\na@example.com,讲座,2025年03月14日\n",
+    ExportFormat::Csv,
+  )
+  .unwrap();
+  assert_eq!(events[0].event, "讲座");
+  assert_eq!(events[0].source, None);
+}
+
+#[test]
+fn test_read_json_without_optional_fields() {
+  let json = r#"[{"sender": "a@example.com", "event": "讲座", "time_begin": "2025年03月14日"}]"#;
+  let jsonl =
+    "{\"sender\": \"a@example.com\", \"event\": \"讲座\", \"time_begin\": \"2025年03月14日\"}\n";
+  let csv = "sender,event,time_begin\na@example.com,讲座,2025年03月14日\n";
+
+  let expected = vec![Event {
+    sender: "a@example.com".to_string(),
+    event: "讲座".to_string(),
+    time_begin: "2025年03月14日".to_string(),
+    ..Default::default()
+  }];
+  // The same rows are accepted whatever the format
+  assert_eq!(read_events(json, ExportFormat::Json).unwrap(), expected);
+  assert_eq!(read_events(jsonl, ExportFormat::Jsonl).unwrap(), expected);
+  assert_eq!(read_events(csv, ExportFormat::Csv).unwrap(), expected);
+}
+
+#[test]
+fn test_format_from_path() {
+  assert_eq!(
+    ExportFormat::from_path(Path::new("out/events.CSV")),
+    Some(ExportFormat::Csv)
+  );
+  assert_eq!(
+    ExportFormat::from_path(Path::new("events.ndjson")),
+    Some(ExportFormat::Jsonl)
+  );
+  assert_eq!(ExportFormat::from_path(Path::new("events")), None);
+}
+
+#[tokio::test]
+async fn test_import_into_database() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let csv = write_events(&sample_events(), ExportFormat::Csv).unwrap();
+  let events = read_events(&csv, ExportFormat::Csv).unwrap();
+  let stats = store_json_to_db(events.clone(), db_path).await.unwrap();
+  assert_eq!(stats.inserted, 2);
+  // Importing the same file again changes nothing
+  assert_eq!(
+    store_json_to_db(events, db_path).await.unwrap(),
+    StoreStats {
+      unchanged: 2,
+      ..Default::default()
+    }
+  );
+
+  let stored = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert_eq!(stored.len(), 2);
+  assert_eq!(stored[0].event, "拓扑绝缘体, \"前沿\"进展");
+  assert_eq!(
+    stored[0].source.as_ref().unwrap().message_id,
+    "<a@example.com>"
+  );
+  assert_eq!(stored[1].time_begin_iso, None);
+}
+
+#[tokio::test]
+async fn test_ics_export_keeps_colliding_legacy_rows_apart() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  // Older versions could store the same event twice
+  let conn = rusqlite::Connection::open(db_path).unwrap();
+  conn
+    .execute_batch(
+      "CREATE TABLE events (
+        id INTEGER PRIMARY KEY, sender TEXT NOT NULL, event TEXT NOT NULL,
+        time_begin TEXT NOT NULL, time_end TEXT NOT NULL, position TEXT NOT NULL,
+        \"abstract\" TEXT NOT NULL, speaker_name TEXT NOT NULL, speaker_title TEXT NOT NULL);
+      INSERT INTO events VALUES (1, 's', '旧标题', '2025-03-14 14:00', '', 'p', 'a', 'n', 't');
+      INSERT INTO events VALUES (2, 's', '新标题', '2025年03月14日 14时00分', '', 'p', 'a', 'n', 't');",
+    )
+    .unwrap();
+  drop(conn);
+
+  let events = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  let ics = write_events(&events, ExportFormat::Ics).unwrap();
+
+  let uids: Vec<&str> = ics
+    .lines()
+    .filter(|line| line.starts_with("UID:"))
+    .collect();
+  assert_eq!(uids.len(), 2);
+  assert_ne!(uids[0], uids[1]);
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use email_abstract_rs::api_req::{Completion, CompletionRequest, LlmProvider, TokenUsage};
+use email_abstract_rs::data_sql::{count_events, search_events, EventQuery};
+use email_abstract_rs::email::EmailTable;
+use email_abstract_rs::extract::{extract_batches_with_progress, ExtractOptions};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// Answers every batch with one event titled after its email's subject, and
+/// fails the batch whose subject is `failing`
+struct FakeProvider {
+  failing: &'static str,
+  in_flight: AtomicUsize,
+  max_in_flight: AtomicUsize,
+}
+
+#[async_trait]
+impl LlmProvider for FakeProvider {
+  fn name(&self) -> &'static str {
+    "fake"
+  }
+
+  fn base_url(&self) -> &str {
+    "http://fake"
+  }
+
+  async fn complete(&self, request: &CompletionRequest) -> Result<Completion, Box<dyn Error>> {
+    let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+    let subject = request
+      .prompt
+      .split("subject: \"")
+      .nth(1)
+      .and_then(|rest| rest.split('"').next())
+      .unwrap();
+    if subject == self.failing {
+      return Err("server error".into());
+    }
+    let day: u32 = subject.trim_start_matches("Talk ").parse().unwrap();
+    let text = serde_json::json!([{
+      "sender": "phys@mail.tsinghua.edu.cn",
+      "event": subject,
+      "time_begin": format!("2025年03月{:02}日 14时00分", day),
+      "time_end": format!("2025年03月{:02}日 16时00分", day),
+      "position": "理科楼C302",
+      "speaker_name": "张三",
+      "speaker_title": "教授",
+      "abstract": "",
+      "source_id": "1"
+    }])
+    .to_string();
+    Ok(Completion {
+      text,
+      usage: TokenUsage {
+        prompt_tokens: 100,
+        completion_tokens: 10,
+      },
+    })
+  }
+}
+
+fn email(day: u32) -> EmailTable {
+  EmailTable {
+    sender: "phys@mail.tsinghua.edu.cn".to_string(),
+    subject: format!("Talk {}", day),
+    body: "body".to_string(),
+    message_id: format!("<talk-{}@example.com>", day),
+    mailbox: "INBOX".to_string(),
+    uid: Some(day),
+    ..Default::default()
+  }
+}
+
+#[tokio::test]
+async fn test_extract_batches_stores_every_batch_despite_a_failure() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+
+  let provider = FakeProvider {
+    failing: "Talk 3",
+    in_flight: AtomicUsize::new(0),
+    max_in_flight: AtomicUsize::new(0),
+  };
+  let batches: Vec<Vec<EmailTable>> = (1..=6).map(|day| vec![email(day)]).collect();
+  let opts = ExtractOptions {
+    model: "fake-model".to_string(),
+    max_tokens: 4096,
+    temperature: 0.0,
+    repair_attempts: 0,
+    concurrency: 2,
+    per_email: true,
+    path_to_db: Some(db_path.to_string()),
+  };
+  let m = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+  let pb = ProgressBar::hidden();
+  pb.set_length(batches.len() as u64);
+
+  let extraction = extract_batches_with_progress(&m, &pb, &provider, &batches, &opts).await;
+
+  // The failed batch is reported, the others are all stored
+  assert_eq!(extraction.failed, 1);
+  assert_eq!(extraction.failed_uids.len(), 1);
+  assert_eq!(extraction.failed_uids[0].0, 3);
+  assert_eq!(extraction.events, 5);
+  assert_eq!(extraction.stored.inserted, 5);
+  assert_eq!(
+    count_events(&EventQuery::default(), db_path).await.unwrap(),
+    5
+  );
+  let stored = search_events(&EventQuery::default(), db_path)
+    .await
+    .unwrap();
+  assert!(stored.iter().all(|event| event.event != "Talk 3"));
+
+  // Every batch counts towards the progress, the failed one included
+  assert_eq!(pb.position(), 6);
+  assert!(pb.is_finished());
+
+  // Requests overlap, but never more than `concurrency` of them
+  assert_eq!(provider.max_in_flight.load(Ordering::SeqCst), 2);
+  // Only answered requests are billed
+  assert_eq!(extraction.usage.prompt_tokens, 500);
+}
+
+#[tokio::test]
+async fn test_extract_batches_without_database_keeps_events() {
+  let provider = FakeProvider {
+    failing: "",
+    in_flight: AtomicUsize::new(0),
+    max_in_flight: AtomicUsize::new(0),
+  };
+  let batches = vec![vec![email(1)], vec![email(2)]];
+  let opts = ExtractOptions {
+    model: "fake-model".to_string(),
+    max_tokens: 4096,
+    temperature: 0.0,
+    repair_attempts: 0,
+    concurrency: 4,
+    per_email: false,
+    path_to_db: None,
+  };
+  let m = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+  let pb = ProgressBar::hidden();
+  pb.set_length(batches.len() as u64);
+
+  let extraction = extract_batches_with_progress(&m, &pb, &provider, &batches, &opts).await;
+
+  assert_eq!(extraction.failed, 0);
+  assert_eq!(extraction.unstored.len(), 2);
+  // Each event is traced back to the email it came from
+  let uids: Vec<_> = extraction
+    .unstored
+    .iter()
+    .map(|event| event.source.as_ref().unwrap().uid)
+    .collect();
+  assert!(uids.contains(&Some(1)) && uids.contains(&Some(2)));
+  assert_eq!(pb.position(), 2);
+}
@@ -0,0 +1,21 @@
+-- Schema written by the first release, before `schema_version` existed, with sample rows
+CREATE TABLE IF NOT EXISTS events (
+          id INTEGER PRIMARY KEY,
+          sender TEXT NOT NULL,
+          event TEXT NOT NULL,
+          time_begin TEXT NOT NULL,
+          time_end TEXT NOT NULL,
+          position TEXT NOT NULL,
+          "abstract" TEXT NOT NULL,
+          speaker_name TEXT NOT NULL,
+          speaker_title TEXT NOT NULL
+      );
+
+INSERT INTO events (sender, event, time_begin, time_end, position, "abstract", speaker_name, speaker_title)
+  VALUES ('phys@mails.tsinghua.edu.cn', '拓扑绝缘体', '2025年03月14日 14时00分', '2025年03月14日 16时00分', '理科楼C302', '表面态输运', '张三', '教授');
+INSERT INTO events (sender, event, time_begin, time_end, position, "abstract", speaker_name, speaker_title)
+  VALUES ('math@mails.tsinghua.edu.cn', '代数几何', '待定', '', '近春园', '模空间', '李四', '研究员');
+INSERT INTO events (sender, event, time_begin, time_end, position, "abstract", speaker_name, speaker_title)
+  VALUES ('math@mails.tsinghua.edu.cn', '数论讨论班', '2025年03月20日', '', '近春园', '', '王五', '');
+INSERT INTO events (sender, event, time_begin, time_end, position, "abstract", speaker_name, speaker_title)
+  VALUES ('math@mails.tsinghua.edu.cn', '几何讨论班', '2025年03月20日', '', '近春园', '', '赵六', '');
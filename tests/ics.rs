@@ -0,0 +1,102 @@
+use email_abstract_rs::event::{Event, SourceEmail};
+use email_abstract_rs::ics::{event_uid, events_to_ics};
+
+fn sample_event() -> Event {
+  Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: "拓扑绝缘体前沿进展".to_string(),
+    time_begin: "2025年03月14日 14时00分".to_string(),
+    time_end: "2025年03月14日 16时00分".to_string(),
+    position: "理科楼C302, 3层".to_string(),
+    r#abstract: "介绍拓扑绝缘体; 以及输运实验".to_string(),
+    speaker_name: "张三".to_string(),
+    speaker_title: "教授".to_string(),
+    time_begin_iso: Some("2025-03-14T14:00:00+08:00".to_string()),
+    time_end_iso: Some("2025-03-14T16:00:00+08:00".to_string()),
+    source: Some(SourceEmail {
+      message_id: "<a@example.com>".to_string(),
+      subject: "讲座通知".to_string(),
+      date: "2025-03-10T09:30:00+08:00".to_string(),
+      mailbox: "INBOX".to_string(),
+      uid: Some(7),
+    }),
+    ..Default::default()
+  }
+}
+
+/// Undo line folding so assertions can look at whole content lines
+fn unfold(ics: &str) -> String {
+  ics.replace("\r\n ", "")
+}
+
+#[test]
+fn test_events_to_ics_vevent_fields() {
+  let ics = events_to_ics(&[sample_event()]);
+  assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+  assert!(ics.ends_with("END:VCALENDAR\r\n"));
+  assert!(!ics.replace("\r\n", "").contains('\n'));
+
+  let unfolded = unfold(&ics);
+  let lines: Vec<&str> = unfolded.split("\r\n").collect();
+  assert!(lines.contains(&"DTSTART:20250314T060000Z"));
+  assert!(lines.contains(&"DTEND:20250314T080000Z"));
+  assert!(lines.contains(&"DTSTAMP:20250310T013000Z"));
+  assert!(lines.contains(&"SUMMARY:拓扑绝缘体前沿进展"));
+  assert!(lines.contains(&r"LOCATION:理科楼C302\, 3层"));
+  assert!(lines.contains(&r"DESCRIPTION:介绍拓扑绝缘体\; 以及输运实验\n\n报告人：张三（教授）"));
+  assert!(lines.contains(&format!("UID:{}", event_uid(&sample_event())).as_str()));
+  assert!(lines.contains(&"X-WR-CALNAME:Email Events"));
+  // Only rows read back from the database know when they last changed
+  assert!(!ics.contains("LAST-MODIFIED"));
+}
+
+#[test]
+fn test_events_to_ics_last_modified() {
+  let event = Event {
+    updated_at: Some("2025-03-12T04:05:06Z".to_string()),
+    ..sample_event()
+  };
+  let ics = events_to_ics(&[event]);
+
+  assert!(ics.contains("\r\nLAST-MODIFIED:20250312T040506Z\r\n"));
+}
+
+#[test]
+fn test_events_to_ics_folds_long_lines() {
+  let mut event = sample_event();
+  event.r#abstract = "长".repeat(100);
+  let ics = events_to_ics(&[event]);
+
+  for line in ics.split("\r\n") {
+    assert!(line.len() <= 75, "line too long: {}", line);
+  }
+  assert!(unfold(&ics).contains(&"长".repeat(100)));
+}
+
+#[test]
+fn test_events_to_ics_end_fallback_and_untimed() {
+  let mut no_end = sample_event();
+  no_end.time_end_iso = None;
+  let mut untimed = sample_event();
+  untimed.event = "时间待定".to_string();
+  untimed.time_begin_iso = None;
+
+  let ics = unfold(&events_to_ics(&[no_end, untimed]));
+  assert!(ics.contains("DTEND:20250314T070000Z"));
+  assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+  assert!(!ics.contains("时间待定"));
+}
+
+#[test]
+fn test_event_uid_is_stable() {
+  let event = sample_event();
+  let mut reextracted = sample_event();
+  reextracted.id = Some(42);
+  reextracted.r#abstract = "摘要改写".to_string();
+  reextracted.event = " 拓扑绝缘体前沿进展 ".to_string();
+  assert_eq!(event_uid(&event), event_uid(&reextracted));
+
+  let mut moved = sample_event();
+  moved.time_begin_iso = Some("2025-03-15T14:00:00+08:00".to_string());
+  assert_ne!(event_uid(&event), event_uid(&moved));
+}
@@ -0,0 +1,144 @@
+use email_abstract_rs::event::Event;
+use email_abstract_rs::insert_html::{generate_events_html, render_events_html};
+use std::fs;
+use tempfile::tempdir;
+
+fn sample_event() -> Event {
+  Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: "拓扑绝缘体前沿进展".to_string(),
+    time_begin: "2025年03月14日 14时00分".to_string(),
+    time_end: "2025年03月14日 16时00分".to_string(),
+    position: "理科楼C302".to_string(),
+    r#abstract: "报告介绍拓扑绝缘体的最新实验进展。".to_string(),
+    speaker_name: "张三".to_string(),
+    speaker_title: "教授".to_string(),
+    time_begin_iso: Some("2025-03-14T14:00:00+08:00".to_string()),
+    time_end_iso: Some("2025-03-14T16:00:00+08:00".to_string()),
+    ..Default::default()
+  }
+}
+
+#[test]
+fn test_render_loops_over_events() {
+  let template =
+    "{% for event in events %}[{{ event.event }}|{{ event | event_time }}]{% endfor %}";
+  let second = Event {
+    event: "代数几何".to_string(),
+    time_begin: "下周五".to_string(),
+    ..Default::default()
+  };
+
+  let html = render_events_html(&[sample_event(), second], template).unwrap();
+
+  assert_eq!(
+    html,
+    "[拓扑绝缘体前沿进展|2025年03月14日 14:00-16:00][代数几何|下周五]"
+  );
+}
+
+#[test]
+fn test_render_datetime_filter() {
+  let template = "{{ events[0].time_begin_iso | datetime(\"%m/%d %H:%M\") }}";
+  let html = render_events_html(&[sample_event()], template).unwrap();
+  assert_eq!(html, "03/14 14:00");
+
+  let broken = Event {
+    time_begin_iso: Some("not a time".to_string()),
+    ..sample_event()
+  };
+  assert!(render_events_html(&[broken], template).is_err());
+}
+
+#[test]
+fn test_full_template_renders_abstract_and_skips_empty_fields() {
+  let template = fs::read_to_string("template/wanyou_full.html").unwrap();
+  let no_speaker = Event {
+    event: String::new(),
+    speaker_name: String::new(),
+    speaker_title: String::new(),
+    position: String::new(),
+    r#abstract: String::new(),
+    ..sample_event()
+  };
+
+  let html = render_events_html(&[sample_event(), no_speaker], &template).unwrap();
+
+  assert_eq!(html.matches("<!-- Event -->").count(), 2);
+  assert!(html.contains("拓扑绝缘体前沿进展"));
+  assert!(html.contains("报告人：张三 教授"));
+  assert!(html.contains("地点: 理科楼C302"));
+  assert!(html.contains("摘要：报告介绍拓扑绝缘体的最新实验进展。"));
+  assert!(html.contains("未知事件"));
+  assert_eq!(html.matches("报告人：").count(), 1);
+  assert_eq!(html.matches("摘要：").count(), 1);
+  assert!(!html.contains("{{"));
+}
+
+#[tokio::test]
+async fn test_generate_events_html_with_mini_template() {
+  let dir = tempdir().unwrap();
+  let output = dir.path().join("out/2025-03-14.html");
+
+  generate_events_html(
+    &[sample_event()],
+    "template/wanyou_mini.html",
+    output.to_str().unwrap(),
+  )
+  .await
+  .unwrap();
+
+  let html = fs::read_to_string(output).unwrap();
+  assert!(html.contains("拓扑绝缘体前沿进展"));
+  assert!(html.contains("2025年03月14日 14:00-16:00"));
+  // The mini template leaves out the abstract
+  assert!(!html.contains("摘要："));
+}
+
+fn hostile_event() -> Event {
+  Event {
+    event: "<script>alert('x')</script>".to_string(),
+    speaker_name: "\"><img src=x onerror=alert(1)>".to_string(),
+    speaker_title: "R&D".to_string(),
+    position: "</p><iframe src=//evil.example>".to_string(),
+    r#abstract: "<b>bold</b>".to_string(),
+    time_begin: "<i>soon</i>".to_string(),
+    ..Default::default()
+  }
+}
+
+#[test]
+fn test_render_escapes_hostile_fields() {
+  let template = fs::read_to_string("template/wanyou_full.html").unwrap();
+
+  let html = render_events_html(&[hostile_event()], &template).unwrap();
+
+  assert!(!html.contains("<script>"));
+  assert!(!html.contains("<img src=x"));
+  assert!(!html.contains("<iframe src"));
+  assert!(!html.contains("<b>bold"));
+  assert!(!html.contains("<i>soon"));
+  assert!(html.contains("&lt;script&gt;alert(&#x27;x&#x27;)&lt;&#x2f;script&gt;"));
+  assert!(html.contains("&quot;&gt;&lt;img src=x onerror=alert(1)&gt;"));
+  assert!(html.contains("R&amp;D"));
+  assert!(html.contains("&lt;i&gt;soon&lt;&#x2f;i&gt;"));
+}
+
+#[test]
+fn test_render_escapes_regardless_of_template_name() {
+  // Escaping must not depend on a `.html` name, the template is given as a string
+  let html = render_events_html(&[hostile_event()], "{{ events[0].event }}").unwrap();
+  assert_eq!(
+    html,
+    "&lt;script&gt;alert(&#x27;x&#x27;)&lt;&#x2f;script&gt;"
+  );
+}
+
+#[test]
+fn test_render_safe_filter_opts_in_to_markup() {
+  let template = "{{ events[0].abstract | safe }}|{{ events[0].abstract }}";
+
+  let html = render_events_html(&[hostile_event()], template).unwrap();
+
+  assert_eq!(html, "<b>bold</b>|&lt;b&gt;bold&lt;&#x2f;b&gt;");
+}
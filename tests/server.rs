@@ -0,0 +1,212 @@
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use email_abstract_rs::data_sql::store_json_to_db;
+use email_abstract_rs::event::Event;
+use email_abstract_rs::server::router;
+use serde_json::Value;
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+fn event(title: &str, begin: &str) -> Event {
+  Event {
+    sender: "phys@mails.tsinghua.edu.cn".to_string(),
+    event: title.to_string(),
+    time_begin: begin.to_string(),
+    position: "理科楼C302".to_string(),
+    r#abstract: format!("{} 的摘要", title),
+    speaker_name: "张三".to_string(),
+    ..Default::default()
+  }
+}
+
+/// Three past events and one far in the future
+async fn seed_db(db_path: &str) {
+  store_json_to_db(
+    vec![
+      event("拓扑绝缘体", "2025年03月14日 14时00分"),
+      event("代数几何", "2025年03月20日 10时00分"),
+      event("超导材料", "2025年04月02日 15时30分"),
+      event("引力波探测", "2999年01月01日 09时00分"),
+    ],
+    db_path,
+  )
+  .await
+  .unwrap();
+}
+
+async fn get(db_path: &str, uri: &str) -> (StatusCode, Value) {
+  let response = router(db_path)
+    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+    .await
+    .unwrap();
+  let status = response.status();
+  let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+  (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn titles(events: &Value) -> Vec<&str> {
+  events
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|e| e["event"].as_str().unwrap())
+    .collect()
+}
+
+#[tokio::test]
+async fn test_list_events_by_date_range_with_pages() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  let (status, body) = get(
+    db_path,
+    "/events?from=2025-03-01&to=2025-04-30&per_page=2&page=2",
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["total"], 3);
+  assert_eq!(body["page"], 2);
+  assert_eq!(body["per_page"], 2);
+  assert_eq!(titles(&body["items"]), vec!["超导材料"]);
+
+  let (status, body) = get(db_path, "/events?from=yesterday").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert!(body["error"].as_str().unwrap().contains("from"));
+
+  let (status, _) = get(db_path, "/events?per_page=1000").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_huge_page_is_rejected() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  for path in [
+    format!("/events?page={}&per_page=100", u64::MAX),
+    format!(
+      "/search?q=%E4%BB%A3%E6%95%B0&page={}&per_page=100",
+      u64::MAX
+    ),
+    // Fits in usize but not in SQLite's signed offset
+    format!("/events?page={}&per_page=1", u64::MAX),
+  ] {
+    let (status, body) = get(db_path, &path).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+    assert!(body["error"].as_str().unwrap().contains("page"));
+  }
+
+  // The last page that still fits is merely empty
+  let (status, body) = get(db_path, &format!("/events?page={}&per_page=1", i64::MAX)).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(titles(&body["items"]), Vec::<&str>::new());
+}
+
+#[tokio::test]
+async fn test_get_event_by_id() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  let (_, body) = get(db_path, "/events?keyword=%E4%BB%A3%E6%95%B0").await;
+  let id = body["items"][0]["id"].as_i64().unwrap();
+
+  let (status, body) = get(db_path, &format!("/events/{}", id)).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["event"], "代数几何");
+  assert_eq!(body["time_begin_iso"], "2025-03-20T10:00:00+08:00");
+
+  let (status, body) = get(db_path, "/events/9999").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(body["error"].is_string());
+}
+
+#[tokio::test]
+async fn test_search_and_upcoming() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  let (status, body) = get(db_path, "/search?q=%E6%8B%93%E6%89%91%E7%BB%9D").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["total"], 1);
+  assert_eq!(body["items"][0]["event"]["event"], "拓扑绝缘体");
+
+  let (status, _) = get(db_path, "/search?q=").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let (status, body) = get(db_path, "/events/upcoming").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(titles(&body), vec!["引力波探测"]);
+}
+
+#[tokio::test]
+async fn test_etag_not_modified() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  let response = router(db_path)
+    .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+    .await
+    .unwrap();
+  let etag = response.headers()[header::ETAG].clone();
+
+  let response = router(db_path)
+    .oneshot(
+      Request::get("/events")
+        .header(header::IF_NONE_MATCH, etag.clone())
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  assert_eq!(response.headers()[header::ETAG], etag);
+  let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+  assert!(body.is_empty());
+
+  // New data changes the tag
+  store_json_to_db(vec![event("新讲座", "2025年05月01日 10时00分")], db_path)
+    .await
+    .unwrap();
+  let response = router(db_path)
+    .oneshot(
+      Request::get("/events")
+        .header(header::IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_calendar_feed() {
+  let db_file = NamedTempFile::new().unwrap();
+  let db_path = db_file.path().to_str().unwrap();
+  seed_db(db_path).await;
+
+  let response = router(db_path)
+    .oneshot(
+      Request::get("/events.ics?from=2025-03-15")
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(
+    response.headers()[header::CONTENT_TYPE],
+    "text/calendar; charset=utf-8"
+  );
+  assert!(response.headers().contains_key(header::ETAG));
+  let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+  let ics = String::from_utf8(body.to_vec()).unwrap();
+  assert!(ics.starts_with("BEGIN:VCALENDAR"));
+  assert_eq!(ics.matches("BEGIN:VEVENT").count(), 3);
+  assert!(!ics.contains("拓扑绝缘体"));
+}